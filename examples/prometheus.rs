@@ -0,0 +1,30 @@
+//Serves the controller metrics on http://0.0.0.0:9000/metrics while polling a motor's position.
+//Run with `cargo run --example prometheus --features metrics`.
+use control_components::controllers::clear_core::{Controller, MotorBuilder};
+use metrics_exporter_prometheus::PrometheusBuilder;
+use std::time::Duration;
+
+#[tokio::main]
+async fn main() {
+    PrometheusBuilder::new()
+        .with_http_listener(([0, 0, 0, 0], 9000))
+        .install()
+        .expect("Failed to install Prometheus exporter");
+
+    let motors = [MotorBuilder {
+        id: 0,
+        scale: 800,
+        ..Default::default()
+    }];
+    let (controller, client) = Controller::with_client("192.168.1.11:8888", motors.as_slice());
+    tokio::spawn(client);
+
+    let motor = controller.get_motor(0);
+    loop {
+        //Every successful read updates the clear_core_motor_position gauge
+        if let Err(e) = motor.get_position().await {
+            eprintln!("Failed to read position: {e}");
+        }
+        tokio::time::sleep(Duration::from_millis(500)).await;
+    }
+}
@@ -0,0 +1,40 @@
+//Runs a short dispense recipe against the mock ClearCore and prints every frame it sent.
+//Run with `cargo run --example sequence --features test-utils`.
+use control_components::controllers::clear_core::{Controller, MotorBuilder};
+use control_components::controllers::sequence::Sequence;
+use control_components::testing::MockClearCore;
+use std::time::Duration;
+
+#[tokio::main]
+async fn main() {
+    let mock = MockClearCore::start()
+        .await
+        .expect("Failed to start mock ClearCore");
+    //Motor 0 always reports ready and at its target
+    mock.on(b"M0GS", b"3233");
+
+    let motors = [MotorBuilder {
+        id: 0,
+        scale: 800,
+        ..Default::default()
+    }];
+    let (controller, client) = Controller::with_client(mock.addr(), motors.as_slice());
+    tokio::spawn(client);
+
+    let recipe = Sequence::new()
+        .enable(0)
+        .move_to(0, 12.5)
+        .wait_complete(0)
+        .set_output(2, true)
+        .dwell(Duration::from_millis(250))
+        .set_output(2, false)
+        .move_to(0, 0.0)
+        .wait_complete(0);
+    match recipe.run(&controller).await {
+        Ok(()) => println!("Recipe finished"),
+        Err(e) => eprintln!("Recipe failed: {e}"),
+    }
+    for frame in mock.received() {
+        println!("{}", String::from_utf8_lossy(&frame[1..frame.len() - 1]));
+    }
+}
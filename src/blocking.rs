@@ -0,0 +1,216 @@
+use crate::components::clear_core_motor::{ClearCoreMotor, MotorStatus};
+use crate::controllers::clear_core::{ComponentId, Controller, EmergencyStopResult, MotorBuilder};
+use crate::error::{MultiResult, Result};
+use std::future::Future;
+use std::path::Path;
+use tokio::net::ToSocketAddrs;
+use tokio::runtime::{self, Runtime};
+use tokio::task::JoinHandle;
+
+//Synchronous access to a ClearCore for callers without an async runtime, e.g. a CLI, behind the
+//blocking feature. It owns a runtime with a single worker, the client runs on it in the background
+//so the link stays up between calls, and every method blocks the calling thread until the command
+//is done. Only for plain threads: calling into it or dropping it from inside an async context
+//panics, as tokio doesn't allow a runtime to be blocked on or shut down from within another. Async
+//code should use the Controller directly.
+pub struct BlockingController {
+    controller: Controller,
+    client: JoinHandle<Result<()>>,
+    runtime: Runtime,
+}
+
+impl BlockingController {
+    pub fn connect<T: ToSocketAddrs + Send + 'static>(
+        addr: T,
+        motors: &[MotorBuilder],
+    ) -> Result<Self> {
+        let runtime = Self::runtime()?;
+        let (controller, client) = Controller::with_client(addr, motors);
+        let client = runtime.spawn(client);
+        Ok(Self {
+            controller,
+            client,
+            runtime,
+        })
+    }
+
+    pub fn from_config_file(path: &Path) -> Result<Self> {
+        let runtime = Self::runtime()?;
+        let (controller, client) = Controller::from_config_file(path)?;
+        let client = runtime.spawn(client);
+        Ok(Self {
+            controller,
+            client,
+            runtime,
+        })
+    }
+
+    fn runtime() -> Result<Runtime> {
+        Ok(runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .enable_all()
+            .build()?)
+    }
+
+    //The controller underneath, for anything the wrappers don't cover, run it with block_on
+    pub fn controller(&self) -> &Controller {
+        &self.controller
+    }
+
+    pub fn block_on<F: Future>(&self, future: F) -> F::Output {
+        self.runtime.block_on(future)
+    }
+
+    pub fn motor(&self, id: impl ComponentId) -> BlockingMotor<'_> {
+        BlockingMotor {
+            motor: self.controller.get_motor(id),
+            runtime: &self.runtime,
+        }
+    }
+
+    pub fn enable_all_motors(&self) -> MultiResult<()> {
+        self.block_on(self.controller.enable_all_motors())
+    }
+
+    pub fn disable_all_motors(&self) -> MultiResult<()> {
+        self.block_on(self.controller.disable_all_motors())
+    }
+
+    pub fn stop_all_motors(&self) -> MultiResult<()> {
+        self.block_on(self.controller.stop_all_motors())
+    }
+
+    pub fn emergency_stop(&self) -> Result<EmergencyStopResult> {
+        self.block_on(self.controller.emergency_stop())
+    }
+
+    pub fn get_digital_input(&self, id: impl ComponentId) -> Result<bool> {
+        self.block_on(self.controller.get_digital_input(id).get_state())
+    }
+
+    pub fn get_analog_input(&self, id: impl ComponentId) -> Result<isize> {
+        self.block_on(self.controller.get_analog_input(id).get_state())
+    }
+
+    pub fn set_output(&self, id: impl ComponentId, state: bool) -> Result<()> {
+        self.block_on(self.controller.get_output(id).send_state(state))
+    }
+
+    pub fn send_raw(&self, payload: &[u8]) -> Result<Vec<u8>> {
+        self.block_on(self.controller.send_raw(payload))
+    }
+
+    //Lets the client finish what's queued and close the link, returning how it ended. Dropping
+    //the BlockingController instead shuts its runtime down with the client wherever it is.
+    pub fn close(self) -> Result<()> {
+        let Self {
+            controller,
+            client,
+            runtime,
+        } = self;
+        drop(controller);
+        match runtime.block_on(client) {
+            Ok(result) => result,
+            Err(e) => std::panic::resume_unwind(e.into_panic()),
+        }
+    }
+}
+
+//A motor of a BlockingController, borrowed from it so the runtime outlives every call
+pub struct BlockingMotor<'a> {
+    motor: ClearCoreMotor,
+    runtime: &'a Runtime,
+}
+
+impl BlockingMotor<'_> {
+    //The async handle, e.g. for a call the wrappers don't cover
+    pub fn motor(&self) -> &ClearCoreMotor {
+        &self.motor
+    }
+
+    pub fn enable(&self) -> Result<()> {
+        self.runtime.block_on(self.motor.enable()).map(|_| ())
+    }
+
+    pub fn disable(&self) -> Result<()> {
+        self.runtime.block_on(self.motor.disable())
+    }
+
+    pub fn move_absolute(&self, position: f64) -> Result<()> {
+        self.runtime.block_on(self.motor.move_absolute(position))
+    }
+
+    pub fn move_relative(&self, delta: f64) -> Result<()> {
+        self.runtime.block_on(self.motor.move_relative(delta))
+    }
+
+    pub fn move_velocity(&self, velocity: f64) -> Result<()> {
+        self.runtime.block_on(self.motor.move_velocity(velocity))
+    }
+
+    pub fn wait_for_move_complete(&self) -> Result<()> {
+        self.runtime.block_on(self.motor.wait_for_move_complete())
+    }
+
+    pub fn stop(&self) -> Result<()> {
+        self.runtime.block_on(self.motor.stop())
+    }
+
+    pub fn abrupt_stop(&self) -> Result<()> {
+        self.runtime.block_on(self.motor.abrupt_stop())
+    }
+
+    pub fn home(&self) -> Result<()> {
+        self.runtime.block_on(self.motor.home())
+    }
+
+    pub fn clear_fault(&self) -> Result<()> {
+        self.runtime.block_on(self.motor.clear_fault())
+    }
+
+    pub fn get_status(&self) -> Result<MotorStatus> {
+        self.runtime.block_on(self.motor.get_status())
+    }
+
+    pub fn get_position(&self) -> Result<f64> {
+        self.runtime.block_on(self.motor.get_position())
+    }
+}
+
+#[test]
+fn test_blocking_controller() {
+    use crate::testing::MockClearCore;
+
+    //The mock gets a runtime of its own, the controller's is private to it
+    let mock_runtime = Runtime::new().unwrap();
+    let mock = mock_runtime.block_on(MockClearCore::start()).unwrap();
+    mock.on(b"M0GS", b"3233")
+        .on(b"M0GP", b"1600")
+        .on(b"I1", b"1");
+    let motors = [MotorBuilder {
+        id: 0,
+        scale: 800,
+        ..Default::default()
+    }];
+    let controller = BlockingController::connect(mock.addr(), motors.as_slice()).unwrap();
+
+    let motor = controller.motor(0);
+    motor.enable().unwrap();
+    motor.move_absolute(2.).unwrap();
+    motor.wait_for_move_complete().unwrap();
+    assert_eq!(motor.get_position().unwrap(), 2.);
+    assert!(controller.get_digital_input(1).unwrap());
+    controller.set_output(0, true).unwrap();
+    //The client only ends once every handle is gone
+    drop(motor);
+    controller.close().unwrap();
+
+    let commands: Vec<Vec<u8>> = mock
+        .received()
+        .into_iter()
+        .filter(|frame| !frame.starts_with(b"\x02M0G"))
+        .collect();
+    assert!(commands.contains(&b"\x02M0EN\r".to_vec()));
+    assert!(commands.contains(&b"\x02M0AM1600\r".to_vec()));
+    assert!(commands.contains(&b"\x02I1\r".to_vec()));
+}
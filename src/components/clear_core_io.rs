@@ -1,24 +1,149 @@
+use crate::components::response::ResponseParser;
 use crate::components::send_recv::SendRecv;
-use crate::controllers::clear_core::{Message, CR, STX};
+use crate::controllers::clear_core::{check_result, Message, CR, STX};
+use crate::error::{ControlError, Result};
+use crate::util::poll::PollConfig;
 use crate::util::utils::{ascii_to_int, int_to_byte, num_to_bytes};
+use futures::stream::{self, Stream};
+use log::{error, warn};
+use std::future::Future;
+use std::time::Duration;
 use tokio::sync::mpsc::Sender;
+use tokio::time::{Instant, Interval, MissedTickBehavior};
 
 pub const CLEAR_CORE_H_BRIDGE_MAX: i16 = 32760;
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_millis(10);
+//The ClearCore's PwmDuty takes a full byte, 255 being always on
+const CLEAR_CORE_PWM_MAX: u16 = 255;
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Edge {
+    Rising,
+    Falling,
+}
+
 #[derive(Clone)]
 pub struct DigitalInput {
     cmd: [u8; 4],
+    poll: PollConfig,
     drive_sender: Sender<Message>,
 }
 
 impl DigitalInput {
     pub fn new(id: u8, drive_sender: Sender<Message>) -> Self {
         let cmd = [STX, b'I', int_to_byte(id), CR];
-        Self { cmd, drive_sender }
+        Self {
+            cmd,
+            poll: PollConfig::fixed(DEFAULT_POLL_INTERVAL),
+            drive_sender,
+        }
+    }
+
+    //How often the wait_for_* calls and count_pulses sample the input
+    pub fn with_poll_interval(self, poll_interval: Duration) -> Self {
+        self.with_poll_config(PollConfig::fixed(poll_interval))
+    }
+
+    //How the wait_for_* calls pace their reads. count_pulses needs a steady sample rate so it
+    //always samples at the base interval.
+    pub fn with_poll_config(mut self, poll: PollConfig) -> Self {
+        self.poll = poll;
+        self
+    }
+
+    pub async fn get_state(&self) -> Result<bool> {
+        let res = self.try_write_idempotent(self.cmd.to_vec(), None).await?;
+        let payload = ResponseParser::DEVICE.payload(&res)?;
+        Ok(ascii_to_int(payload) == 1)
+    }
+
+    //get_state that gives up with Busy instead of waiting when the client's queue is full
+    pub async fn try_get_state(&self) -> Result<bool> {
+        let res = self.try_write_now(self.cmd.to_vec(), None).await?;
+        let payload = ResponseParser::DEVICE.payload(&res)?;
+        Ok(ascii_to_int(payload) == 1)
+    }
+
+    //Keeps sampling every `poll` until the input has read the same for `stable_for`, so switch
+    //bounce never makes it out of here
+    pub async fn get_debounced(&self, stable_for: Duration, poll: Duration) -> Result<bool> {
+        let mut tick_interval = tokio::time::interval(poll);
+        tick_interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        tick_interval.tick().await;
+        let mut state = self.get_state().await?;
+        let mut stable_since = Instant::now();
+        while stable_since.elapsed() < stable_for {
+            tick_interval.tick().await;
+            let current = self.get_state().await?;
+            if current != state {
+                state = current;
+                stable_since = Instant::now();
+            }
+        }
+        Ok(state)
+    }
+
+    async fn wait_for_state(&self, target: bool) -> Result<()> {
+        let mut poller = self.poll.poller();
+        loop {
+            poller.tick().await;
+            if self.get_state().await? == target {
+                return Ok(());
+            }
+        }
     }
 
-    pub async fn get_state(&self) -> bool {
-        let res = self.write(self.cmd.as_slice()).await;
-        ascii_to_int(&res[3..]) == 1
+    pub async fn wait_for_high(&self, timeout: Option<Duration>) -> Result<()> {
+        within(timeout, self.wait_for_state(true)).await
+    }
+
+    pub async fn wait_for_low(&self, timeout: Option<Duration>) -> Result<()> {
+        within(timeout, self.wait_for_state(false)).await
+    }
+
+    //An input that is already at the target level has to leave it first, so a sensor that was
+    //tripped before the call doesn't count as an edge
+    pub async fn wait_for_edge(&self, edge: Edge, timeout: Option<Duration>) -> Result<()> {
+        let target = edge == Edge::Rising;
+        within(timeout, async {
+            self.wait_for_state(!target).await?;
+            self.wait_for_state(target).await
+        })
+        .await
+    }
+
+    //Counts rising edges seen over `window` by sampling every poll interval. A pulse is only seen
+    //if both its high and low phases outlast one sample (the poll interval plus the round trip to
+    //the controller), so the fastest rate this counts reliably is about 1 / (2 * that). With the
+    //default 10ms interval stay well under 50Hz, faster sources need a hardware counter.
+    pub async fn count_pulses(&self, window: Duration) -> Result<u32> {
+        let deadline = Instant::now() + window;
+        let mut tick_interval = tokio::time::interval(self.poll.interval);
+        tick_interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        tick_interval.tick().await;
+        let mut state = self.get_state().await?;
+        let mut pulses = 0;
+        while Instant::now() < deadline {
+            tick_interval.tick().await;
+            let current = self.get_state().await?;
+            if current && !state {
+                pulses += 1;
+            }
+            state = current;
+        }
+        Ok(pulses)
+    }
+}
+
+//Runs the future to completion or gives up with a Timeout once the deadline passes
+async fn within<T>(
+    timeout: Option<Duration>,
+    future: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, future)
+            .await
+            .unwrap_or(Err(ControlError::Timeout)),
+        None => future.await,
     }
 }
 
@@ -27,22 +152,210 @@ impl SendRecv for DigitalInput {
         &self.drive_sender
     }
 }
+//Linear map from raw counts to engineering units (volts, PSI, ...)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Calibration {
+    pub slope: f64,
+    pub offset: f64,
+}
+
+impl Calibration {
+    //Builds the line through two (raw, value) points, e.g. a sensor's 4mA and 20mA readings. The
+    //raw counts have to differ or there's no line to draw.
+    pub fn from_points(low: (isize, f64), high: (isize, f64)) -> Result<Self> {
+        if low.0 == high.0 {
+            return Err(ControlError::InvalidArgument(format!(
+                "calibration points share the raw count {}",
+                low.0
+            )));
+        }
+        let slope = (high.1 - low.1) / (high.0 - low.0) as f64;
+        Ok(Self {
+            slope,
+            offset: low.1 - slope * low.0 as f64,
+        })
+    }
+
+    pub fn apply(&self, raw: isize) -> f64 {
+        raw as f64 * self.slope + self.offset
+    }
+}
+
+impl Default for Calibration {
+    fn default() -> Self {
+        Self {
+            slope: 1.0,
+            offset: 0.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThresholdEvent {
+    Above,
+    Below,
+}
+
 #[derive(Clone, Debug)]
 pub struct AnalogInput {
     cmd: [u8; 4],
+    calibration: Calibration,
+    poll_interval: Duration,
     drive_sender: Sender<Message>,
 }
 
 impl AnalogInput {
     pub fn new(id: u8, drive_sender: Sender<Message>) -> Self {
         let cmd = [STX, b'I', int_to_byte(id), CR];
-        Self { cmd, drive_sender }
+        Self {
+            cmd,
+            calibration: Calibration::default(),
+            poll_interval: DEFAULT_POLL_INTERVAL,
+            drive_sender,
+        }
+    }
+
+    //How often watch_threshold samples the input
+    pub fn with_poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    pub fn with_calibration(mut self, slope: f64, offset: f64) -> Self {
+        self.calibration = Calibration { slope, offset };
+        self
+    }
+
+    pub fn with_two_point_calibration(
+        mut self,
+        low: (isize, f64),
+        high: (isize, f64),
+    ) -> Result<Self> {
+        self.calibration = Calibration::from_points(low, high)?;
+        Ok(self)
+    }
+
+    //Raw counts straight from the ClearCore
+    pub async fn get_state(&self) -> Result<isize> {
+        let res = self.try_write_idempotent(self.cmd.to_vec(), None).await?;
+        parse_counts(&res)
+    }
+
+    //get_state that gives up with Busy instead of waiting when the client's queue is full
+    pub async fn try_get_state(&self) -> Result<isize> {
+        let res = self.try_write_now(self.cmd.to_vec(), None).await?;
+        parse_counts(&res)
     }
 
-    pub async fn get_state(&self) -> isize {
-        let res = self.write(self.cmd.as_slice()).await;
-        ascii_to_int(&res[3..])
+    pub async fn read_scaled(&self) -> Result<f64> {
+        Ok(self.calibration.apply(self.get_state().await?))
     }
+
+    //For reads that go out in a Batch, the reply is turned into a value with scale_reply
+    pub(crate) fn read_frame(&self) -> &[u8] {
+        self.cmd.as_slice()
+    }
+
+    pub(crate) fn scale_reply(&self, reply: &[u8]) -> Result<f64> {
+        Ok(self.calibration.apply(parse_counts(reply)?))
+    }
+
+    //Mean of `samples` back to back scaled reads, any failed read fails the whole batch so a
+    //dropped sample can't skew the average
+    pub async fn read_filtered(&self, samples: usize) -> Result<f64> {
+        if samples == 0 {
+            return Err(ControlError::InvalidArgument(
+                "read_filtered needs at least one sample".to_string(),
+            ));
+        }
+        let mut sum = 0.;
+        for _ in 0..samples {
+            sum += self.read_scaled().await?;
+        }
+        Ok(sum / samples as f64)
+    }
+
+    //Polls the scaled value and yields an event each time it crosses `threshold`. The value has to
+    //get past threshold + hysteresis to count as Above and below threshold - hysteresis to count as
+    //Below, so noise around the threshold doesn't chatter. The first reading outside that band
+    //yields its side straight away. Failed reads are logged and skipped, the stream ends once the
+    //client is gone.
+    pub fn watch_threshold(
+        &self,
+        threshold: f64,
+        hysteresis: f64,
+    ) -> impl Stream<Item = ThresholdEvent> {
+        let hysteresis = hysteresis.abs();
+        let state: (Self, Option<Interval>, Option<ThresholdEvent>) = (self.clone(), None, None);
+        stream::unfold(
+            state,
+            move |(input, mut tick_interval, mut last)| async move {
+                //Made on first poll since an Interval can only be created inside the runtime
+                let ticker = tick_interval.get_or_insert_with(|| {
+                    let mut ticker = tokio::time::interval(input.poll_interval);
+                    ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+                    ticker
+                });
+                loop {
+                    if input.drive_sender.is_closed() {
+                        return None;
+                    }
+                    ticker.tick().await;
+                    let value = match input.read_scaled().await {
+                        Ok(value) => value,
+                        Err(e) => {
+                            warn!(
+                                "Threshold watch on input {} failed to read: {e}",
+                                input.cmd[2] as char
+                            );
+                            continue;
+                        }
+                    };
+                    let event = if value > threshold + hysteresis {
+                        ThresholdEvent::Above
+                    } else if value < threshold - hysteresis {
+                        ThresholdEvent::Below
+                    } else {
+                        continue;
+                    };
+                    if last != Some(event) {
+                        last = Some(event);
+                        return Some((event, (input, tick_interval, last)));
+                    }
+                }
+            },
+        )
+    }
+
+    //Yields a scaled reading every `rate`, stamped with when it was taken, until dropped or the
+    //client is gone. A failed read is yielded as its error and sampling carries on. Ticks the
+    //link can't keep up with are skipped rather than bunched up. Dropping the stream drops any
+    //read in flight, nothing keeps polling behind it. A zero rate yields InvalidArgument and ends.
+    pub fn sample_stream(&self, rate: Duration) -> impl Stream<Item = Result<(Instant, f64)>> {
+        let state: (Self, Option<Interval>, bool) = (self.clone(), None, false);
+        stream::unfold(state, move |(input, mut tick_interval, done)| async move {
+            if done || input.drive_sender.is_closed() {
+                return None;
+            }
+            if rate.is_zero() {
+                let e = ControlError::InvalidArgument("sample rate must be non-zero".to_string());
+                return Some((Err(e), (input, tick_interval, true)));
+            }
+            //Made on first poll since an Interval can only be created inside the runtime
+            let ticker = tick_interval.get_or_insert_with(|| {
+                let mut ticker = tokio::time::interval(rate);
+                ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+                ticker
+            });
+            let at = ticker.tick().await;
+            let sample = input.read_scaled().await.map(|value| (at, value));
+            Some((sample, (input, tick_interval, false)))
+        })
+    }
+}
+
+fn parse_counts(reply: &[u8]) -> Result<isize> {
+    Ok(ascii_to_int(ResponseParser::DEVICE.payload(reply)?))
 }
 
 impl SendRecv for AnalogInput {
@@ -51,10 +364,31 @@ impl SendRecv for AnalogInput {
     }
 }
 
+//What an output is driven to when things go wrong, a duty is a PWM percentage like set_pwm takes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SafeState {
+    Off,
+    On,
+    Duty(u8),
+}
+
+impl From<bool> for SafeState {
+    fn from(state: bool) -> Self {
+        if state {
+            SafeState::On
+        } else {
+            SafeState::Off
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct DigitalOutput {
+    id: u8,
     on_cmd: [u8; 9],
     off_cmd: [u8; 9],
+    //Where Controller::emergency_stop and reconnects drive this output, None leaves it alone
+    safe_state: Option<SafeState>,
     drive_sender: Sender<Message>,
 }
 
@@ -63,12 +397,42 @@ impl DigitalOutput {
         let on_cmd = [STX, b'O', int_to_byte(id), b'3', b'2', b'7', b'0', b'0', CR];
         let off_cmd = [STX, b'O', int_to_byte(id), b'0', CR, 0, 0, 0, 0];
         Self {
+            id,
             on_cmd,
             off_cmd,
+            safe_state: None,
             drive_sender,
         }
     }
 
+    pub fn with_safe_state(mut self, state: impl Into<SafeState>) -> Self {
+        self.safe_state = Some(state.into());
+        self
+    }
+
+    pub fn safe_state(&self) -> Option<SafeState> {
+        self.safe_state
+    }
+
+    //A safe duty above 100% is capped rather than dropped, an output left alone is worse
+    pub(crate) fn safe_state_frame(&self) -> Option<Vec<u8>> {
+        Some(match self.safe_state? {
+            SafeState::Off => self.state_frame(false),
+            SafeState::On => self.state_frame(true),
+            SafeState::Duty(duty) => self.pwm_frame(duty.min(100)).ok()?,
+        })
+    }
+
+    //The frame on its own without the off command's padding, for queueing on a Batch
+    pub(crate) fn state_frame(&self, state: bool) -> Vec<u8> {
+        let cmd = self.command_builder(state);
+        let end = cmd
+            .iter()
+            .position(|&byte| byte == CR)
+            .unwrap_or(cmd.len() - 1);
+        cmd[..=end].to_vec()
+    }
+
     fn command_builder(&self, state: bool) -> [u8; 9] {
         if state {
             self.on_cmd
@@ -76,8 +440,89 @@ impl DigitalOutput {
             self.off_cmd
         }
     }
+    //A failure is only logged, see send_state for callers that need to know
     pub async fn set_state(&self, state: bool) {
-        self.write(self.command_builder(state).as_slice()).await;
+        let _ = self
+            .try_write_idempotent(self.command_builder(state).to_vec(), None)
+            .await;
+    }
+
+    //Reads back what the ClearCore is actually driving the output with, on for any non-zero level
+    //so an output left on a PWM duty also reads as on
+    pub async fn get_state(&self) -> Result<bool> {
+        let cmd = [STX, b'O', int_to_byte(self.id), b'G', b'S', CR];
+        let res = self.try_write_idempotent(cmd.to_vec(), None).await?;
+        ResponseParser::DEVICE.flag(&res)
+    }
+
+    //set_state for callers that need to know the command went through, e.g. a Sequence step
+    pub(crate) async fn send_state(&self, state: bool) -> Result<()> {
+        let resp = self
+            .try_write_idempotent(self.command_builder(state).to_vec(), None)
+            .await?;
+        check_result(&resp)
+    }
+
+    pub async fn pulse(&self, duration: Duration) -> Result<()> {
+        let on = self
+            .try_write_idempotent(self.command_builder(true).to_vec(), None)
+            .await?;
+        check_result(&on)?;
+        //If we get cancelled while sleeping the guard turns the output off behind us
+        let guard = PulseGuard { output: self };
+        tokio::time::sleep(duration).await;
+        let off = self
+            .try_write_idempotent(self.command_builder(false).to_vec(), None)
+            .await;
+        //Only disarm once the off command has actually been handed over
+        std::mem::forget(guard);
+        off.and_then(|off| check_result(&off))
+    }
+
+    //Duty in percent, the ClearCore scales it to 0-255. Anything above 100 is rejected.
+    pub async fn set_pwm(&self, duty: u8) -> Result<()> {
+        let cmd = self.pwm_frame(duty)?;
+        let resp = self.try_write_idempotent(cmd, None).await?;
+        check_result(&resp)
+    }
+
+    fn pwm_frame(&self, duty: u8) -> Result<Vec<u8>> {
+        if duty > 100 {
+            return Err(ControlError::InvalidArgument(format!(
+                "output {} PWM duty must be at most 100%, got {duty}%",
+                self.id
+            )));
+        }
+        let duty = num_to_bytes(duty as u16 * CLEAR_CORE_PWM_MAX / 100);
+        let mut cmd: Vec<u8> = Vec::with_capacity(duty.len() + 4);
+        cmd.extend_from_slice(&[STX, b'P', int_to_byte(self.id)]);
+        cmd.extend_from_slice(duty.as_slice());
+        cmd.push(CR);
+        Ok(cmd)
+    }
+}
+
+struct PulseGuard<'a> {
+    output: &'a DigitalOutput,
+}
+
+impl Drop for PulseGuard<'_> {
+    fn drop(&mut self) {
+        //Drop can't await, so the off command goes out on its own task
+        let output = self.output.clone();
+        match tokio::runtime::Handle::try_current() {
+            Ok(handle) => {
+                handle.spawn(async move {
+                    if let Err(e) = output
+                        .try_write_idempotent(output.command_builder(false).to_vec(), None)
+                        .await
+                    {
+                        error!("Failed to turn off output after cancelled pulse: {e}");
+                    }
+                });
+            }
+            Err(_) => error!("No runtime to turn off output after cancelled pulse"),
+        }
     }
 }
 
@@ -124,7 +569,9 @@ impl HBridge {
     }
 
     pub async fn set_state(&self, state: HBridgeState) {
-        self.write(self.command_builder(state).as_slice()).await;
+        let _ = self
+            .try_write_idempotent(self.command_builder(state), None)
+            .await;
     }
 }
 
@@ -133,3 +580,314 @@ impl SendRecv for HBridge {
         &self.drive_sender
     }
 }
+
+#[tokio::test]
+async fn test_output_pulse() {
+    let (tx, mut rx) = tokio::sync::mpsc::channel::<Message>(10);
+    let output = DigitalOutput::new(2, tx);
+    let on = [STX, b'O', b'2', b'3', b'2', b'7', b'0', b'0', CR];
+    let off = [STX, b'O', b'2', b'0', CR, 0, 0, 0, 0];
+    let mock = tokio::spawn(async move {
+        //One pulse that runs to completion then one that gets cancelled mid-pulse
+        for expected in [on, off, on, off] {
+            let msg = rx.recv().await.unwrap();
+            assert_eq!(msg.buffer, expected);
+            msg.response
+                .send(Ok(vec![STX, b'O', b'2', b'_', CR]))
+                .unwrap();
+        }
+    });
+    output.pulse(Duration::from_millis(10)).await.unwrap();
+
+    let cancelled = output.clone();
+    let pulse = tokio::spawn(async move { cancelled.pulse(Duration::from_secs(60)).await });
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    pulse.abort();
+    mock.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_output_get_state() {
+    use crate::controllers::clear_core::Controller;
+    use crate::testing::MockClearCore;
+
+    let mock = MockClearCore::start().await.unwrap();
+    mock.on(b"O2GS", b"32700").on(b"O3GS", b"0");
+    let (controller, client) = Controller::with_client(mock.addr(), &[]);
+    let handle = tokio::spawn(client);
+
+    assert!(controller.get_output(2).get_state().await.unwrap());
+    assert!(!controller.get_output(3).get_state().await.unwrap());
+    //Without a level in the reply there's nothing to read back
+    assert!(matches!(
+        controller.get_output(4).get_state().await,
+        Err(ControlError::BadResponse(_))
+    ));
+    assert_eq!(
+        mock.received(),
+        [
+            b"\x02O2GS\r".to_vec(),
+            b"\x02O3GS\r".to_vec(),
+            b"\x02O4GS\r".to_vec(),
+        ]
+    );
+
+    drop(controller);
+    handle.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_output_pwm() {
+    let (tx, mut rx) = tokio::sync::mpsc::channel::<Message>(10);
+    let output = DigitalOutput::new(4, tx);
+    let mock = tokio::spawn(async move {
+        for duty in [b"127".as_slice(), b"255".as_slice()] {
+            let msg = rx.recv().await.unwrap();
+            assert_eq!(msg.buffer[..3], [STX, b'P', b'4']);
+            assert_eq!(&msg.buffer[3..msg.buffer.len() - 1], duty);
+            assert_eq!(msg.buffer.last(), Some(&CR));
+            msg.response
+                .send(Ok(vec![STX, b'P', b'4', b'_', CR]))
+                .unwrap();
+        }
+        assert!(rx.recv().await.is_none());
+    });
+    output.set_pwm(50).await.unwrap();
+    output.set_pwm(100).await.unwrap();
+    assert!(matches!(
+        output.set_pwm(101).await,
+        Err(ControlError::InvalidArgument(_))
+    ));
+    drop(output);
+    mock.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_analog_two_point_calibration() {
+    let (tx, mut rx) = tokio::sync::mpsc::channel::<Message>(10);
+    //0-100 PSI transducer reading 819 counts at 0 PSI and 4095 at full scale
+    let input = AnalogInput::new(3, tx)
+        .with_two_point_calibration((819, 0.0), (4095, 100.0))
+        .unwrap();
+    let mock = tokio::spawn(async move {
+        for reply in [b"\x02I32457\r".to_vec(), b"\x02I32457\r".to_vec()] {
+            let msg = rx.recv().await.unwrap();
+            assert_eq!(msg.buffer, [STX, b'I', b'3', CR]);
+            msg.response.send(Ok(reply)).unwrap();
+        }
+    });
+    assert_eq!(input.get_state().await.unwrap(), 2457);
+    assert!((input.read_scaled().await.unwrap() - 50.0).abs() < 1e-9);
+    mock.await.unwrap();
+
+    let calibration = Calibration::from_points((0, 1.0), (10, 21.0)).unwrap();
+    assert_eq!(
+        calibration,
+        Calibration {
+            slope: 2.0,
+            offset: 1.0
+        }
+    );
+    assert!(matches!(
+        Calibration::from_points((5, 1.0), (5, 21.0)),
+        Err(ControlError::InvalidArgument(_))
+    ));
+}
+
+#[tokio::test]
+async fn test_analog_read_filtered() {
+    let (tx, mut rx) = tokio::sync::mpsc::channel::<Message>(10);
+    let input = AnalogInput::new(4, tx);
+    let mock = tokio::spawn(async move {
+        for value in [b"100".as_slice(), b"110", b"120"] {
+            let msg = rx.recv().await.unwrap();
+            let mut reply = vec![STX, b'I', b'4'];
+            reply.extend_from_slice(value);
+            reply.push(CR);
+            msg.response.send(Ok(reply)).unwrap();
+        }
+        //Second batch loses its connection on the second sample
+        let msg = rx.recv().await.unwrap();
+        msg.response.send(Ok(b"\x02I4500\r".to_vec())).unwrap();
+        let msg = rx.recv().await.unwrap();
+        msg.response.send(Err(ControlError::Disconnected)).unwrap();
+    });
+    assert_eq!(input.read_filtered(3).await.unwrap(), 110.0);
+    assert!(matches!(
+        input.read_filtered(3).await,
+        Err(ControlError::Disconnected)
+    ));
+    assert!(matches!(
+        input.read_filtered(0).await,
+        Err(ControlError::InvalidArgument(_))
+    ));
+    mock.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_analog_watch_threshold() {
+    use futures::StreamExt;
+
+    let (tx, mut rx) = tokio::sync::mpsc::channel::<Message>(10);
+    let input = AnalogInput::new(4, tx).with_poll_interval(Duration::from_millis(1));
+    let mock = tokio::spawn(async move {
+        //Rises through the band and wobbles inside it before falling back out
+        for value in [b"0".as_slice(), b"5", b"11", b"10", b"12", b"10", b"9"] {
+            let msg = rx.recv().await.unwrap();
+            let mut reply = vec![STX, b'I', b'4'];
+            reply.extend_from_slice(value);
+            reply.push(CR);
+            msg.response.send(Ok(reply)).unwrap();
+        }
+    });
+    let events: Vec<ThresholdEvent> = input.watch_threshold(10.0, 0.5).take(3).collect().await;
+    assert_eq!(
+        events,
+        [
+            ThresholdEvent::Below,
+            ThresholdEvent::Above,
+            ThresholdEvent::Below
+        ]
+    );
+    mock.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_input_debounced() {
+    let (tx, mut rx) = tokio::sync::mpsc::channel::<Message>(10);
+    let input = DigitalInput::new(1, tx);
+    let mock = tokio::spawn(async move {
+        //Bounces a few times before settling high
+        let mut readings = [b'1', b'0', b'1', b'0'].into_iter();
+        while let Some(msg) = rx.recv().await {
+            let state = readings.next().unwrap_or(b'1');
+            msg.response
+                .send(Ok(vec![STX, b'I', b'1', state, CR]))
+                .unwrap();
+        }
+    });
+    let settled = input
+        .get_debounced(Duration::from_millis(30), Duration::from_millis(5))
+        .await
+        .unwrap();
+    assert!(settled);
+    drop(input);
+    mock.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_input_count_pulses() {
+    let (tx, mut rx) = tokio::sync::mpsc::channel::<Message>(10);
+    let input = DigitalInput::new(2, tx).with_poll_interval(Duration::from_millis(1));
+    let mock = tokio::spawn(async move {
+        //Starting high doesn't count, then three full pulses before the line goes quiet
+        let mut readings = [b'1', b'0', b'1', b'0', b'1', b'1', b'0', b'1', b'0'].into_iter();
+        while let Some(msg) = rx.recv().await {
+            let state = readings.next().unwrap_or(b'0');
+            msg.response
+                .send(Ok(vec![STX, b'I', b'2', state, CR]))
+                .unwrap();
+        }
+    });
+    assert_eq!(
+        input.count_pulses(Duration::from_millis(50)).await.unwrap(),
+        3
+    );
+    drop(input);
+    mock.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_input_wait_for_edge() {
+    let (tx, mut rx) = tokio::sync::mpsc::channel::<Message>(10);
+    let input = DigitalInput::new(0, tx).with_poll_interval(Duration::from_millis(1));
+    let mock = tokio::spawn(async move {
+        //Already high, drops, then comes back up and stays there
+        let mut readings = [b'1', b'1', b'0', b'0'].into_iter();
+        while let Some(msg) = rx.recv().await {
+            let state = readings.next().unwrap_or(b'1');
+            msg.response
+                .send(Ok(vec![STX, b'I', b'0', state, CR]))
+                .unwrap();
+        }
+    });
+    input.wait_for_edge(Edge::Rising, None).await.unwrap();
+    input.wait_for_high(None).await.unwrap();
+    assert!(matches!(
+        input.wait_for_low(Some(Duration::from_millis(20))).await,
+        Err(ControlError::Timeout)
+    ));
+    drop(input);
+    mock.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_try_get_state() {
+    use crate::testing::MockClearCore;
+    use tokio::sync::{mpsc, oneshot};
+
+    let (tx, rx) = mpsc::channel::<Message>(1);
+    let input = DigitalInput::new(1, tx.clone());
+    //Nothing is serving the queue yet, one command fills it
+    let (response, _reply) = oneshot::channel();
+    tx.try_send(Message {
+        buffer: vec![STX, b'I', b'0', CR],
+        response,
+        timeout: None,
+        idempotent: true,
+    })
+    .unwrap();
+    assert!(matches!(
+        input.try_get_state().await,
+        Err(ControlError::Busy)
+    ));
+
+    let mock = MockClearCore::start().await.unwrap();
+    mock.on(b"I1", b"1");
+    let handle = tokio::spawn(crate::interface::tcp::client(mock.addr(), rx));
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    assert!(input.try_get_state().await.unwrap());
+    assert_eq!(mock.received().len(), 2);
+
+    drop((tx, input));
+    handle.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_sample_stream() {
+    use crate::testing::MockClearCore;
+    use futures::StreamExt;
+
+    let mock = MockClearCore::start().await.unwrap();
+    mock.on(b"I3", b"100");
+    let (tx, rx) = tokio::sync::mpsc::channel::<Message>(10);
+    let handle = tokio::spawn(crate::interface::tcp::client(mock.addr(), rx));
+    let input = AnalogInput::new(3, tx)
+        .with_two_point_calibration((0, 0.), (100, 50.))
+        .unwrap();
+
+    let samples: Vec<(Instant, f64)> = input
+        .sample_stream(Duration::from_millis(10))
+        .take(3)
+        .map(Result::unwrap)
+        .collect()
+        .await;
+    assert_eq!(
+        samples.iter().map(|(_, value)| *value).collect::<Vec<_>>(),
+        [50.; 3]
+    );
+    assert!(samples[2].0 - samples[0].0 >= Duration::from_millis(20));
+    //Nothing reads on once the stream is gone
+    tokio::time::sleep(Duration::from_millis(30)).await;
+    assert_eq!(mock.received().len(), 3);
+
+    let mut stalled = Box::pin(input.sample_stream(Duration::ZERO));
+    assert!(matches!(
+        stalled.next().await,
+        Some(Err(ControlError::InvalidArgument(_)))
+    ));
+    assert!(stalled.next().await.is_none());
+
+    drop((input, stalled));
+    handle.await.unwrap().unwrap();
+}
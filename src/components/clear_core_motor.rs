@@ -1,18 +1,51 @@
+use crate::components::clear_core_io::DigitalInput;
+use crate::components::protocol::ProtocolMap;
 use crate::components::send_recv::SendRecv;
+use crate::error::{ControlError, Result};
 use crate::subsystems::linear_actuator::Message;
-use crate::util::utils::{ascii_to_int, make_prefix, num_to_bytes};
-use log::error;
-use serde::Serialize;
-use std::result::Result;
+use crate::telemetry;
+use crate::util::poll::{PollConfig, Poller};
+use crate::util::utils::make_prefix;
+use futures::stream::{self, Stream};
+use log::{error, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::future::Future;
+use std::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+use std::sync::Arc;
 pub use std::time::Duration;
 use tokio::sync::mpsc::Sender;
-use tokio::time::MissedTickBehavior;
+use tokio::sync::{watch, Mutex, MutexGuard};
+use tokio::task::JoinHandle;
 
-const REPLY_IDX: usize = 3;
 const _SUCCESSFUL_REPLY: u8 = b'_';
-const FAILED_REPLY: u8 = b'?';
+//Long enough for the client to ride out a retry or two, short enough that a setup with nothing
+//answering fails before anyone wonders whether it hung
+const DEFAULT_ENABLE_TIMEOUT: Duration = Duration::from_secs(5);
+//Only the stock digital inputs sit on the ClearCore's interrupt capable pins (DI-6 to DI-8), so
+//they are the only ones that can latch a motor position
+pub const CAPTURE_INPUTS: [usize; 3] = [0, 1, 2];
+//Move registers a drive holds for trigger_move, numbered from 0
+pub const MOVE_REGISTERS: u8 = 8;
 
-#[derive(Debug, PartialOrd, PartialEq, Serialize)]
+//Pressed against a stop the drive holds its torque at the limit, and what HLFB measures comes in
+//a little under it, so reaching this fraction of the limit counts as having hit the stop
+const HARD_STOP_TORQUE_FRACTION: f64 = 0.9;
+
+//What last_known_enabled has to go on
+const ENABLED_UNKNOWN: u8 = 0;
+const ENABLED_NO: u8 = 1;
+const ENABLED_YES: u8 = 2;
+
+//Bit layout of the ClearCore's StatusRegMotor, which the firmware replies to GS with in decimal
+const STATUS_AT_TARGET: u32 = 1 << 0;
+const STATUS_STEPS_ACTIVE: u32 = 1 << 1;
+const STATUS_IN_FAULT: u32 = 1 << 4;
+const STATUS_ENABLED: u32 = 1 << 5;
+const STATUS_HLFB_SHIFT: u32 = 7;
+const STATUS_READY_SHIFT: u32 = 10;
+
+#[derive(Debug, Clone, Copy, PartialOrd, PartialEq, Serialize)]
 pub enum Status {
     Disabled,
     Enabling,
@@ -22,11 +55,275 @@ pub enum Status {
     Unknown,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct MotorStatus {
+    pub raw: u32,
+    pub state: Status,
+    pub enabled: bool,
+    pub moving: bool,
+    pub at_target: bool,
+    pub faulted: bool,
+    pub hlfb_asserted: bool,
+    //How far the motor lags its commanded position in user units, only read by
+    //get_status_with_following_error and None everywhere else
+    pub following_error: Option<f64>,
+}
+
+impl MotorStatus {
+    pub fn from_bits(raw: u32) -> Self {
+        let state = match (raw >> STATUS_READY_SHIFT) & 0b111 {
+            0 => Status::Disabled,
+            1 => Status::Enabling,
+            2 => Status::Faulted,
+            3 => Status::Ready,
+            4 => Status::Moving,
+            _ => Status::Unknown,
+        };
+        Self {
+            raw,
+            state,
+            enabled: (raw & STATUS_ENABLED) != 0,
+            moving: (raw & STATUS_STEPS_ACTIVE) != 0,
+            at_target: (raw & STATUS_AT_TARGET) != 0,
+            faulted: (raw & STATUS_IN_FAULT) != 0,
+            hlfb_asserted: ((raw >> STATUS_HLFB_SHIFT) & 0b11) == 1,
+            following_error: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HomingDirection {
+    Positive,
+    Negative,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Positive,
+    Negative,
+}
+
+//How home finds home
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HomeMode {
+    //The drive's own homing, started with the home command and configured on the drive
+    #[default]
+    Drive,
+    //Jogs toward the stop at `velocity` user units per second with the torque limited to
+    //`torque_percent` of peak, until the torque measured over HLFB shows it pushing against the
+    //stop. That spot becomes home, then the motor backs off by `backoff` user units. HLFB has to
+    //be set to Measured Torque on the drive. Afterwards the torque limit goes back to whatever
+    //set_torque_limit last set, 100% if nothing has.
+    HardStop {
+        torque_percent: f64,
+        backoff: f64,
+        velocity: f64,
+    },
+}
+
+//In a config file the timeout is in milliseconds
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HomingConfig {
+    pub direction: HomingDirection,
+    //User units to travel away from home before zeroing, HardStop homing backs off instead
+    pub offset: f64,
+    #[serde(rename = "timeout_ms", with = "crate::util::poll::millis")]
+    pub timeout: Duration,
+    pub mode: HomeMode,
+}
+
+impl Default for HomingConfig {
+    fn default() -> Self {
+        Self {
+            direction: HomingDirection::Negative,
+            offset: 0.0,
+            timeout: Duration::from_secs(30),
+            mode: HomeMode::Drive,
+        }
+    }
+}
+
+//How far recover goes after getting the drive enabled again. Position is lost across a fault
+//unless the motor is homed, so a move to a safe spot is only worth it where the drive keeps it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RecoveryStrategy {
+    ClearAndReenable,
+    ClearReenableRehome,
+    //Then waits out a move to this position in user units
+    ClearReenableMoveTo(f64),
+}
+
+//What the drive was set up to put on its HLFB line, which decides how read_hlfb reads it. The
+//ClearCore tells the three apart by whether the line carries a PWM signal:
+//  Static: a plain on/off output such as Servo On, All Systems Go or In Range, read as asserted
+//  or deasserted.
+//  Torque: Measured Torque, a bipolar PWM whose duty away from 50% is the signed torque as a
+//  percentage of peak, read as that percentage.
+//  Speed: Speed Output, a unipolar PWM whose duty is the fraction of the drive's configured
+//  full scale speed, read in user units per second given that speed in the same units.
+//Either PWM mode falls back to asserted or deasserted while the drive holds the line steady,
+//e.g. when disabled.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum HlfbMode {
+    #[default]
+    Static,
+    Torque,
+    Speed {
+        full_scale: f64,
+    },
+}
+
+//One HLFB reading, see HlfbMode for which variant each mode gives
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HlfbState {
+    Deasserted,
+    Asserted,
+    //Percentage of peak torque, negative for the drive's negative direction
+    Torque(f64),
+    //User units per second
+    Velocity(f64),
+    //A PWM the motor wasn't told to expect, as the ClearCore measured it in percent
+    Duty(f64),
+    //The ClearCore hasn't seen enough of the line to tell yet
+    Unknown,
+}
+
+//Where a motor's move queue is at, from ClearCoreMotor::queue_progress
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct QueueProgress {
+    //Moves finished since the queue last started from idle
+    pub completed: usize,
+    //Moves waiting behind the current one
+    pub pending: usize,
+    //Target of the move underway, None once the queue is idle
+    pub current: Option<f64>,
+    //Why the queue gave up, the failed move isn't counted as completed and the rest were dropped
+    pub error: Option<String>,
+}
+
+//Moves handed to queue_moves that haven't started yet and the task working through them, shared
+//by every clone so any handle can add to or cancel the same queue
+#[derive(Default)]
+struct MoveQueueState {
+    pending: VecDeque<f64>,
+    runner: Option<JoinHandle<()>>,
+}
+
+struct MoveQueue {
+    state: std::sync::Mutex<MoveQueueState>,
+    progress: watch::Sender<QueueProgress>,
+}
+
+impl Default for MoveQueue {
+    fn default() -> Self {
+        Self {
+            state: Default::default(),
+            progress: watch::channel(QueueProgress::default()).0,
+        }
+    }
+}
+
+//The task watching the limit switches while a velocity move or jog runs and the switch that last
+//stopped one, shared by every clone so a new velocity move from any handle replaces the watcher
+struct LimitWatch {
+    runner: std::sync::Mutex<Option<JoinHandle<()>>>,
+    tripped: watch::Sender<Option<u8>>,
+}
+
+impl Default for LimitWatch {
+    fn default() -> Self {
+        Self {
+            runner: Default::default(),
+            tripped: watch::channel(None).0,
+        }
+    }
+}
+
+//What happens to a move whose target lies past the soft limits
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LimitMode {
+    #[default]
+    Reject,
+    Clamp,
+}
+
+//Travel bounds in user units, either side can be left open
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct SoftLimits {
+    pub min_position: Option<f64>,
+    pub max_position: Option<f64>,
+    pub mode: LimitMode,
+}
+
+impl SoftLimits {
+    fn is_set(&self) -> bool {
+        self.min_position.is_some() || self.max_position.is_some()
+    }
+}
+
+//Digital inputs wired to switches at either end of travel, in the application's direction so
+//invert doesn't swap them. A switch counts as tripped while its input reads on, so wire normally
+//closed switches to read on when open. Only the switch in the direction of travel is checked, a
+//motor parked on one can still move off it. Homing itself isn't watched.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LimitSwitches {
+    pub positive: Option<u8>,
+    pub negative: Option<u8>,
+}
+
+impl LimitSwitches {
+    fn is_set(&self) -> bool {
+        self.positive.is_some() || self.negative.is_some()
+    }
+
+    fn toward(&self, travel: f64) -> Option<u8> {
+        if travel > 0. {
+            self.positive
+        } else if travel < 0. {
+            self.negative
+        } else {
+            None
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct ClearCoreMotor {
     id: u8,
     prefix: [u8; 3],
-    scale: usize,
+    //Shared by every clone so a rescale reaches whoever else holds this motor
+    scale: Arc<AtomicUsize>,
+    max_velocity: Option<f64>,
+    //Ramps pushed to the drive every time the motor is enabled
+    velocity_limit: Option<f64>,
+    acceleration: Option<f64>,
+    deceleration: Option<f64>,
+    limits: SoftLimits,
+    limit_switches: LimitSwitches,
+    //The drive's positive is the application's negative
+    invert: bool,
+    homing: HomingConfig,
+    hlfb_mode: HlfbMode,
+    poll: PollConfig,
+    enable_timeout: Duration,
+    protocol: ProtocolMap,
+    //Held across a check and the command that depends on it, e.g. the status read ahead of a move,
+    //so another task driving the same motor can't slip its own command in between. Shared by every
+    //clone. Never held while waiting on the motor, so a wait doesn't hold up anyone's commands,
+    //except by home which keeps it from start to finish.
+    sequence: Arc<Mutex<()>>,
+    //What the last acked enable or disable, or the last status read, said. Shared by every clone.
+    enabled: Arc<AtomicU8>,
+    //The torque limit the drive last acked, 100 until one is set. Hard stop homing puts it back
+    //afterwards. Shared by every clone.
+    torque_limit: Arc<AtomicU8>,
+    queue: Arc<MoveQueue>,
+    limit_watch: Arc<LimitWatch>,
     drive_sender: Sender<Message>,
 }
 
@@ -36,168 +333,1183 @@ impl ClearCoreMotor {
         ClearCoreMotor {
             id,
             prefix,
-            scale,
+            scale: Arc::new(AtomicUsize::new(scale)),
+            max_velocity: None,
+            velocity_limit: None,
+            acceleration: None,
+            deceleration: None,
+            limits: SoftLimits::default(),
+            limit_switches: LimitSwitches::default(),
+            invert: false,
+            homing: HomingConfig::default(),
+            hlfb_mode: HlfbMode::default(),
+            poll: PollConfig::default(),
+            enable_timeout: DEFAULT_ENABLE_TIMEOUT,
+            protocol: ProtocolMap::STOCK,
+            sequence: Arc::new(Mutex::new(())),
+            enabled: Arc::new(AtomicU8::new(ENABLED_UNKNOWN)),
+            torque_limit: Arc::new(AtomicU8::new(100)),
+            queue: Arc::new(MoveQueue::default()),
+            limit_watch: Arc::new(LimitWatch::default()),
             drive_sender,
         }
     }
 
-    async fn check_reply(&self, reply: &[u8]) -> Result<(), Status> {
-        if reply[REPLY_IDX] == FAILED_REPLY {
+    pub fn id(&self) -> u8 {
+        self.id
+    }
+
+    //Counts per user unit
+    pub fn scale(&self) -> usize {
+        self.scale.load(Ordering::Relaxed)
+    }
+
+    //Changes the counts per user unit live, e.g. after a gearbox swap, for this motor and every
+    //clone of it. Safe to call from any task without holding the controller's lock since the
+    //scale is atomic, but a command already on its way was converted with the old one and a
+    //call that straddles the change can mix the two, so rescale while the motor is idle.
+    pub fn set_scale(&self, scale: usize) -> Result<()> {
+        if scale == 0 {
+            return Err(ControlError::InvalidArgument(format!(
+                "motor {} scale must be at least 1",
+                self.id
+            )));
+        }
+        self.scale.store(scale, Ordering::Relaxed);
+        Ok(())
+    }
+
+    pub fn with_homing(mut self, homing: HomingConfig) -> Self {
+        self.homing = homing;
+        self
+    }
+
+    //Has moves refuse to start toward a tripped switch and every wait on a move stop the motor
+    //once one trips, see LimitSwitches. A move that's only sent, e.g. move_absolute on its own,
+    //is only checked before it starts, nothing watches the switches until something waits on it.
+    pub fn with_limit_switches(mut self, limit_switches: LimitSwitches) -> Self {
+        self.limit_switches = limit_switches;
+        self
+    }
+
+    pub fn with_hlfb_mode(mut self, hlfb_mode: HlfbMode) -> Self {
+        self.hlfb_mode = hlfb_mode;
+        self
+    }
+
+    //Polls the status at a fixed interval while waiting, see with_poll_config for backing off
+    pub fn with_poll_interval(self, poll_interval: Duration) -> Self {
+        self.with_poll_config(PollConfig::fixed(poll_interval))
+    }
+
+    //How wait_for_move_complete and enable_and_wait pace their status reads
+    pub fn with_poll_config(mut self, poll: PollConfig) -> Self {
+        self.poll = poll;
+        self
+    }
+
+    //How long enable waits for the drive to take the enable and its ramps before giving up with
+    //EnableUnanswered. It bounds the whole exchange on top of the client's own command timeout,
+    //so it also holds when nothing is serving the queue at all.
+    pub fn with_enable_timeout(mut self, timeout: Duration) -> Self {
+        self.enable_timeout = timeout;
+        self
+    }
+
+    //For firmware whose motor commands differ from the stock sketch's
+    pub fn with_protocol(mut self, protocol: ProtocolMap) -> Self {
+        self.prefix = make_prefix(protocol.motor, self.id);
+        self.protocol = protocol;
+        self
+    }
+
+    pub fn with_max_velocity(mut self, max_velocity: f64) -> Self {
+        self.max_velocity = Some(max_velocity.abs());
+        self
+    }
+
+    pub fn with_velocity_limit(mut self, velocity: f64) -> Self {
+        self.velocity_limit = Some(velocity);
+        self
+    }
+
+    pub fn with_acceleration(mut self, acceleration: f64) -> Self {
+        self.acceleration = Some(acceleration);
+        self
+    }
+
+    pub fn with_deceleration(mut self, deceleration: f64) -> Self {
+        self.deceleration = Some(deceleration);
+        self
+    }
+
+    pub fn with_soft_limits(mut self, limits: SoftLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    //Flips every position, velocity and homing direction going to the drive and every position and
+    //velocity read back, so callers use the same sign convention however the axis is mounted.
+    //Soft limits stay in the application's convention.
+    pub fn with_invert(mut self, invert: bool) -> Self {
+        self.invert = invert;
+        self
+    }
+
+    pub(crate) fn check_reply(&self, reply: &[u8]) -> Result<()> {
+        self.protocol.replies.ack.check(reply).inspect_err(|_| {
             error!(
-                "Response from motor controller: {:?}",
+                "Motor {} rejected command, response: {:?}",
+                self.id,
                 reply.to_ascii_lowercase()
-            );
-            Err(self.get_status().await)
-        } else {
-            Ok(())
+            )
+        })
+    }
+
+    pub async fn enable(&self) -> Result<&Self> {
+        match tokio::time::timeout(self.enable_timeout, self.send_enable()).await {
+            Ok(result) => result.map(|_| self),
+            Err(_) => {
+                error!(
+                    "Motor {} got no answer to its enable within {:?}",
+                    self.id, self.enable_timeout
+                );
+                Err(ControlError::EnableUnanswered(self.id, self.enable_timeout))
+            }
+        }
+    }
+
+    async fn send_enable(&self) -> Result<()> {
+        let enable_cmd = self.bare_frame(self.protocol.enable);
+        let resp = self.try_write_idempotent(enable_cmd, None).await?;
+        self.check_reply(resp.as_slice())?;
+        self.set_known_enabled(true);
+        self.apply_limits().await
+    }
+
+    //Enables and then polls until the drive reports ready with HLFB asserted, a drive that is
+    //powered but faulted never gets there and comes back as EnableTimeout
+    pub async fn enable_and_wait(&self, timeout: Duration) -> Result<&Self> {
+        self.enable().await?;
+        match tokio::time::timeout(timeout, self.wait_for_ready()).await {
+            Ok(result) => result.map(|_| self),
+            Err(_) => {
+                error!("Motor {} did not come ready within {timeout:?}", self.id);
+                Err(ControlError::EnableTimeout(self.id))
+            }
+        }
+    }
+
+    async fn wait_for_ready(&self) -> Result<()> {
+        let mut poller = self.poll.poller();
+        loop {
+            poller.tick().await;
+            let status = self.get_status().await?;
+            if status.state == Status::Ready && status.hlfb_asserted {
+                return Ok(());
+            }
+        }
+    }
+
+    //The drive forgets its ramps when it power cycles, so the configured ones go out on every enable
+    async fn apply_limits(&self) -> Result<()> {
+        if let Some(velocity) = self.velocity_limit {
+            self.set_velocity_limit(velocity).await?;
         }
+        if let Some(acceleration) = self.acceleration {
+            self.set_acceleration(acceleration).await?;
+        }
+        if let Some(deceleration) = self.deceleration {
+            self.set_deceleration(deceleration).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn disable(&self) -> Result<()> {
+        let disable_cmd = self.bare_frame(self.protocol.disable);
+        let resp = self.try_write_idempotent(disable_cmd, None).await?;
+        self.check_reply(&resp)?;
+        self.set_known_enabled(false);
+        Ok(())
     }
 
-    pub async fn enable(&self) -> Result<&Self, Status> {
-        let enable_cmd = [2, b'M', self.id + 48, b'E', b'N', 13];
-        let resp = self.write(enable_cmd.as_ref()).await;
-        if let Err(err) = self.check_reply(resp.as_slice()).await {
-            Err(err)
+    //Whether the motor was enabled as of the last enable or disable the drive acked or the last
+    //status read, without asking the drive, None until there has been one. Only as good as that
+    //last word: a fault, an e-stop or a power cycle disables the drive without anything here
+    //hearing of it until the next status read, so check get_status().enabled before relying on
+    //it for anything more than a display.
+    pub fn last_known_enabled(&self) -> Option<bool> {
+        match self.enabled.load(Ordering::Relaxed) {
+            ENABLED_YES => Some(true),
+            ENABLED_NO => Some(false),
+            _ => None,
+        }
+    }
+
+    fn set_known_enabled(&self, enabled: bool) {
+        let state = if enabled { ENABLED_YES } else { ENABLED_NO };
+        self.enabled.store(state, Ordering::Relaxed);
+    }
+
+    pub(crate) async fn ensure_enabled(&self) -> Result<()> {
+        match self.get_status().await?.state {
+            Status::Disabled => Err(ControlError::NotEnabled(self.id)),
+            Status::Faulted => Err(ControlError::MotorFault(self.id)),
+            _ => Ok(()),
+        }
+    }
+
+    //Rounding rather than truncating keeps repeated relative moves from drifting by a count
+    fn to_counts(&self, value: f64) -> isize {
+        (value * (self.scale() as f64)).round() as isize
+    }
+
+    //Applied to counts on their way to the drive and back, negation is its own inverse
+    fn drive_counts(&self, counts: isize) -> isize {
+        if self.invert {
+            -counts
         } else {
-            Ok(self)
+            counts
         }
     }
 
-    pub async fn disable(&self) {
-        let enable_cmd = [2, b'M', self.id + 48, b'D', b'E', 13];
-        self.write(enable_cmd.as_ref()).await;
+    //Bounds are compared in counts so the check agrees with the target that actually gets sent
+    fn limit_target(&self, position: f64) -> Result<isize> {
+        let counts = self.to_counts(position);
+        let min = self
+            .limits
+            .min_position
+            .map_or(isize::MIN, |min| self.to_counts(min));
+        let max = self
+            .limits
+            .max_position
+            .map_or(isize::MAX, |max| self.to_counts(max));
+        if (min..=max).contains(&counts) {
+            return Ok(counts);
+        }
+        match self.limits.mode {
+            LimitMode::Reject => Err(ControlError::OutOfBounds(self.id, position)),
+            LimitMode::Clamp => {
+                warn!(
+                    "Motor {} target {position} is outside its soft limits, clamping",
+                    self.id
+                );
+                Ok(counts.max(min).min(max))
+            }
+        }
     }
 
-    pub async fn absolute_move(&self, position: f64) -> Result<(), Status> {
-        let position = num_to_bytes((position * (self.scale as f64)).trunc() as isize);
-        let mut msg: Vec<u8> = Vec::with_capacity(position.len() + self.prefix.len() + 1);
-        msg.extend_from_slice(self.prefix.as_slice());
-        msg.extend_from_slice(b"AM");
-        msg.extend_from_slice(position.as_slice());
-        msg.push(13);
-        let resp = self.write(msg.as_slice()).await;
-        self.check_reply(&resp).await
+    pub async fn move_absolute(&self, position: f64) -> Result<()> {
+        let _sequence = self.sequence.lock().await;
+        self.check_move_absolute(position).await?;
+        self.send_move_absolute(position).await
     }
 
-    pub async fn relative_move(&self, position: f64) -> Result<(), Status> {
-        let position = num_to_bytes((position * (self.scale as f64)).trunc() as isize);
-        let mut msg: Vec<u8> = Vec::with_capacity(position.len() + self.prefix.len() + 1);
-        msg.extend_from_slice(self.prefix.as_slice());
-        msg.extend_from_slice(b"RM");
-        msg.extend_from_slice(position.as_slice());
-        msg.push(13);
-        let resp = self.write(msg.as_slice()).await;
-        self.check_reply(&resp).await
+    //For moves that go out with other motors' in one write, see
+    //Controller::move_motors_synchronized
+    pub(crate) async fn lock_sequence(&self) -> MutexGuard<'_, ()> {
+        self.sequence.lock().await
     }
 
-    pub async fn jog(&self, speed: f64) -> Result<(), Status> {
-        let speed = num_to_bytes((speed * (self.scale as f64)).trunc() as isize);
-        let mut msg: Vec<u8> = Vec::with_capacity(speed.len() + self.prefix.len() + 1);
-        msg.extend_from_slice(self.prefix.as_slice());
-        msg.extend_from_slice(b"JG");
-        msg.extend_from_slice(speed.as_slice());
-        msg.push(13);
-        let resp = self.write(msg.as_slice()).await;
-        self.check_reply(&resp).await
+    //What move_absolute checks ahead of the AM, for callers already holding the sequence lock
+    pub(crate) async fn check_move_absolute(&self, position: f64) -> Result<()> {
+        self.ensure_enabled().await?;
+        //Only motors with limit switches pay for the extra position read
+        if self.limit_switches.is_set() {
+            let current = self.get_position().await?;
+            self.reject_tripped_limit(position - current).await?;
+        }
+        Ok(())
     }
 
-    pub async fn abrupt_stop(&self) {
-        let stop_cmd = [2, b'M', self.id + 48, b'A', b'S', 13];
-        self.write(stop_cmd.as_ref()).await;
+    //The AM command on its own, for callers that have already checked the drive is enabled
+    async fn send_move_absolute(&self, position: f64) -> Result<()> {
+        let msg = self.move_absolute_frame(position)?;
+        let resp = self.try_write_idempotent(msg, None).await?;
+        self.check_reply(&resp)
     }
 
-    pub async fn stop(&self) {
-        let stop_cmd = [2, b'M', self.id + 48, b'S', b'T', 13];
-        self.write(stop_cmd.as_ref()).await;
+    pub async fn move_relative(&self, delta: f64) -> Result<()> {
+        let _sequence = self.sequence.lock().await;
+        self.send_move_relative(delta).await
     }
 
-    pub async fn set_position(&self, position: isize) {
-        let pos = num_to_bytes(position * self.scale as isize);
-        let mut msg: Vec<u8> = Vec::with_capacity(pos.len() + self.prefix.len() + 1);
-        msg.extend_from_slice(self.prefix.as_slice());
-        msg.extend_from_slice(b"SP");
-        msg.extend_from_slice(pos.as_slice());
-        msg.push(13);
-        self.write(msg.as_slice()).await;
+    //move_relative for callers already holding the sequence lock
+    async fn send_move_relative(&self, delta: f64) -> Result<()> {
+        let mut counts = self.to_counts(delta);
+        //Only limited motors pay for the extra position read
+        if self.limits.is_set() {
+            let current = self.get_position().await?;
+            counts = self.limit_target(current + delta)? - self.to_counts(current);
+        }
+        if counts == 0 {
+            return Ok(());
+        }
+        self.reject_tripped_limit(delta).await?;
+        let msg = self.command_frame(self.protocol.move_relative, self.drive_counts(counts));
+        let resp = self.try_write_owned(msg, None).await?;
+        self.check_reply(&resp)
+    }
+
+    //Spins continuously at `velocity` user units per second, the sign picks the direction.
+    //Clamped to the motor's max velocity. With limit switches the one toward travel is watched
+    //for as long as the motor keeps moving, see limit_trips.
+    pub async fn move_velocity(&self, velocity: f64) -> Result<()> {
+        let _sequence = self.sequence.lock().await;
+        let velocity = self.send_move_velocity(velocity).await?;
+        self.watch_limits(velocity);
+        Ok(())
     }
 
-    pub async fn set_velocity(&self, mut velocity: f64) {
-        if velocity < 0. {
-            velocity = 0.;
+    //The JG command on its own after the clamp and the limit check, returning the velocity that
+    //went out. Nothing watches the switches afterwards.
+    async fn send_move_velocity(&self, mut velocity: f64) -> Result<f64> {
+        if let Some(max) = self.max_velocity {
+            if velocity.abs() > max {
+                warn!("Motor {} velocity {velocity} clamped to {max}", self.id);
+                velocity = max.copysign(velocity);
+            }
         }
-        let vel = num_to_bytes((velocity * (self.scale as f64)).trunc() as isize);
-        let mut msg: Vec<u8> = Vec::with_capacity(vel.len() + self.prefix.len() + 1);
-        msg.extend_from_slice(self.prefix.as_slice());
-        msg.extend_from_slice(b"SV");
-        msg.extend_from_slice(vel.as_slice());
-        msg.push(13);
-        self.write(msg.as_slice()).await;
+        self.reject_tripped_limit(velocity).await?;
+        let speed = self.drive_counts(self.to_counts(velocity));
+        let msg = self.command_frame(self.protocol.jog, speed);
+        let resp = self.try_write_idempotent(msg, None).await?;
+        self.check_reply(&resp)?;
+        Ok(velocity)
     }
 
-    pub async fn set_acceleration(&self, acceleration: f64) {
-        let accel = num_to_bytes((acceleration * (self.scale as f64)).trunc() as isize);
-        let mut msg: Vec<u8> = Vec::with_capacity(accel.len() + self.prefix.len() + 1);
+    //Open-ended motion for manual control, runs until jog_stop. Jogging again while already jogging
+    //just retargets the running move, the drive ramps straight to the new speed or direction with
+    //its acceleration instead of stopping first. Soft limits don't apply since there's no target,
+    //limit switches are watched as for move_velocity.
+    pub async fn jog_start(&self, velocity: f64, direction: Direction) -> Result<()> {
+        let _sequence = self.sequence.lock().await;
+        self.ensure_enabled().await?;
+        let velocity = match direction {
+            Direction::Positive => velocity.abs(),
+            Direction::Negative => -velocity.abs(),
+        };
+        let velocity = self.send_move_velocity(velocity).await?;
+        self.watch_limits(velocity);
+        Ok(())
+    }
+
+    //The input of the limit switch that last stopped a velocity move or jog, the LimitTripped
+    //that move would have failed with had anything been waiting on it. Back to None whenever a
+    //new watched move starts.
+    pub fn limit_trips(&self) -> watch::Receiver<Option<u8>> {
+        self.limit_watch.tripped.subscribe()
+    }
+
+    //Swaps whatever watcher an earlier velocity move left running for one following `velocity`,
+    //none at all without a switch in that direction
+    fn watch_limits(&self, velocity: f64) {
+        let mut runner = self.limit_watch.runner.lock().unwrap();
+        if let Some(previous) = runner.take() {
+            previous.abort();
+        }
+        if self.limit_switches.toward(velocity).is_some() {
+            self.limit_watch
+                .tripped
+                .send_if_modified(|tripped| tripped.take().is_some());
+            *runner = Some(tokio::spawn(self.clone().run_limit_watch(velocity)));
+        }
+    }
+
+    //Reads the switch toward travel on every poll until the motor stops moving, a tripped one
+    //stops it abruptly and is published on limit_trips
+    async fn run_limit_watch(self, velocity: f64) {
+        let mut poller = self.poll.poller();
+        loop {
+            poller.tick().await;
+            let tripped = match self.get_status().await {
+                Ok(status) if status.state != Status::Moving => return,
+                Ok(_) => self.tripped_limit(velocity).await,
+                Err(e) => Err(e),
+            };
+            match tripped {
+                Ok(None) => {}
+                Ok(Some(input)) => {
+                    error!(
+                        "Motor {} hit the limit switch on input {input}, stopping",
+                        self.id
+                    );
+                    if let Err(e) = self.abrupt_stop().await {
+                        error!("Motor {} failed to stop at the limit switch: {e}", self.id);
+                    }
+                    self.limit_watch.tripped.send_replace(Some(input));
+                    return;
+                }
+                Err(e) => {
+                    warn!("Motor {} stopped watching its limit switches: {e}", self.id);
+                    return;
+                }
+            }
+        }
+    }
+
+    //Ramps down with the deceleration rather than halting abruptly, safe to call when not jogging
+    pub async fn jog_stop(&self) -> Result<()> {
+        self.stop().await
+    }
+
+    //Halts as fast as the drive allows, ignoring the deceleration ramp
+    pub async fn abrupt_stop(&self) -> Result<()> {
+        let resp = self
+            .try_write_idempotent(self.abrupt_stop_frame(), None)
+            .await?;
+        self.check_reply(&resp)
+    }
+
+    pub fn abrupt_stop_frame(&self) -> Vec<u8> {
+        self.bare_frame(self.protocol.abrupt_stop)
+    }
+
+    //Decelerates to rest using the configured deceleration
+    pub async fn stop(&self) -> Result<()> {
+        let stop_cmd = self.bare_frame(self.protocol.stop);
+        let resp = self.try_write_idempotent(stop_cmd, None).await?;
+        self.check_reply(&resp)
+    }
+
+    pub async fn set_position(&self, position: isize) -> Result<()> {
+        let pos = self.drive_counts(position * self.scale() as isize);
+        let msg = self.command_frame(self.protocol.set_position, pos);
+        let resp = self.try_write_idempotent(msg, None).await?;
+        self.check_reply(&resp)
+    }
+
+    //Max velocity the drive uses for positional moves, in user units per second
+    pub async fn set_velocity_limit(&self, velocity: f64) -> Result<()> {
+        let msg = self.velocity_limit_frame(velocity)?;
+        let resp = self.try_write_idempotent(msg, None).await?;
+        self.check_reply(&resp)
+    }
+
+    pub async fn set_acceleration(&self, acceleration: f64) -> Result<()> {
+        let msg = self.acceleration_frame(acceleration)?;
+        let resp = self.try_write_idempotent(msg, None).await?;
+        self.check_reply(&resp)
+    }
+
+    //Frame builders for the commands above, for queueing on a Batch instead of sending one by one
+    pub fn velocity_limit_frame(&self, velocity: f64) -> Result<Vec<u8>> {
+        if velocity.is_nan() || velocity < 0. {
+            return Err(ControlError::InvalidArgument(format!(
+                "motor {} velocity limit must not be negative, got {velocity}",
+                self.id
+            )));
+        }
+        Ok(self.command_frame(self.protocol.velocity_limit, self.to_counts(velocity)))
+    }
+
+    pub fn acceleration_frame(&self, acceleration: f64) -> Result<Vec<u8>> {
+        if acceleration.is_nan() || acceleration < 0. {
+            return Err(ControlError::InvalidArgument(format!(
+                "motor {} acceleration must not be negative, got {acceleration}",
+                self.id
+            )));
+        }
+        Ok(self.command_frame(self.protocol.acceleration, self.to_counts(acceleration)))
+    }
+
+    pub fn deceleration_frame(&self, deceleration: f64) -> Result<Vec<u8>> {
+        if deceleration.is_nan() || deceleration < 0. {
+            return Err(ControlError::InvalidArgument(format!(
+                "motor {} deceleration must not be negative, got {deceleration}",
+                self.id
+            )));
+        }
+        Ok(self.command_frame(self.protocol.deceleration, self.to_counts(deceleration)))
+    }
+
+    //Soft limits still apply, but unlike move_absolute the enable state isn't checked first
+    pub fn move_absolute_frame(&self, position: f64) -> Result<Vec<u8>> {
+        let counts = self.limit_target(position)?;
+        Ok(self.command_frame(self.protocol.move_absolute, self.drive_counts(counts)))
+    }
+
+    //Frames go out with try_write_idempotent, so the client may resend them after a transient
+    //failure, unless running them twice does something different: relative moves, register
+    //triggers, capture arming and homing are sent with try_write_owned. It goes by the field used,
+    //so a remapped ProtocolMap is resent exactly as the stock one would be.
+    fn command_frame(&self, command: [u8; 2], value: isize) -> Vec<u8> {
+        let value = self.protocol.values.encode(value);
+        let mut msg: Vec<u8> = Vec::with_capacity(value.len() + self.prefix.len() + 3);
         msg.extend_from_slice(self.prefix.as_slice());
-        msg.extend_from_slice(b"SA");
-        msg.extend_from_slice(accel.as_slice());
+        msg.extend_from_slice(&command);
+        msg.extend_from_slice(value.as_slice());
         msg.push(13);
-        self.write(msg.as_slice()).await;
+        msg
     }
 
-    pub async fn set_deceleration(&self, deceleration: f64) {
-        let accel = num_to_bytes((deceleration * (self.scale as f64)).trunc() as isize);
-        let mut msg: Vec<u8> = Vec::with_capacity(accel.len() + self.prefix.len() + 1);
+    //A command that carries no value, e.g. a query
+    fn bare_frame(&self, command: [u8; 2]) -> Vec<u8> {
+        let mut msg: Vec<u8> = Vec::with_capacity(self.prefix.len() + 3);
         msg.extend_from_slice(self.prefix.as_slice());
-        msg.extend_from_slice(b"SD");
-        msg.extend_from_slice(accel.as_slice());
+        msg.extend_from_slice(&command);
         msg.push(13);
-        self.write(msg.as_slice()).await;
-    }
-
-    pub async fn get_status(&self) -> Status {
-        let status_cmd = [2, b'M', self.id + 48, b'G', b'S', 13];
-        let res = self.write(status_cmd.as_slice()).await;
-        match res[3] {
-            48 => Status::Disabled,
-            49 => Status::Enabling,
-            50 => Status::Faulted,
-            51 => Status::Ready,
-            52 => Status::Moving,
-            _ => Status::Unknown,
+        msg
+    }
+
+    //Caps the drive's torque as a percentage of its peak, above 100 is meaningless to the drive
+    pub async fn set_torque_limit(&self, percent: u8) -> Result<()> {
+        if percent > 100 {
+            return Err(ControlError::InvalidArgument(format!(
+                "motor {} torque limit must be at most 100%, got {percent}%",
+                self.id
+            )));
         }
+        let msg = self.command_frame(self.protocol.torque_limit, percent as isize);
+        let resp = self.try_write_idempotent(msg, None).await?;
+        self.check_reply(&resp)?;
+        self.torque_limit.store(percent, Ordering::Relaxed);
+        Ok(())
     }
 
-    pub async fn get_position(&self) -> f64 {
-        let get_pos_cmd = [2, b'M', self.id + 48, b'G', b'P', 13];
-        let res = self.write(get_pos_cmd.as_slice()).await;
-        (ascii_to_int(res.as_slice()) as f64) / (self.scale as f64)
+    //Torque as a signed percentage of peak, measured by the ClearCore from the drive's HLFB duty
+    //cycle. HLFB has to be configured for torque output on the drive for this to mean anything.
+    pub async fn get_torque(&self) -> Result<f64> {
+        let get_torque_cmd = self.bare_frame(self.protocol.get_torque);
+        let res = self.try_write_idempotent(get_torque_cmd, None).await?;
+        Ok(self.protocol.replies.torque.integer(&res)? as f64)
     }
 
-    pub async fn clear_alerts(&self) {
-        let clear_cmd = [2, b'M', self.id + 48, b'C', b'A', 13];
-        self.write(clear_cmd.as_slice()).await;
+    //Reads the HLFB line from the status, and when the ClearCore reports a PWM measurement on it
+    //the duty it measured, translated as the motor's HlfbMode says
+    pub async fn read_hlfb(&self) -> Result<HlfbState> {
+        let status = self.get_status().await?;
+        let state = match (status.raw >> STATUS_HLFB_SHIFT) & 0b11 {
+            0 => HlfbState::Deasserted,
+            1 => HlfbState::Asserted,
+            2 => {
+                let duty = self.get_torque().await?;
+                match self.hlfb_mode {
+                    HlfbMode::Static => HlfbState::Duty(duty),
+                    HlfbMode::Torque => HlfbState::Torque(duty),
+                    HlfbMode::Speed { full_scale } => {
+                        HlfbState::Velocity(duty.abs() / 100. * full_scale)
+                    }
+                }
+            }
+            _ => HlfbState::Unknown,
+        };
+        Ok(state)
     }
 
-    pub async fn wait_for_move(&self, interval: Duration) -> Result<(), Status> {
-        let mut tick_interval = tokio::time::interval(interval);
-        tick_interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
-        loop {
-            let status = self.get_status().await;
-            match status {
-                Status::Moving => {
-                    tick_interval.tick().await;
-                    continue;
+    //Ramp used by stop and at the end of moves, independent of the acceleration. Firmware built
+    //with a single ramp rejects SD and decelerates with the acceleration instead, that is only
+    //warned about since the motor still stops, just not as gently as asked.
+    pub async fn set_deceleration(&self, deceleration: f64) -> Result<()> {
+        let msg = self.deceleration_frame(deceleration)?;
+        let resp = self.try_write_idempotent(msg, None).await?;
+        match self.protocol.replies.ack.check(&resp) {
+            Err(ControlError::CommandRejected(_)) => {
+                warn!(
+                    "Motor {} has no separate deceleration ramp, it decelerates with its acceleration",
+                    self.id
+                );
+                Ok(())
+            }
+            result => result,
+        }
+    }
+
+    pub async fn get_status(&self) -> Result<MotorStatus> {
+        let status_cmd = self.bare_frame(self.protocol.get_status);
+        let res = self.try_write_idempotent(status_cmd, None).await?;
+        let status = MotorStatus::from_bits(self.protocol.replies.status.integer(&res)? as u32);
+        self.set_known_enabled(status.enabled);
+        Ok(status)
+    }
+
+    //get_status that gives up with Busy instead of waiting when the client's queue is full
+    pub async fn try_get_status(&self) -> Result<MotorStatus> {
+        let status_cmd = self.bare_frame(self.protocol.get_status);
+        let res = self.try_write_now(status_cmd, None).await?;
+        let status = MotorStatus::from_bits(self.protocol.replies.status.integer(&res)? as u32);
+        self.set_known_enabled(status.enabled);
+        Ok(status)
+    }
+
+    //The status with following_error filled in, at the cost of a second read
+    pub async fn get_status_with_following_error(&self) -> Result<MotorStatus> {
+        let mut status = self.get_status().await?;
+        status.following_error = Some(self.get_following_error().await?);
+        Ok(status)
+    }
+
+    //Commanded minus actual position in user units as the drive reports it, with the same sign
+    //as the move. An error that keeps growing on moves that used to track is an early sign of a
+    //jam or a wearing mechanism, long before the drive trips its own fault limit.
+    pub async fn get_following_error(&self) -> Result<f64> {
+        let get_error_cmd = self.bare_frame(self.protocol.get_following_error);
+        let res = self.try_write_idempotent(get_error_cmd, None).await?;
+        Ok(
+            (self.drive_counts(self.protocol.replies.following_error.integer(&res)?) as f64)
+                / (self.scale() as f64),
+        )
+    }
+
+    pub async fn get_position(&self) -> Result<f64> {
+        let get_pos_cmd = self.bare_frame(self.protocol.get_position);
+        let res = self.try_write_idempotent(get_pos_cmd, None).await?;
+        let position = (self.drive_counts(self.protocol.replies.position.integer(&res)?) as f64)
+            / (self.scale() as f64);
+        telemetry::record_motor_position(self.id, position);
+        Ok(position)
+    }
+
+    //get_position that gives up with Busy instead of waiting when the client's queue is full
+    pub async fn try_get_position(&self) -> Result<f64> {
+        let get_pos_cmd = self.bare_frame(self.protocol.get_position);
+        let res = self.try_write_now(get_pos_cmd, None).await?;
+        let position = (self.drive_counts(self.protocol.replies.position.integer(&res)?) as f64)
+            / (self.scale() as f64);
+        telemetry::record_motor_position(self.id, position);
+        Ok(position)
+    }
+
+    //The drive's current commanded velocity in units/sec, negative while moving backwards
+    pub async fn get_velocity(&self) -> Result<f64> {
+        let get_vel_cmd = self.bare_frame(self.protocol.get_velocity);
+        let res = self.try_write_idempotent(get_vel_cmd, None).await?;
+        Ok(
+            (self.drive_counts(self.protocol.replies.velocity.integer(&res)?) as f64)
+                / (self.scale() as f64),
+        )
+    }
+
+    //Stores a relative move of `distance` units in one of the drive's MOVE_REGISTERS, sent as RS
+    //with the register digit ahead of the counts. Needs a firmware sketch that handles RS and RT,
+    //the stock one rejects both. The firmware keeps the registers in RAM so they are lost when the
+    //ClearCore resets and have to be loaded again after a reconnect. Soft limits are not checked
+    //since where the move ends depends on where the motor is when it's triggered.
+    pub async fn set_move_register(&self, index: u8, distance: f64) -> Result<()> {
+        let msg = self.move_register_frame(index, distance)?;
+        let resp = self.try_write_idempotent(msg, None).await?;
+        self.check_reply(&resp)
+    }
+
+    pub fn move_register_frame(&self, index: u8, distance: f64) -> Result<Vec<u8>> {
+        check_move_register(index)?;
+        let mut msg = self.command_frame(
+            self.protocol.store_register,
+            self.drive_counts(self.to_counts(distance)),
+        );
+        msg.insert(self.prefix.len() + 2, index + 48);
+        Ok(msg)
+    }
+
+    //Starts the move stored in register `index` with the drive's current ramps, a far shorter frame
+    //than a full move for sequences that repeat the same moves. The drive rejects registers that
+    //were never loaded.
+    pub async fn trigger_move(&self, index: u8) -> Result<()> {
+        let msg = self.trigger_move_frame(index)?;
+        let resp = self.try_write_owned(msg, None).await?;
+        self.check_reply(&resp)
+    }
+
+    pub fn trigger_move_frame(&self, index: u8) -> Result<Vec<u8>> {
+        check_move_register(index)?;
+        Ok(self.command_frame(self.protocol.trigger_register, index as isize))
+    }
+
+    //Arms the drive to latch its position on the next rising edge of `input`, one of
+    //CAPTURE_INPUTS. Arming again discards whatever was latched before.
+    pub async fn enable_position_capture(&self, input: usize) -> Result<()> {
+        if !CAPTURE_INPUTS.contains(&input) {
+            return Err(ControlError::InvalidArgument(format!(
+                "input {input} can't capture positions, only inputs {CAPTURE_INPUTS:?} can"
+            )));
+        }
+        let msg = self.command_frame(self.protocol.arm_capture, input as isize);
+        let resp = self.try_write_owned(msg, None).await?;
+        self.check_reply(&resp)
+    }
+
+    //The position latched since capture was armed, the drive rejects the read if the input hasn't
+    //fired yet
+    pub async fn read_captured_position(&self) -> Result<f64> {
+        let get_capture_cmd = self.bare_frame(self.protocol.get_capture);
+        let res = self.try_write_idempotent(get_capture_cmd, None).await?;
+        self.check_reply(&res)?;
+        Ok(
+            (self.drive_counts(self.protocol.replies.capture.integer(&res)?) as f64)
+                / (self.scale() as f64),
+        )
+    }
+
+    //Clears the drive's alert register and reads the status back, a fault that is still latched
+    //(e.g. the jam is still there) comes back as StillFaulted rather than silently staying on
+    pub async fn clear_fault(&self) -> Result<()> {
+        let clear_cmd = self.bare_frame(self.protocol.clear_alerts);
+        let resp = self.try_write_idempotent(clear_cmd, None).await?;
+        self.check_reply(&resp)?;
+        if self.get_status().await?.faulted {
+            Err(ControlError::StillFaulted(self.id))
+        } else {
+            Ok(())
+        }
+    }
+
+    //The drive reports Moving for as long as the homing sequence runs and Ready once it has found
+    //home, after which the optional offset is applied and that spot becomes position zero. The
+    //sequence lock is held throughout, so no other command moves the axis while it's homing.
+    pub async fn home(&self) -> Result<()> {
+        let _sequence = self.sequence.lock().await;
+        if let HomeMode::HardStop {
+            torque_percent,
+            backoff,
+            velocity,
+        } = self.homing.mode
+        {
+            return self.home_hard_stop(torque_percent, backoff, velocity).await;
+        }
+        self.ensure_enabled().await?;
+        let direction = self.drive_counts(match self.homing.direction {
+            HomingDirection::Positive => 1,
+            HomingDirection::Negative => -1,
+        });
+        let msg = self.command_frame(self.protocol.home, direction);
+        let resp = self.try_write_owned(msg, None).await?;
+        self.check_reply(&resp)?;
+
+        //Unguarded since a timeout is answered with an abrupt stop below. The drive homes on its
+        //own, against a hard stop or its sensor that may sit right by a limit switch, so the
+        //switches are left to the moves after it.
+        let homing = self.poll_until_done(self.poll, false);
+        match tokio::time::timeout(self.homing.timeout, homing).await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => {
+                error!("Motor {} failed to home: {e}", self.id);
+                return Err(ControlError::HomingFailed(self.id));
+            }
+            Err(_) => {
+                error!("Motor {} timed out while homing", self.id);
+                if let Err(e) = self.abrupt_stop().await {
+                    error!("Motor {} failed to stop after homing timeout: {e}", self.id);
                 }
-                Status::Disabled | Status::Faulted | Status::Unknown => return Err(status),
-                Status::Ready => break,
-                Status::Enabling => {
-                    tick_interval.tick().await;
-                    continue;
+                return Err(ControlError::HomingFailed(self.id));
+            }
+        }
+
+        if self.homing.offset != 0.0 {
+            self.send_move_relative(self.homing.offset).await?;
+            self.wait_for_move_polling(self.poll)
+                .await
+                .map_err(|_| ControlError::HomingFailed(self.id))?;
+        }
+        self.set_position(0).await
+    }
+
+    //Only called by home, which holds the sequence lock
+    async fn home_hard_stop(&self, torque_percent: f64, backoff: f64, velocity: f64) -> Result<()> {
+        if !(torque_percent > 0. && torque_percent <= 100.) {
+            return Err(ControlError::InvalidArgument(format!(
+                "motor {} hard stop torque must be within 0-100%, got {torque_percent}%",
+                self.id
+            )));
+        }
+        self.ensure_enabled().await?;
+        let toward = match self.homing.direction {
+            HomingDirection::Positive => 1.,
+            HomingDirection::Negative => -1.,
+        };
+        let torque_limit = self.torque_limit.load(Ordering::Relaxed);
+        self.set_torque_limit(torque_percent.ceil() as u8).await?;
+        //Unguarded like the drive's homing, the timeout and failures are answered with an abrupt
+        //stop below
+        let found = async {
+            self.send_move_velocity(velocity.abs() * toward).await?;
+            let threshold = torque_percent * HARD_STOP_TORQUE_FRACTION;
+            let mut poller = self.poll.poller();
+            loop {
+                poller.tick().await;
+                let status = self.get_status().await?;
+                if status.faulted {
+                    return Err(ControlError::MotorFault(self.id));
+                }
+                if !status.enabled {
+                    return Err(ControlError::NotEnabled(self.id));
                 }
+                if self.get_torque().await?.abs() >= threshold {
+                    return Ok(());
+                }
+            }
+        };
+        let found = match tokio::time::timeout(self.homing.timeout, found).await {
+            Ok(found) => found,
+            Err(_) => Err(ControlError::Timeout),
+        };
+        if let Err(e) = self.abrupt_stop().await {
+            error!("Motor {} failed to stop at the hard stop: {e}", self.id);
+        }
+        let homed = async {
+            found?;
+            self.set_position(0).await?;
+            self.set_torque_limit(torque_limit).await?;
+            self.send_move_relative(backoff.abs() * -toward).await?;
+            self.wait_for_move_polling(self.poll).await
+        };
+        match homed.await {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                error!(
+                    "Motor {} failed to home against the hard stop: {e}",
+                    self.id
+                );
+                if let Err(e) = self.set_torque_limit(torque_limit).await {
+                    error!("Motor {} torque limit was left reduced: {e}", self.id);
+                }
+                Err(ControlError::HomingFailed(self.id))
+            }
+        }
+    }
+
+    //Like the move_*_blocking variants this stops the motor if it's dropped before the move is
+    //done, see stop_if_cancelled
+    pub async fn wait_for_move(&self, interval: Duration) -> Result<()> {
+        self.stop_if_cancelled(self.wait_for_move_polling(PollConfig::fixed(interval)))
+            .await
+    }
+
+    async fn wait_for_move_polling(&self, poll: PollConfig) -> Result<()> {
+        self.poll_until_done(poll, true).await
+    }
+
+    async fn poll_until_done(&self, poll: PollConfig, watch_limits: bool) -> Result<()> {
+        let mut poller = poll.poller();
+        loop {
+            //The first tick completes immediately so a motor that is already done returns at once
+            poller.tick().await;
+            let status = self.get_status().await?;
+            if status.faulted {
+                return Err(ControlError::MotorFault(self.id));
+            }
+            match status.state {
+                Status::Ready => return Ok(()),
+                Status::Moving if watch_limits => self.stop_at_tripped_limit().await?,
+                Status::Moving => continue,
+                Status::Enabling => continue,
+                Status::Disabled => return Err(ControlError::NotEnabled(self.id)),
+                Status::Faulted | Status::Unknown => return Err(ControlError::MotorFault(self.id)),
+            }
+        }
+    }
+
+    //The limit switch toward `travel` if it is tripped, reading nothing without switches
+    async fn tripped_limit(&self, travel: f64) -> Result<Option<u8>> {
+        let Some(input) = self.limit_switches.toward(travel) else {
+            return Ok(None);
+        };
+        let tripped = DigitalInput::new(input, self.drive_sender.clone())
+            .get_state()
+            .await?;
+        Ok(tripped.then_some(input))
+    }
+
+    async fn reject_tripped_limit(&self, travel: f64) -> Result<()> {
+        match self.tripped_limit(travel).await? {
+            Some(input) => {
+                error!(
+                    "Motor {} is against the limit switch on input {input}",
+                    self.id
+                );
+                Err(ControlError::LimitTripped(self.id, input))
             }
+            None => Ok(()),
+        }
+    }
+
+    //Checked on every poll of a wait while the motor is moving, the velocity says which way it is
+    //going. A tripped switch stops the motor abruptly and fails the wait.
+    async fn stop_at_tripped_limit(&self) -> Result<()> {
+        if !self.limit_switches.is_set() {
+            return Ok(());
+        }
+        let velocity = self.get_velocity().await?;
+        if let Some(input) = self.tripped_limit(velocity).await? {
+            error!(
+                "Motor {} hit the limit switch on input {input}, stopping",
+                self.id
+            );
+            self.abrupt_stop().await?;
+            return Err(ControlError::LimitTripped(self.id, input));
         }
         Ok(())
     }
+
+    pub async fn wait_for_move_complete(&self) -> Result<()> {
+        self.stop_if_cancelled(self.wait_for_move_polling(self.poll))
+            .await
+    }
+
+    //Moves, waits for the move to finish and then holds for `settle` so whatever the motor carries
+    //has stopped swinging before the next step. Cancelled before the move is done, the motor is
+    //stopped.
+    pub async fn move_and_settle(&self, position: f64, settle: Duration) -> Result<()> {
+        self.stop_if_cancelled(async {
+            self.move_absolute(position).await?;
+            self.wait_for_move_polling(self.poll).await
+        })
+        .await?;
+        self.dwell(settle).await
+    }
+
+    //Moves, waits for the move to finish and returns the position the drive ended up at. A fault
+    //along the way comes back as the error instead of wherever the motor happened to stop.
+    //Cancelled before the move is done, the motor is stopped.
+    pub async fn move_absolute_blocking(&self, position: f64) -> Result<f64> {
+        self.stop_if_cancelled(async {
+            self.move_absolute(position).await?;
+            self.wait_for_move_polling(self.poll).await?;
+            self.get_position().await
+        })
+        .await
+    }
+
+    //Moves to `position` and reports how far along the move is, from 0.0 at the position it
+    //started from to 1.0 at the target, every poll interval while it runs and a final 1.0 once
+    //the drive reports it done. The stream ends after that or after its first error, a fault or
+    //a tripped limit switch included. Dropping it only stops the reports, the move carries on.
+    pub fn move_with_progress(&self, position: f64) -> impl Stream<Item = Result<f64>> {
+        let poll = PollConfig::fixed(self.poll.interval);
+        //Where the move started from once it has been sent
+        let state: (Self, Option<(f64, Poller)>, bool) = (self.clone(), None, false);
+        stream::unfold(state, move |(motor, moving, done)| async move {
+            if done {
+                return None;
+            }
+            let (start, mut poller) = match moving {
+                Some(moving) => moving,
+                None => {
+                    let started = async {
+                        let start = motor.get_position().await?;
+                        motor.move_absolute(position).await?;
+                        Ok::<_, ControlError>(start)
+                    };
+                    match started.await {
+                        Ok(start) => (start, poll.poller()),
+                        Err(e) => return Some((Err(e), (motor, None, true))),
+                    }
+                }
+            };
+            poller.tick().await;
+            match motor.move_progress(start, position).await {
+                Ok(Some(progress)) => Some((Ok(progress), (motor, Some((start, poller)), false))),
+                Ok(None) => Some((Ok(1.), (motor, None, true))),
+                Err(e) => Some((Err(e), (motor, None, true))),
+            }
+        })
+    }
+
+    //None once the move is done
+    async fn move_progress(&self, start: f64, target: f64) -> Result<Option<f64>> {
+        let status = self.get_status().await?;
+        if status.faulted {
+            return Err(ControlError::MotorFault(self.id));
+        }
+        match status.state {
+            Status::Ready => return Ok(None),
+            Status::Moving => self.stop_at_tripped_limit().await?,
+            Status::Enabling => {}
+            Status::Disabled => return Err(ControlError::NotEnabled(self.id)),
+            Status::Faulted | Status::Unknown => return Err(ControlError::MotorFault(self.id)),
+        }
+        let travel = target - start;
+        if travel == 0. {
+            return Ok(Some(0.));
+        }
+        let current = self.get_position().await?;
+        Ok(Some(((current - start) / travel).clamp(0., 1.)))
+    }
+
+    //A wait dropped before it's done, e.g. by an aborted recipe or a tokio::select! branch that
+    //lost, would otherwise leave the motor running with nobody watching it. Instead a decelerating
+    //stop goes out from a spawned task, so the motor comes to rest shortly after the future is
+    //dropped rather than the instant it goes. Whatever `future` returns, errors included, disarms
+    //the stop.
+    async fn stop_if_cancelled<T>(&self, future: impl Future<Output = T>) -> T {
+        let guard = StopOnDrop {
+            motor: self,
+            armed: true,
+        };
+        let output = future.await;
+        guard.disarm();
+        output
+    }
+
+    //Brings a faulted motor back: stops it, clears the alerts, cycles the enable and waits for
+    //ready, then homes or moves as `strategy` says. Each step is checked before the next one and
+    //the first to fail comes back as RecoveryFailed naming it, with the motor left where that
+    //step got it.
+    pub async fn recover(&self, strategy: RecoveryStrategy) -> Result<()> {
+        let step = |name: &'static str| {
+            let id = self.id;
+            move |e: ControlError| {
+                error!("Motor {id} recovery failed while {name}: {e}");
+                ControlError::RecoveryFailed(id, name, Box::new(e))
+            }
+        };
+        warn!("Recovering motor {} with {strategy:?}", self.id);
+        self.abrupt_stop().await.map_err(step("stopping"))?;
+        self.clear_fault()
+            .await
+            .map_err(step("clearing the fault"))?;
+        self.disable().await.map_err(step("disabling"))?;
+        self.enable_and_wait(self.enable_timeout)
+            .await
+            .map_err(step("re-enabling"))?;
+        match strategy {
+            RecoveryStrategy::ClearAndReenable => {}
+            RecoveryStrategy::ClearReenableRehome => self.home().await.map_err(step("homing"))?,
+            RecoveryStrategy::ClearReenableMoveTo(position) => {
+                self.move_absolute_blocking(position)
+                    .await
+                    .map_err(step("moving to the safe position"))?;
+            }
+        }
+        Ok(())
+    }
+
+    //Runs absolute moves to `moves` one after the other on a spawned task, each waited out before
+    //the next goes, so the caller doesn't have to await in between. Queueing while the queue is
+    //still running appends to it. Every target is checked against the soft limits up front and
+    //nothing is queued if one is rejected. The first move to fail drops the rest, see
+    //queue_progress for how far it got.
+    pub fn queue_moves(&self, moves: &[f64]) -> Result<()> {
+        for &position in moves {
+            self.limit_target(position)?;
+        }
+        let mut state = self.queue.state.lock().unwrap();
+        state.pending.extend(moves);
+        let idle = state.runner.is_none();
+        self.queue.progress.send_modify(|progress| {
+            if idle {
+                *progress = QueueProgress::default();
+            }
+            progress.pending = state.pending.len();
+        });
+        if idle && !state.pending.is_empty() {
+            state.runner = Some(tokio::spawn(self.clone().run_queue()));
+        }
+        Ok(())
+    }
+
+    //Drops every queued move that hasn't started and stops the motor, whether or not a queue
+    //was running
+    pub async fn cancel_queue(&self) -> Result<()> {
+        let runner = {
+            let mut state = self.queue.state.lock().unwrap();
+            state.pending.clear();
+            state.runner.take()
+        };
+        if let Some(runner) = runner {
+            runner.abort();
+            let _ = runner.await;
+            warn!("Motor {} move queue cancelled", self.id);
+        }
+        self.queue.progress.send_modify(|progress| {
+            progress.pending = 0;
+            progress.current = None;
+        });
+        self.stop().await
+    }
+
+    //Follows the move queue, the receiver sees every change from here on
+    pub fn queue_progress(&self) -> watch::Receiver<QueueProgress> {
+        self.queue.progress.subscribe()
+    }
+
+    async fn run_queue(self) {
+        loop {
+            let position = {
+                let mut state = self.queue.state.lock().unwrap();
+                let Some(position) = state.pending.pop_front() else {
+                    state.runner = None;
+                    self.queue
+                        .progress
+                        .send_modify(|progress| progress.current = None);
+                    return;
+                };
+                self.queue.progress.send_modify(|progress| {
+                    progress.pending = state.pending.len();
+                    progress.current = Some(position);
+                });
+                position
+            };
+            //Unguarded, cancel_queue stops the motor itself once the runner is gone
+            let moved = async {
+                self.move_absolute(position).await?;
+                self.wait_for_move_polling(self.poll).await
+            };
+            match moved.await {
+                Ok(()) => self
+                    .queue
+                    .progress
+                    .send_modify(|progress| progress.completed += 1),
+                Err(e) => {
+                    error!("Motor {} queued move to {position} failed: {e}", self.id);
+                    let mut state = self.queue.state.lock().unwrap();
+                    state.pending.clear();
+                    state.runner = None;
+                    self.queue.progress.send_modify(|progress| {
+                        progress.pending = 0;
+                        progress.current = None;
+                        progress.error = Some(e.to_string());
+                    });
+                    return;
+                }
+            }
+        }
+    }
+}
+
+struct StopOnDrop<'a> {
+    motor: &'a ClearCoreMotor,
+    armed: bool,
+}
+
+impl StopOnDrop<'_> {
+    fn disarm(mut self) {
+        self.armed = false;
+    }
+}
+
+impl Drop for StopOnDrop<'_> {
+    fn drop(&mut self) {
+        if !self.armed {
+            return;
+        }
+        let motor = self.motor.clone();
+        match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => {
+                warn!("Motor {} wait was cancelled, stopping it", motor.id);
+                runtime.spawn(async move {
+                    if let Err(e) = motor.stop().await {
+                        error!(
+                            "Motor {} failed to stop after a cancelled wait: {e}",
+                            motor.id
+                        );
+                    }
+                });
+            }
+            Err(_) => error!(
+                "Motor {} wait was cancelled outside the runtime and it couldn't be stopped",
+                motor.id
+            ),
+        }
+    }
+}
+
+fn check_move_register(index: u8) -> Result<()> {
+    if index >= MOVE_REGISTERS {
+        return Err(ControlError::InvalidArgument(format!(
+            "move register {index} doesn't exist, the drive has {MOVE_REGISTERS}"
+        )));
+    }
+    Ok(())
 }
 
 impl SendRecv for ClearCoreMotor {
@@ -206,6 +1518,660 @@ impl SendRecv for ClearCoreMotor {
     }
 }
 
+#[tokio::test]
+async fn test_move_and_settle() {
+    let (tx, mut rx) = tokio::sync::mpsc::channel::<Message>(10);
+    let motor = ClearCoreMotor::new(0, 800, tx).with_poll_interval(Duration::from_millis(1));
+    let mock = tokio::spawn(async move {
+        let replies: [(&[u8], &[u8]); 3] = [
+            (b"\x02M0GS\r", b"\x02M03232\r"),
+            (b"\x02M0AM800\r", b"\x02M0_\r"),
+            (b"\x02M0GS\r", b"\x02M03233\r"),
+        ];
+        for (expected, reply) in replies {
+            let msg = rx.recv().await.unwrap();
+            assert_eq!(msg.buffer, expected);
+            msg.response.send(Ok(reply.to_vec())).unwrap();
+        }
+        rx
+    });
+    let start = tokio::time::Instant::now();
+    motor
+        .move_and_settle(1.0, Duration::from_millis(20))
+        .await
+        .unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(20));
+
+    //With the client gone a long dwell ends straight away
+    drop(mock.await.unwrap());
+    assert!(matches!(
+        motor.dwell(Duration::from_secs(60)).await,
+        Err(ControlError::Disconnected)
+    ));
+}
+
+#[tokio::test]
+async fn test_move_absolute_blocking() {
+    let (tx, mut rx) = tokio::sync::mpsc::channel::<Message>(10);
+    let motor = ClearCoreMotor::new(0, 800, tx).with_poll_interval(Duration::from_millis(1));
+    let mock = tokio::spawn(async move {
+        let replies: [(&[u8], &[u8]); 8] = [
+            (b"\x02M0GS\r", b"\x02M03233\r"),
+            (b"\x02M0AM800\r", b"\x02M0_\r"),
+            (b"\x02M0GS\r", b"\x02M04130\r"),
+            (b"\x02M0GS\r", b"\x02M03233\r"),
+            (b"\x02M0GP\r", b"\x02M0798\r"),
+            //The second move faults on the way
+            (b"\x02M0GS\r", b"\x02M03233\r"),
+            (b"\x02M0AM1600\r", b"\x02M0_\r"),
+            (b"\x02M0GS\r", b"\x02M02064\r"),
+        ];
+        for (expected, reply) in replies {
+            let msg = rx.recv().await.unwrap();
+            assert_eq!(msg.buffer, expected);
+            msg.response.send(Ok(reply.to_vec())).unwrap();
+        }
+        rx
+    });
+    assert_eq!(
+        motor.move_absolute_blocking(1.0).await.unwrap(),
+        798. / 800.
+    );
+    assert!(matches!(
+        motor.move_absolute_blocking(2.0).await,
+        Err(ControlError::MotorFault(0))
+    ));
+    let mut rx = mock.await.unwrap();
+    assert!(rx.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn test_wait_polls_with_backoff() {
+    use tokio::time::Instant;
+
+    let (tx, mut rx) = tokio::sync::mpsc::channel::<Message>(10);
+    let motor = ClearCoreMotor::new(0, 800, tx).with_poll_config(PollConfig {
+        interval: Duration::from_millis(20),
+        max_interval: Duration::from_millis(40),
+        backoff: 2.,
+    });
+    let mock = tokio::spawn(async move {
+        let mut reads = Vec::new();
+        for status in [&b"4130"[..], b"4130", b"4130", b"4130", b"3233"] {
+            let msg = rx.recv().await.unwrap();
+            reads.push(Instant::now());
+            let mut reply = b"\x02M0".to_vec();
+            reply.extend_from_slice(status);
+            reply.push(13);
+            msg.response.send(Ok(reply)).unwrap();
+        }
+        reads
+    });
+    motor.wait_for_move_complete().await.unwrap();
+    let reads = mock.await.unwrap();
+    let gaps: Vec<Duration> = reads.windows(2).map(|pair| pair[1] - pair[0]).collect();
+    for (gap, expected) in gaps.iter().zip([20, 40, 40, 40]) {
+        assert!(*gap >= Duration::from_millis(expected), "{gaps:?}");
+    }
+    //Capped at the max rather than doubling again
+    assert!(gaps[3] < Duration::from_millis(70), "{gaps:?}");
+}
+
+#[tokio::test]
+async fn test_enable_timeout() {
+    let (tx, mut rx) = tokio::sync::mpsc::channel::<Message>(10);
+    let motor = ClearCoreMotor::new(2, 800, tx).with_enable_timeout(Duration::from_millis(20));
+    //The command is taken but never answered, like a mock that forgot to reply
+    let mock = tokio::spawn(async move {
+        let msg = rx.recv().await.unwrap();
+        assert_eq!(msg.buffer, b"\x02M2EN\r");
+        let _held = msg;
+        while rx.recv().await.is_some() {}
+    });
+    let Err(e) = motor.enable().await else {
+        panic!("enable should give up when nothing answers");
+    };
+    assert!(matches!(e, ControlError::EnableUnanswered(2, _)));
+    assert!(e.to_string().contains("Motor 2"));
+    drop(motor);
+    mock.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_enable_and_wait() {
+    let (tx, mut rx) = tokio::sync::mpsc::channel::<Message>(10);
+    let motor = ClearCoreMotor::new(0, 800, tx).with_poll_interval(Duration::from_millis(5));
+    let mock = tokio::spawn(async move {
+        let msg = rx.recv().await.unwrap();
+        assert_eq!(msg.buffer, b"\x02M0EN\r");
+        msg.response.send(Ok(b"\x02M0_\r".to_vec())).unwrap();
+        //Enabling, then ready with HLFB asserted
+        let msg = rx.recv().await.unwrap();
+        msg.response.send(Ok(b"\x02M01024\r".to_vec())).unwrap();
+        let msg = rx.recv().await.unwrap();
+        msg.response.send(Ok(b"\x02M03232\r".to_vec())).unwrap();
+
+        //The second enable lands on a faulted drive that never comes ready
+        let msg = rx.recv().await.unwrap();
+        msg.response.send(Ok(b"\x02M0_\r".to_vec())).unwrap();
+        while let Some(msg) = rx.recv().await {
+            let _ = msg.response.send(Ok(b"\x02M02064\r".to_vec()));
+        }
+    });
+    motor.enable_and_wait(Duration::from_secs(1)).await.unwrap();
+    assert!(matches!(
+        motor.enable_and_wait(Duration::from_millis(50)).await,
+        Err(ControlError::EnableTimeout(0))
+    ));
+    drop(motor);
+    mock.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_move_absolute() {
+    let (tx, mut rx) = tokio::sync::mpsc::channel::<Message>(10);
+    let motor = ClearCoreMotor::new(0, 800, tx);
+    let mock = tokio::spawn(async move {
+        let status = rx.recv().await.unwrap();
+        assert_eq!(status.buffer, [2, b'M', b'0', b'G', b'S', 13]);
+        status.response.send(Ok(b"\x02M03233\r".to_vec())).unwrap();
+        let msg = rx.recv().await.unwrap();
+        assert_eq!(msg.buffer, b"\x02M0AM12000\r");
+        msg.response
+            .send(Ok(vec![2, b'M', b'0', b'_', 13]))
+            .unwrap();
+
+        let status = rx.recv().await.unwrap();
+        status
+            .response
+            .send(Ok(vec![2, b'M', b'0', b'0', 13]))
+            .unwrap();
+    });
+    motor.move_absolute(15.0).await.unwrap();
+    assert!(matches!(
+        motor.move_absolute(15.0).await,
+        Err(ControlError::NotEnabled(0))
+    ));
+    mock.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_move_relative() {
+    let (tx, mut rx) = tokio::sync::mpsc::channel::<Message>(10);
+    let motor = ClearCoreMotor::new(1, 800, tx);
+    let mock = tokio::spawn(async move {
+        let msg = rx.recv().await.unwrap();
+        assert_eq!(msg.buffer, b"\x02M1RM-400\r");
+        msg.response
+            .send(Ok(vec![2, b'M', b'1', b'_', 13]))
+            .unwrap();
+        //A zero delta never reaches the wire
+        assert!(rx.recv().await.is_none());
+    });
+    motor.move_relative(-0.5).await.unwrap();
+    motor.move_relative(0.0).await.unwrap();
+    drop(motor);
+    mock.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_soft_limits() {
+    let (tx, mut rx) = tokio::sync::mpsc::channel::<Message>(10);
+    let limits = SoftLimits {
+        min_position: Some(0.0),
+        max_position: Some(10.0),
+        mode: LimitMode::Reject,
+    };
+    let motor = ClearCoreMotor::new(0, 800, tx.clone()).with_soft_limits(limits);
+    let clamped = ClearCoreMotor::new(1, 800, tx).with_soft_limits(SoftLimits {
+        mode: LimitMode::Clamp,
+        ..limits
+    });
+    let mock = tokio::spawn(async move {
+        //Rejected absolute move only checks the status
+        let msg = rx.recv().await.unwrap();
+        msg.response.send(Ok(b"\x02M03233\r".to_vec())).unwrap();
+        //Rejected relative move only reads the position
+        let msg = rx.recv().await.unwrap();
+        assert_eq!(msg.buffer, b"\x02M0GP\r");
+        msg.response.send(Ok(b"\x02M07200\r".to_vec())).unwrap();
+
+        let msg = rx.recv().await.unwrap();
+        msg.response.send(Ok(b"\x02M13233\r".to_vec())).unwrap();
+        let msg = rx.recv().await.unwrap();
+        assert_eq!(msg.buffer, b"\x02M1AM0\r");
+        msg.response.send(Ok(b"\x02M1_\r".to_vec())).unwrap();
+        let msg = rx.recv().await.unwrap();
+        msg.response.send(Ok(b"\x02M17200\r".to_vec())).unwrap();
+        let msg = rx.recv().await.unwrap();
+        assert_eq!(msg.buffer, b"\x02M1RM800\r");
+        msg.response.send(Ok(b"\x02M1_\r".to_vec())).unwrap();
+    });
+    assert!(matches!(
+        motor.move_absolute(-1.0).await,
+        Err(ControlError::OutOfBounds(0, _))
+    ));
+    assert!(matches!(
+        motor.move_relative(2.0).await,
+        Err(ControlError::OutOfBounds(0, _))
+    ));
+    clamped.move_absolute(-1.0).await.unwrap();
+    clamped.move_relative(2.0).await.unwrap();
+    mock.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_jog() {
+    let (tx, mut rx) = tokio::sync::mpsc::channel::<Message>(10);
+    let motor = ClearCoreMotor::new(0, 800, tx);
+    let mock = tokio::spawn(async move {
+        for expected in [&b"\x02M0JG-1600\r"[..], b"\x02M0JG800\r"] {
+            let status = rx.recv().await.unwrap();
+            status.response.send(Ok(b"\x02M03233\r".to_vec())).unwrap();
+            let msg = rx.recv().await.unwrap();
+            assert_eq!(msg.buffer, expected);
+            msg.response.send(Ok(b"\x02M0_\r".to_vec())).unwrap();
+        }
+        let msg = rx.recv().await.unwrap();
+        assert_eq!(msg.buffer, b"\x02M0ST\r");
+        msg.response.send(Ok(b"\x02M0_\r".to_vec())).unwrap();
+    });
+    motor.jog_start(2.0, Direction::Negative).await.unwrap();
+    //Reversing mid-jog goes straight out as the new velocity
+    motor.jog_start(-1.0, Direction::Positive).await.unwrap();
+    motor.jog_stop().await.unwrap();
+    mock.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_rejected_command() {
+    let (tx, mut rx) = tokio::sync::mpsc::channel::<Message>(10);
+    let motor = ClearCoreMotor::new(1, 800, tx);
+    let mock = tokio::spawn(async move {
+        let msg = rx.recv().await.unwrap();
+        msg.response
+            .send(Ok(vec![2, b'M', b'1', b'?', 13]))
+            .unwrap();
+    });
+    assert!(matches!(
+        motor.move_relative(2.0).await,
+        Err(ControlError::CommandRejected(_))
+    ));
+    mock.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_get_position() {
+    let (tx, mut rx) = tokio::sync::mpsc::channel::<Message>(10);
+    let motor = ClearCoreMotor::new(2, 800, tx);
+    let mock = tokio::spawn(async move {
+        let msg = rx.recv().await.unwrap();
+        assert_eq!(msg.buffer, [2, b'M', b'2', b'G', b'P', 13]);
+        msg.response.send(Ok(b"\x02M2-12000\r".to_vec())).unwrap();
+        let msg = rx.recv().await.unwrap();
+        msg.response.send(Ok(vec![2, b'M', b'2', 13])).unwrap();
+    });
+    assert_eq!(motor.get_position().await.unwrap(), -15.0);
+    assert!(matches!(
+        motor.get_position().await,
+        Err(ControlError::BadResponse(_))
+    ));
+    mock.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_limits_applied_on_enable() {
+    let (tx, mut rx) = tokio::sync::mpsc::channel::<Message>(10);
+    let motor = ClearCoreMotor::new(0, 800, tx)
+        .with_velocity_limit(2.5)
+        .with_acceleration(40.0)
+        .with_deceleration(10.0);
+    let mock = tokio::spawn(async move {
+        for expected in [
+            b"\x02M0EN\r".to_vec(),
+            b"\x02M0SV2000\r".to_vec(),
+            b"\x02M0SA32000\r".to_vec(),
+            b"\x02M0SD8000\r".to_vec(),
+        ] {
+            let msg = rx.recv().await.unwrap();
+            assert_eq!(msg.buffer, expected);
+            msg.response
+                .send(Ok(vec![2, b'M', b'0', b'_', 13]))
+                .unwrap();
+        }
+        //Firmware with a single ramp turns SD down
+        let msg = rx.recv().await.unwrap();
+        assert_eq!(msg.buffer, b"\x02M0SD4000\r");
+        msg.response.send(Ok(b"\x02M0?\r".to_vec())).unwrap();
+        //Rejected values never reach the wire
+        assert!(rx.recv().await.is_none());
+    });
+    motor.enable().await.unwrap();
+    assert!(matches!(
+        motor.set_velocity_limit(-1.0).await,
+        Err(ControlError::InvalidArgument(_))
+    ));
+    assert!(matches!(
+        motor.set_acceleration(-40.0).await,
+        Err(ControlError::InvalidArgument(_))
+    ));
+    motor.set_deceleration(5.0).await.unwrap();
+    assert!(matches!(
+        motor.set_deceleration(-5.0).await,
+        Err(ControlError::InvalidArgument(_))
+    ));
+    drop(motor);
+    mock.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_torque_limit() {
+    let (tx, mut rx) = tokio::sync::mpsc::channel::<Message>(10);
+    let motor = ClearCoreMotor::new(3, 800, tx);
+    let mock = tokio::spawn(async move {
+        let msg = rx.recv().await.unwrap();
+        assert_eq!(msg.buffer, b"\x02M3TL50\r");
+        msg.response
+            .send(Ok(vec![2, b'M', b'3', b'_', 13]))
+            .unwrap();
+        let msg = rx.recv().await.unwrap();
+        assert_eq!(msg.buffer, [2, b'M', b'3', b'G', b'T', 13]);
+        msg.response.send(Ok(b"\x02M3-42\r".to_vec())).unwrap();
+        assert!(rx.recv().await.is_none());
+    });
+    motor.set_torque_limit(50).await.unwrap();
+    assert_eq!(motor.get_torque().await.unwrap(), -42.0);
+    assert!(matches!(
+        motor.set_torque_limit(101).await,
+        Err(ControlError::InvalidArgument(_))
+    ));
+    drop(motor);
+    mock.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_clear_fault() {
+    let (tx, mut rx) = tokio::sync::mpsc::channel::<Message>(10);
+    let motor = ClearCoreMotor::new(1, 800, tx);
+    let mock = tokio::spawn(async move {
+        //First clear works, the second leaves the fault bit set
+        for status in [b"\x02M13233\r".to_vec(), b"\x02M12096\r".to_vec()] {
+            let msg = rx.recv().await.unwrap();
+            assert_eq!(msg.buffer, [2, b'M', b'1', b'C', b'A', 13]);
+            msg.response
+                .send(Ok(vec![2, b'M', b'1', b'_', 13]))
+                .unwrap();
+            let msg = rx.recv().await.unwrap();
+            assert_eq!(msg.buffer, [2, b'M', b'1', b'G', b'S', 13]);
+            msg.response.send(Ok(status)).unwrap();
+        }
+    });
+    motor.clear_fault().await.unwrap();
+    assert!(matches!(
+        motor.clear_fault().await,
+        Err(ControlError::StillFaulted(1))
+    ));
+    mock.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_get_status() {
+    let (tx, mut rx) = tokio::sync::mpsc::channel::<Message>(10);
+    let motor = ClearCoreMotor::new(0, 800, tx);
+    let mock = tokio::spawn(async move {
+        //Enabled, moving toward target with HLFB deasserted
+        let msg = rx.recv().await.unwrap();
+        msg.response.send(Ok(b"\x02M04130\r".to_vec())).unwrap();
+    });
+    let status = motor.get_status().await.unwrap();
+    assert_eq!(status.raw, 4130);
+    assert_eq!(status.state, Status::Moving);
+    assert!(status.enabled && status.moving);
+    assert!(!status.at_target && !status.faulted && !status.hlfb_asserted);
+    mock.await.unwrap();
+
+    let ready = MotorStatus::from_bits(3233);
+    assert_eq!(ready.state, Status::Ready);
+    assert!(ready.enabled && ready.at_target && ready.hlfb_asserted);
+}
+
+#[tokio::test]
+async fn test_invert() {
+    let (tx, mut rx) = tokio::sync::mpsc::channel::<Message>(10);
+    let motor = ClearCoreMotor::new(0, 800, tx.clone());
+    let inverted = ClearCoreMotor::new(1, 800, tx).with_invert(true);
+    assert_eq!(motor.move_absolute_frame(1.5).unwrap(), b"\x02M0AM1200\r");
+    assert_eq!(
+        inverted.move_absolute_frame(1.5).unwrap(),
+        b"\x02M1AM-1200\r"
+    );
+
+    let mock = tokio::spawn(async move {
+        let replies: [(&[u8], &[u8]); 3] = [
+            (b"\x02M1JG-400\r", b"\x02M1_\r"),
+            (b"\x02M1RM800\r", b"\x02M1_\r"),
+            (b"\x02M1GP\r", b"\x02M1-1600\r"),
+        ];
+        for (expected, reply) in replies {
+            let msg = rx.recv().await.unwrap();
+            assert_eq!(msg.buffer, expected);
+            msg.response.send(Ok(reply.to_vec())).unwrap();
+        }
+    });
+    inverted.move_velocity(0.5).await.unwrap();
+    inverted.move_relative(-1.0).await.unwrap();
+    assert_eq!(inverted.get_position().await.unwrap(), 2.0);
+    mock.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_position_capture() {
+    let (tx, mut rx) = tokio::sync::mpsc::channel::<Message>(10);
+    let motor = ClearCoreMotor::new(0, 800, tx);
+    let mock = tokio::spawn(async move {
+        let replies: [(&[u8], &[u8]); 3] = [
+            (b"\x02M0CI1\r", b"\x02M0_\r"),
+            (b"\x02M0GC\r", b"\x02M0?\r"),
+            (b"\x02M0GC\r", b"\x02M02000\r"),
+        ];
+        for (expected, reply) in replies {
+            let msg = rx.recv().await.unwrap();
+            assert_eq!(msg.buffer, expected);
+            msg.response.send(Ok(reply.to_vec())).unwrap();
+        }
+        assert!(rx.recv().await.is_none());
+    });
+    assert!(matches!(
+        motor.enable_position_capture(5).await,
+        Err(ControlError::InvalidArgument(_))
+    ));
+    motor.enable_position_capture(1).await.unwrap();
+    //Nothing latched yet
+    assert!(matches!(
+        motor.read_captured_position().await,
+        Err(ControlError::CommandRejected(_))
+    ));
+    assert_eq!(motor.read_captured_position().await.unwrap(), 2.5);
+    drop(motor);
+    mock.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_move_registers() {
+    let (tx, mut rx) = tokio::sync::mpsc::channel::<Message>(10);
+    let motor = ClearCoreMotor::new(0, 800, tx);
+    assert_eq!(
+        motor.move_register_frame(3, -1.5).unwrap(),
+        b"\x02M0RS3-1200\r"
+    );
+    assert_eq!(motor.trigger_move_frame(3).unwrap(), b"\x02M0RT3\r");
+    assert!(matches!(
+        motor.trigger_move_frame(MOVE_REGISTERS),
+        Err(ControlError::InvalidArgument(_))
+    ));
+    let inverted = motor.clone().with_invert(true);
+    assert_eq!(
+        inverted.move_register_frame(0, 1.0).unwrap(),
+        b"\x02M0RS0-800\r"
+    );
+
+    let mock = tokio::spawn(async move {
+        let replies: [(&[u8], &[u8]); 3] = [
+            (b"\x02M0RS12400\r", b"\x02M0_\r"),
+            (b"\x02M0RT1\r", b"\x02M0_\r"),
+            (b"\x02M0RT2\r", b"\x02M0?\r"),
+        ];
+        for (expected, reply) in replies {
+            let msg = rx.recv().await.unwrap();
+            assert_eq!(msg.buffer, expected);
+            msg.response.send(Ok(reply.to_vec())).unwrap();
+        }
+    });
+    motor.set_move_register(1, 3.0).await.unwrap();
+    motor.trigger_move(1).await.unwrap();
+    assert!(matches!(
+        motor.trigger_move(2).await,
+        Err(ControlError::CommandRejected(_))
+    ));
+    mock.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_protocol_map() {
+    let (tx, mut rx) = tokio::sync::mpsc::channel::<Message>(10);
+    let protocol = ProtocolMap {
+        motor: b'X',
+        move_absolute: *b"MA",
+        get_status: *b"ST",
+        stop: *b"HL",
+        ..ProtocolMap::STOCK
+    };
+    let motor = ClearCoreMotor::new(1, 800, tx).with_protocol(protocol);
+    assert_eq!(motor.move_absolute_frame(1.0).unwrap(), b"\x02X1MA800\r");
+    assert_eq!(motor.abrupt_stop_frame(), b"\x02X1AS\r");
+    let mock = tokio::spawn(async move {
+        let replies: [(&[u8], &[u8]); 3] = [
+            (b"\x02X1ST\r", b"\x02X13233\r"),
+            (b"\x02X1MA1600\r", b"\x02X1_\r"),
+            (b"\x02X1HL\r", b"\x02X1_\r"),
+        ];
+        for (expected, reply) in replies {
+            let msg = rx.recv().await.unwrap();
+            assert_eq!(msg.buffer, expected);
+            msg.response.send(Ok(reply.to_vec())).unwrap();
+        }
+    });
+    motor.move_absolute(2.0).await.unwrap();
+    motor.stop().await.unwrap();
+    mock.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_idempotent_by_field() {
+    let (tx, mut rx) = tokio::sync::mpsc::channel::<Message>(10);
+    //Swapped letters must not swap which of the moves the client may resend
+    let protocol = ProtocolMap {
+        move_absolute: *b"RM",
+        move_relative: *b"AM",
+        ..ProtocolMap::STOCK
+    };
+    let motor = ClearCoreMotor::new(0, 800, tx).with_protocol(protocol);
+    let sent = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let log = sent.clone();
+    tokio::spawn(async move {
+        while let Some(msg) = rx.recv().await {
+            let reply: &[u8] = if msg.buffer == b"\x02M0GS\r" {
+                b"\x02M03233\r"
+            } else {
+                b"\x02M0_\r"
+            };
+            log.lock().unwrap().push((msg.buffer, msg.idempotent));
+            msg.response.send(Ok(reply.to_vec())).unwrap();
+        }
+    });
+    motor.move_absolute(1.0).await.unwrap();
+    motor.move_relative(1.0).await.unwrap();
+    let sent = sent.lock().unwrap();
+    assert!(sent.contains(&(b"\x02M0GS\r".to_vec(), true)));
+    assert!(sent.contains(&(b"\x02M0RM800\r".to_vec(), true)));
+    assert!(sent.contains(&(b"\x02M0AM800\r".to_vec(), false)));
+}
+
+#[tokio::test]
+async fn test_set_scale() {
+    let (tx, mut rx) = tokio::sync::mpsc::channel::<Message>(10);
+    let motor = ClearCoreMotor::new(0, 800, tx);
+    let shared = motor.clone();
+    assert_eq!(motor.move_absolute_frame(1.0).unwrap(), b"\x02M0AM800\r");
+    shared.set_scale(200).unwrap();
+    assert_eq!(motor.scale(), 200);
+    assert_eq!(motor.move_absolute_frame(1.0).unwrap(), b"\x02M0AM200\r");
+    assert!(matches!(
+        motor.set_scale(0),
+        Err(ControlError::InvalidArgument(_))
+    ));
+    let mock = tokio::spawn(async move {
+        let msg = rx.recv().await.unwrap();
+        msg.response.send(Ok(b"\x02M0800\r".to_vec())).unwrap();
+    });
+    assert_eq!(motor.get_position().await.unwrap(), 4.0);
+    mock.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_get_velocity() {
+    let (tx, mut rx) = tokio::sync::mpsc::channel::<Message>(10);
+    let motor = ClearCoreMotor::new(0, 800, tx);
+    let mock = tokio::spawn(async move {
+        for reply in [&b"\x02M01200\r"[..], b"\x02M0-400\r", b"\x02M0?\r"] {
+            let msg = rx.recv().await.unwrap();
+            assert_eq!(msg.buffer, b"\x02M0GV\r");
+            msg.response.send(Ok(reply.to_vec())).unwrap();
+        }
+    });
+    assert_eq!(motor.get_velocity().await.unwrap(), 1.5);
+    assert_eq!(motor.get_velocity().await.unwrap(), -0.5);
+    assert!(matches!(
+        motor.get_velocity().await,
+        Err(ControlError::BadResponse(_))
+    ));
+    mock.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_following_error() {
+    let (tx, mut rx) = tokio::sync::mpsc::channel::<Message>(10);
+    let motor = ClearCoreMotor::new(0, 800, tx);
+    let inverted = motor.clone().with_invert(true);
+    let mock = tokio::spawn(async move {
+        let replies: [(&[u8], &[u8]); 7] = [
+            (b"\x02M0GE\r", b"\x02M024\r"),
+            (b"\x02M0GE\r", b"\x02M0-40\r"),
+            (b"\x02M0GE\r", b"\x02M0-40\r"),
+            (b"\x02M0GS\r", b"\x02M04130\r"),
+            (b"\x02M0GE\r", b"\x02M016\r"),
+            (b"\x02M0GS\r", b"\x02M04130\r"),
+            (b"\x02M0GE\r", b"\x02M0?\r"),
+        ];
+        for (expected, reply) in replies {
+            let msg = rx.recv().await.unwrap();
+            assert_eq!(msg.buffer, expected);
+            msg.response.send(Ok(reply.to_vec())).unwrap();
+        }
+    });
+    assert_eq!(motor.get_following_error().await.unwrap(), 0.03);
+    assert_eq!(motor.get_following_error().await.unwrap(), -0.05);
+    assert_eq!(inverted.get_following_error().await.unwrap(), 0.05);
+    let status = motor.get_status_with_following_error().await.unwrap();
+    assert!(status.moving);
+    assert_eq!(status.following_error, Some(0.02));
+    assert!(matches!(
+        motor.get_status_with_following_error().await,
+        Err(ControlError::BadResponse(_))
+    ));
+    mock.await.unwrap();
+}
+
 //
 // #[tokio::test]
 // pub async fn test_motor_enable_disable() {
@@ -343,3 +2309,438 @@ impl SendRecv for ClearCoreMotor {
 //     });
 //     let (_, _) = tokio::join!(task, cc1_handler);
 // }
+
+#[tokio::test]
+async fn test_recover() {
+    use crate::testing::MockClearCore;
+    use tokio::sync::mpsc;
+
+    let mock = MockClearCore::start().await.unwrap();
+    mock.on(b"M0GS", b"3233");
+    let (tx, rx) = mpsc::channel::<Message>(10);
+    let handle = tokio::spawn(crate::interface::tcp::client(mock.addr(), rx));
+    let motor = ClearCoreMotor::new(0, 800, tx);
+
+    motor
+        .recover(RecoveryStrategy::ClearReenableRehome)
+        .await
+        .unwrap();
+    let commands: Vec<_> = mock
+        .received()
+        .iter()
+        .map(|frame| frame[3..frame.len() - 1].to_vec())
+        .collect();
+    assert_eq!(
+        commands,
+        [
+            b"AS".to_vec(),
+            b"CA".to_vec(),
+            b"GS".to_vec(),
+            b"DE".to_vec(),
+            b"EN".to_vec(),
+            b"GS".to_vec(),
+            b"GS".to_vec(),
+            b"HM-1".to_vec(),
+            b"GS".to_vec(),
+            b"SP0".to_vec(),
+        ]
+    );
+
+    //A fault that won't clear stops it before the motor is touched again
+    mock.on(b"M0GS", b"2064");
+    let Err(e) = motor.recover(RecoveryStrategy::ClearAndReenable).await else {
+        panic!("recovered a motor that is still faulted");
+    };
+    assert!(matches!(
+        e,
+        ControlError::RecoveryFailed(0, "clearing the fault", ref cause)
+            if matches!(**cause, ControlError::StillFaulted(0))
+    ));
+    assert_eq!(mock.received().last().unwrap(), b"\x02M0GS\r");
+
+    drop(motor);
+    handle.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_cancelled_move_stops() {
+    use crate::testing::MockClearCore;
+    use tokio::sync::mpsc;
+
+    let mock = MockClearCore::start().await.unwrap();
+    mock.on(b"M0GS", b"4130");
+    let (tx, rx) = mpsc::channel::<Message>(10);
+    let handle = tokio::spawn(crate::interface::tcp::client(mock.addr(), rx));
+    let motor = ClearCoreMotor::new(0, 800, tx).with_poll_interval(Duration::from_millis(5));
+
+    let stop = b"\x02M0ST\r".to_vec();
+    let aborted =
+        tokio::time::timeout(Duration::from_millis(30), motor.move_absolute_blocking(1.0)).await;
+    assert!(aborted.is_err());
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    let received = mock.received();
+    assert!(received.contains(&b"\x02M0AM800\r".to_vec()));
+    assert_eq!(received.last(), Some(&stop));
+
+    //A wait that finishes leaves the motor alone
+    mock.on(b"M0GS", b"3233");
+    motor.move_absolute_blocking(2.0).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    let received = mock.received();
+    assert_eq!(received.iter().filter(|frame| **frame == stop).count(), 1);
+
+    drop(motor);
+    handle.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_queue_moves() {
+    use crate::testing::MockClearCore;
+    use tokio::sync::mpsc;
+
+    let mock = MockClearCore::start().await.unwrap();
+    mock.on(b"M0GS", b"3233");
+    let (tx, rx) = mpsc::channel::<Message>(10);
+    let handle = tokio::spawn(crate::interface::tcp::client(mock.addr(), rx));
+    let motor = ClearCoreMotor::new(0, 800, tx)
+        .with_poll_interval(Duration::from_millis(5))
+        .with_soft_limits(SoftLimits {
+            min_position: None,
+            max_position: Some(10.),
+            mode: LimitMode::Reject,
+        });
+
+    let mut progress = motor.queue_progress();
+    motor.queue_moves(&[1.0, 2.0]).unwrap();
+    motor.queue_moves(&[3.0]).unwrap();
+    assert!(matches!(
+        motor.queue_moves(&[4.0, 11.0]),
+        Err(ControlError::OutOfBounds(0, _))
+    ));
+    progress
+        .wait_for(|progress| progress.completed == 3 && progress.current.is_none())
+        .await
+        .unwrap();
+    let moves: Vec<Vec<u8>> = mock
+        .received()
+        .into_iter()
+        .filter(|frame| frame.starts_with(b"\x02M0AM"))
+        .collect();
+    assert_eq!(
+        moves,
+        [
+            b"\x02M0AM800\r".to_vec(),
+            b"\x02M0AM1600\r".to_vec(),
+            b"\x02M0AM2400\r".to_vec(),
+        ]
+    );
+
+    //A move that never finishes holds up the rest until the queue is cancelled
+    mock.on(b"M0GS", b"4130");
+    motor.queue_moves(&[5.0, 6.0]).unwrap();
+    progress
+        .wait_for(|progress| progress.current == Some(5.0))
+        .await
+        .unwrap();
+    assert_eq!(progress.borrow().completed, 0);
+    motor.cancel_queue().await.unwrap();
+    assert_eq!(*progress.borrow(), QueueProgress::default());
+    let received = mock.received();
+    assert_eq!(received.last(), Some(&b"\x02M0ST\r".to_vec()));
+    assert!(!received.contains(&b"\x02M0AM4800\r".to_vec()));
+
+    drop(motor);
+    drop(progress);
+    handle.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_read_hlfb() {
+    use crate::testing::MockClearCore;
+    use tokio::sync::mpsc;
+
+    let mock = MockClearCore::start().await.unwrap();
+    let (tx, rx) = mpsc::channel::<Message>(10);
+    let handle = tokio::spawn(crate::interface::tcp::client(mock.addr(), rx));
+    let motor = ClearCoreMotor::new(0, 800, tx);
+
+    //Ready with HLFB asserted, then deasserted
+    mock.on(b"M0GS", b"3233");
+    assert_eq!(motor.read_hlfb().await.unwrap(), HlfbState::Asserted);
+    mock.on(b"M0GS", b"3105");
+    assert_eq!(motor.read_hlfb().await.unwrap(), HlfbState::Deasserted);
+
+    //Ready with a PWM measurement on the line
+    mock.on(b"M0GS", b"3361").on(b"M0GT", b"-40");
+    assert_eq!(motor.read_hlfb().await.unwrap(), HlfbState::Duty(-40.));
+    let torque = motor.clone().with_hlfb_mode(HlfbMode::Torque);
+    assert_eq!(torque.read_hlfb().await.unwrap(), HlfbState::Torque(-40.));
+    let speed = motor
+        .clone()
+        .with_hlfb_mode(HlfbMode::Speed { full_scale: 10. });
+    assert_eq!(speed.read_hlfb().await.unwrap(), HlfbState::Velocity(4.));
+
+    drop((motor, torque, speed));
+    handle.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_reply_layouts() {
+    use crate::components::response::{ReplyMap, ResponseParser};
+    use crate::testing::MockClearCore;
+    use tokio::sync::mpsc;
+
+    let mock = MockClearCore::start().await.unwrap();
+    mock.on(b"M0GP", b"GP1600").on(b"M0GS", b"3233");
+    let (tx, rx) = mpsc::channel::<Message>(10);
+    let handle = tokio::spawn(crate::interface::tcp::client(mock.addr(), rx));
+    //Only position replies echo the command letters
+    let protocol = ProtocolMap {
+        replies: ReplyMap {
+            position: ResponseParser::ECHOED,
+            ..ReplyMap::STOCK
+        },
+        ..ProtocolMap::STOCK
+    };
+    let motor = ClearCoreMotor::new(0, 800, tx).with_protocol(protocol);
+    assert_eq!(motor.get_position().await.unwrap(), 2.0);
+    assert_eq!(motor.get_status().await.unwrap().raw, 3233);
+    motor.stop().await.unwrap();
+
+    drop(motor);
+    handle.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_limit_switch_trips() {
+    use crate::testing::MockClearCore;
+    use tokio::sync::mpsc;
+
+    let mock = MockClearCore::start().await.unwrap();
+    mock.on(b"M0GS", b"4130")
+        .on(b"M0GP", b"0")
+        .on(b"M0GV", b"800")
+        .on(b"I1", b"0")
+        .on(b"I2", b"1");
+    let (tx, rx) = mpsc::channel::<Message>(10);
+    let handle = tokio::spawn(crate::interface::tcp::client(mock.addr(), rx));
+    let motor = ClearCoreMotor::new(0, 800, tx)
+        .with_poll_interval(Duration::from_millis(5))
+        .with_limit_switches(LimitSwitches {
+            positive: Some(1),
+            negative: Some(2),
+        });
+
+    //Parked on the negative switch, it can only move away from it
+    assert!(matches!(
+        motor.move_relative(-1.0).await,
+        Err(ControlError::LimitTripped(0, 2))
+    ));
+    let trip = async {
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        mock.on(b"I1", b"1");
+    };
+    let (moved, ()) = tokio::join!(motor.move_absolute_blocking(5.0), trip);
+    assert!(matches!(moved, Err(ControlError::LimitTripped(0, 1))));
+    let received = mock.received();
+    assert!(received.contains(&b"\x02M0AM4000\r".to_vec()));
+    assert_eq!(received.last(), Some(&b"\x02M0AS\r".to_vec()));
+    assert!(!received.contains(&b"\x02M0RM-800\r".to_vec()));
+
+    drop(motor);
+    handle.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_jog_limit_watch() {
+    use crate::testing::MockClearCore;
+    use tokio::sync::mpsc;
+
+    let mock = MockClearCore::start().await.unwrap();
+    mock.on(b"M0GS", b"4130").on(b"I1", b"0");
+    let (tx, rx) = mpsc::channel::<Message>(10);
+    let handle = tokio::spawn(crate::interface::tcp::client(mock.addr(), rx));
+    let motor = ClearCoreMotor::new(0, 800, tx)
+        .with_poll_interval(Duration::from_millis(5))
+        .with_limit_switches(LimitSwitches {
+            positive: Some(1),
+            negative: None,
+        });
+
+    motor.jog_start(1.0, Direction::Positive).await.unwrap();
+    let mut trips = motor.limit_trips();
+    assert!(mock.received().contains(&b"\x02M0JG800\r".to_vec()));
+    mock.on(b"I1", b"1");
+    tokio::time::timeout(Duration::from_secs(1), trips.changed())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(*trips.borrow(), Some(1));
+    assert_eq!(mock.received().last(), Some(&b"\x02M0AS\r".to_vec()));
+    //Already against the switch, the next jog toward it is refused before it goes out
+    assert!(matches!(
+        motor.jog_start(1.0, Direction::Positive).await,
+        Err(ControlError::LimitTripped(0, 1))
+    ));
+
+    drop(motor);
+    handle.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_move_with_progress() {
+    use crate::testing::MockClearCore;
+    use futures::StreamExt;
+    use tokio::sync::mpsc;
+
+    let mock = MockClearCore::start().await.unwrap();
+    mock.on(b"M0GS", b"4130").on(b"M0GP", b"0");
+    let (tx, rx) = mpsc::channel::<Message>(10);
+    let handle = tokio::spawn(crate::interface::tcp::client(mock.addr(), rx));
+    let motor = ClearCoreMotor::new(0, 800, tx).with_poll_interval(Duration::from_millis(5));
+
+    let mut progress = Box::pin(motor.move_with_progress(4.0));
+    assert_eq!(progress.next().await.unwrap().unwrap(), 0.);
+    assert!(mock.received().contains(&b"\x02M0AM3200\r".to_vec()));
+    mock.on(b"M0GP", b"1600");
+    assert_eq!(progress.next().await.unwrap().unwrap(), 0.5);
+    //Overshoot is clamped
+    mock.on(b"M0GP", b"4000");
+    assert_eq!(progress.next().await.unwrap().unwrap(), 1.);
+    mock.on(b"M0GS", b"3233");
+    assert_eq!(progress.next().await.unwrap().unwrap(), 1.);
+    assert!(progress.next().await.is_none());
+
+    //A fault ends the stream with it
+    mock.on(b"M0GS", b"2064");
+    let mut faulted = Box::pin(motor.move_with_progress(1.0));
+    assert!(faulted.next().await.unwrap().is_err());
+    assert!(faulted.next().await.is_none());
+
+    drop((motor, progress, faulted));
+    handle.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_last_known_enabled() {
+    use crate::testing::MockClearCore;
+    use tokio::sync::mpsc;
+
+    let mock = MockClearCore::start().await.unwrap();
+    let (tx, rx) = mpsc::channel::<Message>(10);
+    let handle = tokio::spawn(crate::interface::tcp::client(mock.addr(), rx));
+    let motor = ClearCoreMotor::new(0, 800, tx);
+    let clone = motor.clone();
+
+    assert_eq!(motor.last_known_enabled(), None);
+    motor.enable().await.unwrap();
+    assert_eq!(clone.last_known_enabled(), Some(true));
+    let sent = mock.received().len();
+    for _ in 0..10 {
+        assert_eq!(motor.last_known_enabled(), Some(true));
+    }
+    assert_eq!(mock.received().len(), sent);
+
+    //A fault disables the drive behind the cache's back until the next status read
+    mock.on(b"M0GS", b"2064");
+    assert_eq!(motor.last_known_enabled(), Some(true));
+    assert!(!motor.get_status().await.unwrap().enabled);
+    assert_eq!(motor.last_known_enabled(), Some(false));
+
+    motor.enable().await.unwrap();
+    mock.on(b"M0DE", b"?");
+    assert!(motor.disable().await.is_err());
+    assert_eq!(motor.last_known_enabled(), Some(true));
+
+    drop((motor, clone));
+    handle.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_home_hard_stop() {
+    use crate::testing::MockClearCore;
+    use tokio::sync::mpsc;
+
+    let mock = MockClearCore::start().await.unwrap();
+    mock.on(b"M0GS", b"4130").on(b"M0GT", b"-5");
+    let (tx, rx) = mpsc::channel::<Message>(10);
+    let handle = tokio::spawn(crate::interface::tcp::client(mock.addr(), rx));
+    let motor = ClearCoreMotor::new(0, 800, tx)
+        .with_poll_interval(Duration::from_millis(5))
+        .with_homing(HomingConfig {
+            mode: HomeMode::HardStop {
+                torque_percent: 30.,
+                backoff: 0.5,
+                velocity: 2.,
+            },
+            ..Default::default()
+        });
+
+    //The torque climbs as the axis runs into the stop
+    let stop = async {
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        mock.on(b"M0GT", b"-20");
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!mock.received().contains(&b"\x02M0AS\r".to_vec()));
+        mock.on(b"M0GT", b"-28").on(b"M0GS", b"3233");
+    };
+    //Whatever limit was set before homing is the one put back
+    motor.set_torque_limit(80).await.unwrap();
+    let (homed, ()) = tokio::join!(motor.home(), stop);
+    homed.unwrap();
+    let commands: Vec<Vec<u8>> = mock
+        .received()
+        .into_iter()
+        .filter(|frame| !frame.starts_with(b"\x02M0G"))
+        .collect();
+    assert_eq!(
+        commands,
+        [
+            b"\x02M0TL80\r".to_vec(),
+            b"\x02M0TL30\r".to_vec(),
+            b"\x02M0JG-1600\r".to_vec(),
+            b"\x02M0AS\r".to_vec(),
+            b"\x02M0SP0\r".to_vec(),
+            b"\x02M0TL80\r".to_vec(),
+            b"\x02M0RM400\r".to_vec(),
+        ]
+    );
+
+    drop(motor);
+    handle.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_home_holds_sequence() {
+    use crate::testing::MockClearCore;
+    use tokio::sync::mpsc;
+
+    let mock = MockClearCore::start().await.unwrap();
+    mock.on(b"M0GS", b"4130");
+    let (tx, rx) = mpsc::channel::<Message>(10);
+    let handle = tokio::spawn(crate::interface::tcp::client(mock.addr(), rx));
+    let motor = ClearCoreMotor::new(0, 800, tx).with_poll_interval(Duration::from_millis(5));
+
+    let homing = tokio::spawn({
+        let motor = motor.clone();
+        async move { motor.home().await }
+    });
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    //A move from another task waits for homing to finish
+    let moving = tokio::spawn({
+        let motor = motor.clone();
+        async move { motor.move_absolute(1.0).await }
+    });
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    assert!(!mock.received().contains(&b"\x02M0AM800\r".to_vec()));
+    mock.on(b"M0GS", b"3233");
+    homing.await.unwrap().unwrap();
+    moving.await.unwrap().unwrap();
+    let received = mock.received();
+    let zeroed = received.iter().position(|frame| frame == b"\x02M0SP0\r");
+    let moved = received.iter().position(|frame| frame == b"\x02M0AM800\r");
+    assert!(zeroed.unwrap() < moved.unwrap());
+
+    drop(motor);
+    handle.await.unwrap().unwrap();
+}
@@ -4,6 +4,8 @@ pub mod clear_core_io;
 pub mod clear_core_motor;
 mod led;
 pub mod load_cell;
+pub mod protocol;
+pub mod response;
 pub mod scale;
 pub mod send_recv;
 
@@ -0,0 +1,76 @@
+use crate::components::response::ReplyMap;
+use crate::util::number::NumberFormat;
+
+//The bytes ClearCoreMotor builds its frames from, for firmware sketches that name their motor
+//commands differently from the stock one. Every frame is STX, `motor`, the motor id digit, one of
+//the two letter commands, an optional value and CR, so only the letters can be remapped. Whether
+//a command is resent after a transient failure goes by which field it is, never by its letters.
+//`replies` says where each command's reply carries its payload and `values` how the numbers in
+//commands are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProtocolMap {
+    pub motor: u8,
+    pub enable: [u8; 2],
+    pub disable: [u8; 2],
+    pub move_absolute: [u8; 2],
+    pub move_relative: [u8; 2],
+    pub jog: [u8; 2],
+    pub stop: [u8; 2],
+    pub abrupt_stop: [u8; 2],
+    pub set_position: [u8; 2],
+    pub velocity_limit: [u8; 2],
+    pub acceleration: [u8; 2],
+    pub deceleration: [u8; 2],
+    pub torque_limit: [u8; 2],
+    pub home: [u8; 2],
+    pub clear_alerts: [u8; 2],
+    pub get_status: [u8; 2],
+    pub get_position: [u8; 2],
+    pub get_velocity: [u8; 2],
+    pub get_torque: [u8; 2],
+    pub get_following_error: [u8; 2],
+    pub arm_capture: [u8; 2],
+    pub get_capture: [u8; 2],
+    pub store_register: [u8; 2],
+    pub trigger_register: [u8; 2],
+    pub replies: ReplyMap,
+    pub values: NumberFormat,
+}
+
+impl ProtocolMap {
+    //What the stock ClearCore sketch understands
+    pub const STOCK: ProtocolMap = ProtocolMap {
+        motor: b'M',
+        enable: *b"EN",
+        disable: *b"DE",
+        move_absolute: *b"AM",
+        move_relative: *b"RM",
+        jog: *b"JG",
+        stop: *b"ST",
+        abrupt_stop: *b"AS",
+        set_position: *b"SP",
+        velocity_limit: *b"SV",
+        acceleration: *b"SA",
+        deceleration: *b"SD",
+        torque_limit: *b"TL",
+        home: *b"HM",
+        clear_alerts: *b"CA",
+        get_status: *b"GS",
+        get_position: *b"GP",
+        get_velocity: *b"GV",
+        get_torque: *b"GT",
+        get_following_error: *b"GE",
+        arm_capture: *b"CI",
+        get_capture: *b"GC",
+        store_register: *b"RS",
+        trigger_register: *b"RT",
+        replies: ReplyMap::STOCK,
+        values: NumberFormat::AsciiDecimal,
+    };
+}
+
+impl Default for ProtocolMap {
+    fn default() -> Self {
+        Self::STOCK
+    }
+}
@@ -0,0 +1,157 @@
+use crate::controllers::clear_core::{ResultCode, CR};
+use crate::error::{ControlError, Result};
+use crate::util::number::NumberFormat;
+
+//Where a command's reply carries its payload, counted from the STX, so each read says which
+//layout it expects instead of every reply being taken apart at the same index. The payload runs
+//up to the CR and values in it are read in `format`. Ack replies and value replies share a
+//parser, a Nak where the payload starts is a rejection either way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResponseParser {
+    pub offset: usize,
+    pub format: NumberFormat,
+}
+
+impl ResponseParser {
+    //STX, the device letter and its id digit ahead of the payload, e.g. "M0_" or "M01600". The
+    //stock firmware answers every motor, input and output command like this.
+    pub const DEVICE: ResponseParser = ResponseParser {
+        offset: 3,
+        format: NumberFormat::AsciiDecimal,
+    };
+    //Firmware that echoes the two command letters after the id, e.g. "M0GP1600"
+    pub const ECHOED: ResponseParser = ResponseParser {
+        offset: 5,
+        format: NumberFormat::AsciiDecimal,
+    };
+
+    pub const fn with_format(self, format: NumberFormat) -> Self {
+        Self { format, ..self }
+    }
+
+    //Everything from the offset up to the CR, or the Nak as CommandRejected. A reply too short
+    //to reach the offset has an empty payload. A binary value may start with the Nak byte, so
+    //with a binary format only a payload that is the Nak alone counts.
+    pub fn payload<'a>(&self, reply: &'a [u8]) -> Result<&'a [u8]> {
+        let payload = reply.get(self.offset..).unwrap_or_default();
+        let payload = payload.strip_suffix(&[CR]).unwrap_or(payload);
+        let code = match payload {
+            [code] => Some(*code),
+            [code, ..] if !self.format.is_binary() => Some(*code),
+            _ => None,
+        };
+        match code.and_then(ResultCode::from_byte) {
+            Some(ResultCode::Nak) => Err(ControlError::CommandRejected(reply.to_vec())),
+            _ => Ok(payload),
+        }
+    }
+
+    //For commands that only ack, value replies carry data where the code would be so only an
+    //explicit Nak counts as a failure
+    pub fn check(&self, reply: &[u8]) -> Result<()> {
+        self.payload(reply).map(|_| ())
+    }
+
+    //A signed value in `format`, a payload that can't be one is a BadResponse
+    pub fn integer(&self, reply: &[u8]) -> Result<isize> {
+        self.format
+            .decode(self.payload(reply)?)
+            .ok_or_else(|| ControlError::BadResponse(reply.to_vec()))
+    }
+
+    //An on/off payload, on for any non-zero value
+    pub fn flag(&self, reply: &[u8]) -> Result<bool> {
+        self.integer(reply).map(|value| value != 0)
+    }
+}
+
+impl Default for ResponseParser {
+    fn default() -> Self {
+        Self::DEVICE
+    }
+}
+
+//Which parser each motor command's reply goes through, part of a ProtocolMap so firmware that
+//lays out some replies differently only needs those entries changed. One parser covers every
+//command that only acks, the value queries each get their own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReplyMap {
+    pub ack: ResponseParser,
+    pub status: ResponseParser,
+    pub position: ResponseParser,
+    pub velocity: ResponseParser,
+    pub torque: ResponseParser,
+    pub following_error: ResponseParser,
+    pub capture: ResponseParser,
+}
+
+impl ReplyMap {
+    //Every command's reply through `parser`, e.g. for firmware that answers in binary throughout
+    pub const fn uniform(parser: ResponseParser) -> Self {
+        Self {
+            ack: parser,
+            status: parser,
+            position: parser,
+            velocity: parser,
+            torque: parser,
+            following_error: parser,
+            capture: parser,
+        }
+    }
+
+    pub const STOCK: ReplyMap = ReplyMap {
+        ack: ResponseParser::DEVICE,
+        status: ResponseParser::DEVICE,
+        position: ResponseParser::DEVICE,
+        velocity: ResponseParser::DEVICE,
+        torque: ResponseParser::DEVICE,
+        following_error: ResponseParser::DEVICE,
+        capture: ResponseParser::DEVICE,
+    };
+}
+
+impl Default for ReplyMap {
+    fn default() -> Self {
+        Self::STOCK
+    }
+}
+
+#[test]
+fn test_response_parser() {
+    let device = ResponseParser::DEVICE;
+    //Ack and Nak
+    assert!(device.check(b"\x02M0_\r").is_ok());
+    assert!(matches!(
+        device.check(b"\x02M0?\r"),
+        Err(ControlError::CommandRejected(_))
+    ));
+    //Signed value, and the same read without a value
+    assert_eq!(device.integer(b"\x02M0-1600\r").unwrap(), -1600);
+    assert!(matches!(
+        device.integer(b"\x02M0_\r"),
+        Err(ControlError::BadResponse(_))
+    ));
+    //Input level
+    assert!(device.flag(b"\x02I11\r").unwrap());
+    assert!(!device.flag(b"\x02I10\r").unwrap());
+    //Echoed command letters ahead of the value, which DEVICE would misread
+    let echoed = ResponseParser::ECHOED;
+    assert_eq!(echoed.payload(b"\x02M0GP1600\r").unwrap(), b"1600");
+    assert_eq!(echoed.integer(b"\x02M0GP1600\r").unwrap(), 1600);
+    assert_eq!(device.payload(b"\x02M0GP1600\r").unwrap(), b"GP1600");
+    assert!(echoed.check(b"\x02M0GP?\r").is_err());
+    //Too short to reach the offset
+    assert_eq!(echoed.payload(b"\x02M0\r").unwrap(), b"");
+    //Binary values, one led by the Nak byte included
+    let binary = device.with_format(NumberFormat::BigEndian);
+    assert_eq!(binary.integer(b"\x02M0\x00\x00\x06\x40\r").unwrap(), 1600);
+    assert_eq!(
+        binary.integer(b"\x02M0?\x00\x00\x00\r").unwrap(),
+        0x3f000000
+    );
+    assert!(binary.check(b"\x02M0?\r").is_err());
+    assert!(matches!(
+        binary.integer(b"\x02M0\x06\x40\r"),
+        Err(ControlError::BadResponse(_))
+    ));
+}
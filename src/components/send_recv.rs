@@ -1,27 +1,146 @@
 use crate::controllers::clear_core::Message;
+use crate::error::{ControlError, Result};
 use std::future::Future;
-use log::error;
+use std::time::Duration;
 use tokio::sync::{mpsc, oneshot};
+use tracing::{debug_span, warn, Instrument};
 
 pub trait SendRecv {
     fn get_sender(&self) -> &mpsc::Sender<Message>;
-    fn write(&self, buffer: &[u8]) -> impl Future<Output = Vec<u8>>
+    fn try_write_timeout(
+        &self,
+        buffer: &[u8],
+        timeout: Option<Duration>,
+    ) -> impl Future<Output = Result<Vec<u8>>>
     where
         Self: Sync,
     {
-        async {
+        self.try_write_owned(buffer.to_vec(), timeout)
+    }
+    //For callers that built the frame themselves, it moves straight into the Message without a copy.
+    //The command is never resent, see try_write_idempotent for ones that can be.
+    fn try_write_owned(
+        &self,
+        buffer: Vec<u8>,
+        timeout: Option<Duration>,
+    ) -> impl Future<Output = Result<Vec<u8>>>
+    where
+        Self: Sync,
+    {
+        self.try_write_message(buffer, timeout, false)
+    }
+    //try_write_owned for a command the caller knows is safe to run twice, e.g. a read or an
+    //absolute target, which the client may resend after a transient failure
+    fn try_write_idempotent(
+        &self,
+        buffer: Vec<u8>,
+        timeout: Option<Duration>,
+    ) -> impl Future<Output = Result<Vec<u8>>>
+    where
+        Self: Sync,
+    {
+        self.try_write_message(buffer, timeout, true)
+    }
+    fn try_write_message(
+        &self,
+        buffer: Vec<u8>,
+        timeout: Option<Duration>,
+        idempotent: bool,
+    ) -> impl Future<Output = Result<Vec<u8>>>
+    where
+        Self: Sync,
+    {
+        //The frames are plain ASCII apart from STX and CR, so the trimmed frame reads as the command
+        let command = buffer
+            .get(1..buffer.len().saturating_sub(1))
+            .unwrap_or_default();
+        let span = debug_span!("command", frame = %String::from_utf8_lossy(command));
+        async move {
             let (resp_tx, resp_rx) = oneshot::channel();
             let msg = Message {
-                buffer: buffer.to_vec(),
+                buffer,
                 response: resp_tx,
+                timeout,
+                idempotent,
             };
-            if let Err(e) = self
-                .get_sender()
-                .send(msg)
-                .await {
-                error!("DEBUG {:?}", e);
+            if self.get_sender().send(msg).await.is_err() {
+                return Err(ControlError::Disconnected);
+            }
+            //If the client task is gone the response sender is dropped with it
+            let reply = resp_rx.await.unwrap_or(Err(ControlError::Disconnected));
+            if let Err(e) = &reply {
+                warn!(error = %e, "Command failed");
             }
-            resp_rx.await.expect("No MSG from client")
+            reply
         }
+        .instrument(span)
+    }
+    //try_write_owned that never waits for room on the client's queue, a full queue comes back
+    //straight away as Busy and the command is dropped. Only the reply is waited for. Meant for
+    //polls that are better skipped than queued behind a slow link, which is why only the status,
+    //position and input reads have try_ variants built on it: ClearCoreMotor::try_get_status and
+    //try_get_position, DigitalInput::try_get_state and AnalogInput::try_get_state. Commands that
+    //change anything always wait their turn. Being reads they're all safe to resend.
+    fn try_write_now(
+        &self,
+        buffer: Vec<u8>,
+        timeout: Option<Duration>,
+    ) -> impl Future<Output = Result<Vec<u8>>>
+    where
+        Self: Sync,
+    {
+        let command = buffer
+            .get(1..buffer.len().saturating_sub(1))
+            .unwrap_or_default();
+        let span = debug_span!("command", frame = %String::from_utf8_lossy(command));
+        async move {
+            let (resp_tx, resp_rx) = oneshot::channel();
+            let msg = Message {
+                buffer,
+                response: resp_tx,
+                timeout,
+                idempotent: true,
+            };
+            match self.get_sender().try_send(msg) {
+                Ok(()) => {}
+                Err(mpsc::error::TrySendError::Full(_)) => return Err(ControlError::Busy),
+                Err(mpsc::error::TrySendError::Closed(_)) => {
+                    return Err(ControlError::Disconnected)
+                }
+            }
+            resp_rx.await.unwrap_or(Err(ControlError::Disconnected))
+        }
+        .instrument(span)
+    }
+    fn try_write(&self, buffer: &[u8]) -> impl Future<Output = Result<Vec<u8>>>
+    where
+        Self: Sync,
+    {
+        self.try_write_timeout(buffer, None)
+    }
+    //Fire and forget, a failed command is only logged by try_write_message
+    fn write(&self, buffer: &[u8]) -> impl Future<Output = ()>
+    where
+        Self: Sync,
+    {
+        async {
+            let _ = self.try_write(buffer).await;
+        }
+    }
+    //A pause between steps of a sequence, see dwell
+    fn dwell(&self, duration: Duration) -> impl Future<Output = Result<()>>
+    where
+        Self: Sync,
+    {
+        dwell(self.get_sender(), duration)
+    }
+}
+
+//Sleeps for `duration` but gives up with Disconnected as soon as the client is gone, so a recipe
+//doesn't sit out a long wait only to fail on its next command. Dropping it cancels the wait.
+pub(crate) async fn dwell(sender: &mpsc::Sender<Message>, duration: Duration) -> Result<()> {
+    tokio::select! {
+        _ = tokio::time::sleep(duration) => Ok(()),
+        _ = sender.closed() => Err(ControlError::Disconnected),
     }
 }
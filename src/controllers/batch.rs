@@ -0,0 +1,156 @@
+use crate::controllers::clear_core::{check_result, Message};
+use crate::error::{ControlError, Result};
+use crate::interface::transport::split_frames;
+use std::time::Duration;
+use tokio::sync::mpsc::Sender;
+use tokio::sync::oneshot;
+
+//Several commands sent in a single write, made with Controller::batch. Reply n belongs to frame n
+//and nothing from other tasks is interleaved. A timeout or lost connection fails the whole batch,
+//a rejected command only its own entry while the ones after it still run. The client only resends
+//the batch after a transient failure when every frame was pushed with push_idempotent.
+pub struct Batch {
+    sender: Sender<Message>,
+    frames: Vec<Vec<u8>>,
+    timeout: Option<Duration>,
+    idempotent: bool,
+}
+
+impl Batch {
+    pub(crate) fn new(sender: Sender<Message>) -> Self {
+        Self {
+            sender,
+            frames: Vec::new(),
+            timeout: None,
+            idempotent: true,
+        }
+    }
+
+    //A complete STX..CR frame, e.g. from ClearCoreMotor::velocity_limit_frame
+    pub fn push(mut self, frame: impl Into<Vec<u8>>) -> Self {
+        self.frames.push(frame.into());
+        self.idempotent = false;
+        self
+    }
+
+    //push for a frame that's safe to run twice, e.g. a read, a setting or an absolute move
+    pub fn push_idempotent(mut self, frame: impl Into<Vec<u8>>) -> Self {
+        self.frames.push(frame.into());
+        self
+    }
+
+    //For the whole batch, by default the client allows its command timeout per frame
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    //One result per frame in the order they were pushed
+    pub async fn flush(self) -> Result<Vec<Result<Vec<u8>>>> {
+        if self.frames.is_empty() {
+            return Ok(Vec::new());
+        }
+        let expected = self.frames.len();
+        let (resp_tx, resp_rx) = oneshot::channel();
+        let buffer = self.frames.concat();
+        let msg = Message {
+            buffer,
+            response: resp_tx,
+            timeout: self.timeout,
+            idempotent: self.idempotent,
+        };
+        self.sender
+            .send(msg)
+            .await
+            .map_err(|_| ControlError::Disconnected)?;
+        let reply = resp_rx.await.unwrap_or(Err(ControlError::Disconnected))?;
+        let replies = split_frames(&reply);
+        if replies.len() != expected {
+            return Err(ControlError::BadResponse(reply));
+        }
+        Ok(replies
+            .into_iter()
+            .map(|reply| check_result(&reply).map(|_| reply))
+            .collect())
+    }
+}
+
+#[tokio::test]
+async fn test_batch() {
+    use crate::controllers::clear_core::{Controller, MotorBuilder};
+    use crate::testing::MockClearCore;
+
+    let mock = MockClearCore::start().await.unwrap();
+    mock.on(b"M0SA", b"?");
+    let motors = [MotorBuilder {
+        id: 0,
+        scale: 800,
+        ..Default::default()
+    }];
+    let (controller, client) = Controller::with_client(mock.addr(), motors.as_slice());
+    let handle = tokio::spawn(client);
+
+    let motor = controller.get_motor(0);
+    let results = controller
+        .batch()
+        .push(motor.velocity_limit_frame(2.5).unwrap())
+        .push(motor.acceleration_frame(40.0).unwrap())
+        .push(motor.move_absolute_frame(1.0).unwrap())
+        .flush()
+        .await
+        .unwrap();
+    assert_eq!(results.len(), 3);
+    assert_eq!(results[0].as_ref().unwrap(), b"\x02M0_\r");
+    assert!(matches!(results[1], Err(ControlError::CommandRejected(_))));
+    assert!(results[2].is_ok());
+    assert_eq!(
+        mock.received(),
+        [
+            b"\x02M0SV2000\r".to_vec(),
+            b"\x02M0SA32000\r".to_vec(),
+            b"\x02M0AM800\r".to_vec(),
+        ]
+    );
+    assert!(controller.batch().flush().await.unwrap().is_empty());
+
+    drop((controller, motor));
+    handle.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_batch_idempotent() {
+    let (tx, mut rx) = tokio::sync::mpsc::channel::<Message>(10);
+    let replier = tokio::spawn(async move {
+        let mut flags = Vec::new();
+        while let Some(msg) = rx.recv().await {
+            flags.push(msg.idempotent);
+            let reply = b"\x02M0_\r".repeat(split_frames(&msg.buffer).len());
+            msg.response.send(Ok(reply)).unwrap();
+        }
+        flags
+    });
+
+    //One frame that isn't known to be safe keeps the whole write from being resent
+    Batch::new(tx.clone())
+        .push_idempotent(b"\x02M0GS\r".to_vec())
+        .push_idempotent(b"\x02I1\r".to_vec())
+        .flush()
+        .await
+        .unwrap();
+    Batch::new(tx.clone())
+        .push_idempotent(b"\x02M0GS\r".to_vec())
+        .push(b"\x02M0RM800\r".to_vec())
+        .flush()
+        .await
+        .unwrap();
+    drop(tx);
+    assert_eq!(replier.await.unwrap(), [true, false]);
+}
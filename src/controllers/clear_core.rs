@@ -1,25 +1,104 @@
-use crate::components::clear_core_io::{AnalogInput, DigitalInput, DigitalOutput, HBridge};
-use crate::components::clear_core_motor::{ClearCoreMotor, Status};
-use crate::interface::tcp::client;
-use std::error::Error;
+use crate::components::clear_core_io::{
+    AnalogInput, DigitalInput, DigitalOutput, HBridge, SafeState,
+};
+use crate::components::clear_core_motor::{
+    ClearCoreMotor, HomingConfig, LimitMode, LimitSwitches, MotorStatus, SoftLimits,
+};
+use crate::components::protocol::ProtocolMap;
+use crate::components::response::ResponseParser;
+use crate::components::send_recv::dwell;
+use crate::controllers::batch::Batch;
+use crate::controllers::output_group::OutputGroup;
+use crate::error::{ControlError, MultiResult, Result};
+use crate::interface::audit::Recorder;
+#[cfg(feature = "serial")]
+use crate::interface::serial::serial_client;
+use crate::interface::tcp::{client_with_config, client_with_shutdown, ClientConfig};
+use crate::interface::transport::{
+    run_client, ConnectionState, ConnectionStatus, Queues, ReconnectFrames, Transport,
+};
+use crate::util::poll::PollConfig;
+use crate::util::utils::hex_dump;
+use futures::future::join_all;
+use futures::stream::{self, Stream};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
 use std::future::Future;
+use std::ops::{Deref, DerefMut};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tokio::net::ToSocketAddrs;
 use tokio::sync::mpsc::{channel, Sender};
-use tokio::sync::oneshot;
+use tokio::sync::{oneshot, watch};
 use tokio::task::JoinSet;
 
 pub const STX: u8 = 2;
 pub const CR: u8 = 13;
-pub const RESULT_IDX: u8 = 3;
+
+//What the firmware puts where the payload starts for commands that don't reply with a value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResultCode {
+    Ack,
+    Nak,
+}
+
+impl ResultCode {
+    pub fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            b'_' => Some(ResultCode::Ack),
+            b'?' => Some(ResultCode::Nak),
+            _ => None,
+        }
+    }
+}
+
+//Asks the firmware who it is, answered with e.g. "ClearCore control 1.4.2" after the letters
+const IDENTITY_COMMAND: [u8; 2] = *b"VR";
+//Oldest firmware this crate's commands are known to work against, older identities are warned about
+pub const MIN_FIRMWARE_VERSION: (u32, u32, u32) = (1, 0, 0);
+
+//A stock layout reply, value replies carry data where the code would be so only an explicit Nak
+//counts as a failure
+pub fn check_result(reply: &[u8]) -> Result<()> {
+    ResponseParser::DEVICE.check(reply)
+}
 
 const NO_DIGITAL_INPUTS: usize = 3;
 const NO_ANALOG_INPUTS: usize = 4;
 const NO_OUTPUTS: usize = 6;
 const NO_HBRIDGE: usize = 2;
+//Commands that can queue up for the client before senders have to wait
+pub const DEFAULT_CAPACITY: usize = 100;
+//The priority queue only ever holds a handful of e-stops
+const PRIORITY_CAPACITY: usize = 8;
 
 pub struct Message {
     pub buffer: Vec<u8>,
-    pub response: oneshot::Sender<Vec<u8>>,
+    pub response: oneshot::Sender<Result<Vec<u8>>>,
+    //Overrides the client's default command timeout when set
+    pub timeout: Option<Duration>,
+    //Whether running the buffer twice leaves the controller the same as running it once, only
+    //these are retried by the client. Set by whoever built the frame, nothing reads it off the bytes.
+    pub idempotent: bool,
+}
+
+impl Message {
+    pub fn hex_dump(&self) -> String {
+        hex_dump(&self.buffer)
+    }
+}
+
+//The response sender has nothing worth printing, so only the bytes and options show up
+impl fmt::Debug for Message {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Message")
+            .field("buffer", &format_args!("{}", self.hex_dump()))
+            .field("timeout", &self.timeout)
+            .field("idempotent", &self.idempotent)
+            .finish_non_exhaustive()
+    }
 }
 
 //TODO: Change to arrays using array::from_fn
@@ -30,21 +109,353 @@ pub type AnalogInputs = Vec<AnalogInput>;
 pub type Outputs = Vec<DigitalOutput>;
 pub type HBridges = [HBridge; NO_HBRIDGE];
 
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
 pub struct MotorBuilder {
     pub id: u8,
     pub scale: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub homing: HomingConfig,
+    //Velocity limit and ramps sent to the drive whenever the motor is enabled
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub velocity: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub acceleration: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deceleration: Option<f64>,
+    //Soft travel limits in user units, checked before any move is sent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_position: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_position: Option<f64>,
+    #[serde(default)]
+    pub limit_mode: LimitMode,
+    //For axes mounted so that the drive's positive is the application's negative
+    #[serde(default)]
+    pub invert: bool,
+    //Digital inputs wired to limit switches at either end of travel, see LimitSwitches
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub positive_limit: Option<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub negative_limit: Option<u8>,
+}
+
+//How many of each IO the controller exposes, expansion modules add more than the stock board has
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ControllerLayout {
+    pub digital_inputs: usize,
+    pub analog_inputs: usize,
+    pub outputs: usize,
+}
+
+impl Default for ControllerLayout {
+    fn default() -> Self {
+        Self {
+            digital_inputs: NO_DIGITAL_INPUTS,
+            analog_inputs: NO_ANALOG_INPUTS,
+            outputs: NO_OUTPUTS,
+        }
+    }
+}
+
+//Everything needed to stand up a controller and its client, see controller.example.toml. The IO
+//counts fall back to the stock ClearCore layout when left out.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ControllerConfig {
+    pub addr: String,
+    #[serde(flatten)]
+    pub layout: ControllerLayout,
+    //Must match the firmware, see ClientConfig::checksum
+    #[serde(default)]
+    pub checksum: bool,
+    //Left out, the client gives up on a connect attempt after ClientConfig's default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connect_timeout_ms: Option<u64>,
+    //An NDJSON file every command and reply is appended to, see Recorder
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub audit_log: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub safe_outputs: Vec<SafeOutput>,
+    //Left out, motors and inputs keep their own default poll intervals
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub poll: Option<PollConfig>,
+    pub motors: Vec<MotorBuilder>,
+}
+
+//An output's safe state in the config, a duty takes precedence over the on/off state
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SafeOutput {
+    pub id: usize,
+    #[serde(default)]
+    pub state: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duty: Option<u8>,
+}
+
+impl SafeOutput {
+    pub fn safe_state(&self) -> SafeState {
+        match self.duty {
+            Some(duty) => SafeState::Duty(duty),
+            None => self.state.into(),
+        }
+    }
+}
+
+//One failed read only shows up in its own slot, everything is in index order
+#[derive(Debug)]
+pub struct IoSnapshot {
+    pub digital_inputs: MultiResult<bool>,
+    pub analog_inputs: MultiResult<isize>,
+}
+
+//Everything the controller can see at one moment, from Controller::snapshot. A read that failed is
+//None (null in JSON) so one unplugged sensor doesn't hide the rest, everything is in index order.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ControllerSnapshot {
+    //Wall clock time the reads were started, in milliseconds since the Unix epoch
+    pub timestamp_ms: u64,
+    pub connection: ConnectionState,
+    pub motors: Vec<MotorSnapshot>,
+    pub digital_inputs: Vec<Option<bool>>,
+    pub analog_inputs: Vec<Option<isize>>,
+    pub outputs: Vec<Option<bool>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MotorSnapshot {
+    pub id: u8,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub position: Option<f64>,
+    pub status: Option<MotorStatus>,
+}
+
+//What Controller::self_test found. `connected` is whether anything answered at all, `ready`
+//whether every motor answered a status query without a fault and every IO point could be read.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SelfTestReport {
+    pub ready: bool,
+    pub connected: bool,
+    pub connection: ConnectionState,
+    pub motors: Vec<ComponentCheck>,
+    pub digital_inputs: Vec<ComponentCheck>,
+    pub analog_inputs: Vec<ComponentCheck>,
+    pub outputs: Vec<ComponentCheck>,
+}
+
+impl SelfTestReport {
+    pub fn failures(&self) -> impl Iterator<Item = &ComponentCheck> {
+        self.motors
+            .iter()
+            .chain(&self.digital_inputs)
+            .chain(&self.analog_inputs)
+            .chain(&self.outputs)
+            .filter(|check| !check.ok)
+    }
+}
+
+//What came of an emergency_stop, each motor's abrupt stop and each safe output tagged with its index
+#[derive(Debug)]
+pub struct EmergencyStopResult {
+    pub motors: MultiResult<()>,
+    pub outputs: MultiResult<()>,
+}
+
+impl EmergencyStopResult {
+    pub fn is_ok(&self) -> bool {
+        self.motors.is_ok() && self.outputs.is_ok()
+    }
+}
+
+//One component's part of a SelfTestReport, `index` is its index on the controller and `error`
+//says why it isn't ok
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ComponentCheck {
+    pub index: usize,
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl ComponentCheck {
+    fn new<T>(index: usize, result: &Result<T>) -> Self {
+        Self {
+            index,
+            ok: result.is_ok(),
+            error: result.as_ref().err().map(|e| e.to_string()),
+        }
+    }
+}
+
+//A change in a motor's fault state reported by Controller::fault_events
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MotorFault {
+    Raised(MotorStatus),
+    Cleared,
+}
+
+//Stops the client started by Controller::with_shutdown_client, optionally bringing every motor
+//to a controlled stop first while the connection is still up
+pub struct ShutdownHandle {
+    motors: Motors,
+    signal: oneshot::Sender<()>,
+}
+
+impl ShutdownHandle {
+    //Returns the stop results in motor index order, empty when the motors are left alone
+    pub async fn shutdown(self, stop_motors: bool) -> MultiResult<()> {
+        let stopped = if stop_motors {
+            MultiResult::new(join_all(self.motors.iter().map(|motor| motor.stop())).await)
+        } else {
+            MultiResult::new([])
+        };
+        //The client may already be gone, in which case there is nothing left to shut down
+        let _ = self.signal.send(());
+        stopped
+    }
+}
+
+//Stops every motor when dropped unless disarmed first, so a panic or an early return out of the
+//code driving the machine doesn't leave anything running. Derefs to the controller it wraps.
+//Drop can't wait on the link, so the stop goes out from a spawned task holding its own clone of
+//the controller and is best effort: it needs a tokio runtime that is still running, and with
+//none it is only logged. Once that task is done with its clone, and it was the last one, the
+//client shuts down and closes the connection.
+pub struct ControllerGuard {
+    controller: Option<Controller>,
+    emergency: bool,
+}
+
+impl ControllerGuard {
+    //Brings the motors to a controlled stop on drop
+    pub fn new(controller: Controller) -> Self {
+        Self {
+            controller: Some(controller),
+            emergency: false,
+        }
+    }
+
+    //Has the drop go through emergency_stop instead, abrupt stops and safe states included
+    pub fn emergency(mut self) -> Self {
+        self.emergency = true;
+        self
+    }
+
+    //Gives the controller back without stopping anything
+    pub fn disarm(mut self) -> Controller {
+        self.controller.take().expect("guard already disarmed")
+    }
+}
+
+impl Deref for ControllerGuard {
+    type Target = Controller;
+
+    fn deref(&self) -> &Controller {
+        self.controller.as_ref().expect("guard already disarmed")
+    }
+}
+
+impl DerefMut for ControllerGuard {
+    fn deref_mut(&mut self) -> &mut Controller {
+        self.controller.as_mut().expect("guard already disarmed")
+    }
+}
+
+impl Drop for ControllerGuard {
+    fn drop(&mut self) {
+        let Some(controller) = self.controller.take() else {
+            return;
+        };
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            error!("Controller dropped outside a tokio runtime, motors were not stopped");
+            return;
+        };
+        warn!("Controller guard dropped, stopping every motor");
+        let emergency = self.emergency;
+        runtime.spawn(async move {
+            if emergency {
+                //flush_all has logged each failed command already
+                if let Err(e) = controller.emergency_stop().await {
+                    error!("Emergency stop on guard drop failed: {e}");
+                }
+            } else {
+                for (index, e) in controller.stop_all_motors().await.failures() {
+                    error!("Stopping motor {index} on guard drop failed: {e}");
+                }
+            }
+        });
+    }
+}
+
+//Names a motor, input or output by its index on the controller so the accessors can take an
+//application's own ids, e.g. get_motor(Motor::Gantry). Plain usize indices keep working as before.
+//The component_ids! macro writes the enum and this impl in one go.
+pub trait ComponentId {
+    fn index(self) -> usize;
+}
+
+impl ComponentId for usize {
+    fn index(self) -> usize {
+        self
+    }
+}
+
+//Declares a fieldless enum with an index for each variant and implements ComponentId for it:
+//
+//    component_ids! {
+//        pub enum Motor {
+//            Gantry = 0,
+//            Hatch = 1,
+//        }
+//    }
+#[macro_export]
+macro_rules! component_ids {
+    ($(#[$meta:meta])* $vis:vis enum $name:ident { $($variant:ident = $index:expr),+ $(,)? }) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        $vis enum $name {
+            $($variant),+
+        }
+
+        impl $crate::controllers::clear_core::ComponentId for $name {
+            fn index(self) -> usize {
+                match self {
+                    $($name::$variant => $index),+
+                }
+            }
+        }
+    };
 }
 
 //The way controller is meant to be used now is to feed it the "recipe" for how to make a motor
 //(id and scale) and a single tx that the constructor then copies so that we don't have to copy it
 //ourselves and worry about it being dropped correctly.
+//Every component handed out is a clone holding its own sender, so nothing it does needs the
+//controller any more. With the controller shared behind a Mutex, take the handle and let the lock go
+//before anything long like wait_for_move_complete, then a motor that's polling never holds up
+//commands to the others.
 #[derive(Clone)]
 pub struct Controller {
+    sender: Sender<Message>,
+    //Same as sender unless the client was started with a priority queue
+    priority: Sender<Message>,
+    //The outputs' safe state frames, shared with the client so it can replay them on reconnect
+    safe_states: ReconnectFrames,
+    //Updated by the client this controller's client_config was handed to
+    connection: ConnectionStatus,
     motors: Motors,
+    motor_names: HashMap<String, usize>,
     digital_inputs: Inputs,
     analog_inputs: AnalogInputs,
     outputs: Outputs,
     h_bridges: HBridges,
+    //What set_poll_config last handed the components, None until it's called. fault_events polls
+    //with the default until then.
+    poll: Option<PollConfig>,
+    //What set_protocol last handed the motors
+    protocol: ProtocolMap,
 }
 
 impl Controller {
@@ -54,17 +465,31 @@ impl Controller {
     //change now is in that file. Something we can do in the future is make a HashMap of controllers
     //with a name and associate a sender to that but that seems like overkill to me now.
     pub fn new(tx: Sender<Message>, motors: &[MotorBuilder]) -> Self {
+        Self::with_layout(tx, motors, ControllerLayout::default())
+    }
+
+    pub fn with_layout(
+        tx: Sender<Message>,
+        motors: &[MotorBuilder],
+        layout: ControllerLayout,
+    ) -> Self {
+        let motor_names = motors
+            .iter()
+            .enumerate()
+            .filter_map(|(index, motor)| motor.name.clone().map(|name| (name, index)))
+            .collect();
         let motors = motors
             .iter()
-            .map(|motor| ClearCoreMotor::new(motor.id, motor.scale, tx.clone()))
+            .map(|motor| build_motor(motor, tx.clone()))
             .collect();
-        let digital_inputs = (0..NO_DIGITAL_INPUTS)
+        let digital_inputs = (0..layout.digital_inputs)
             .map(|index| DigitalInput::new(index as u8, tx.clone()))
             .collect();
-        let analog_inputs = (0..NO_ANALOG_INPUTS)
-            .map(|index| AnalogInput::new(index as u8 + 3, tx.clone()))
+        //Analog inputs are numbered straight after the digital ones
+        let analog_inputs = (0..layout.analog_inputs)
+            .map(|index| AnalogInput::new((index + layout.digital_inputs) as u8, tx.clone()))
             .collect();
-        let outputs = (0..NO_OUTPUTS)
+        let outputs = (0..layout.outputs)
             .map(|index| DigitalOutput::new(index as u8, tx.clone()))
             .collect();
 
@@ -74,50 +499,406 @@ impl Controller {
         ];
 
         Controller {
+            priority: tx.clone(),
+            safe_states: ReconnectFrames::default(),
+            connection: ConnectionStatus::default(),
+            sender: tx,
             motors,
+            motor_names,
             digital_inputs,
             analog_inputs,
             outputs,
             h_bridges,
+            poll: None,
+            protocol: ProtocolMap::STOCK,
         }
     }
 
+    //Adds a motor after construction, e.g. on a hot-plugged expansion module, sharing the client
+    //the rest already use, and returns its index. It gets whatever set_poll_config and
+    //set_protocol last set. Needs &mut self, so a controller shared behind a lock has to be
+    //write-locked for it, and clones of the controller taken earlier don't see the new motor.
+    pub fn add_motor(&mut self, builder: MotorBuilder) -> usize {
+        let mut motor = build_motor(&builder, self.sender.clone()).with_protocol(self.protocol);
+        if let Some(poll) = self.poll {
+            motor = motor.with_poll_config(poll);
+        }
+        let index = self.motors.len();
+        if let Some(name) = builder.name {
+            self.motor_names.insert(name, index);
+        }
+        self.motors.push(motor);
+        index
+    }
+
+    //Same as add_motor for a digital input, `id` being the number the firmware reads it by
+    pub fn add_input(&mut self, id: u8) -> usize {
+        let mut input = DigitalInput::new(id, self.sender.clone());
+        if let Some(poll) = self.poll {
+            input = input.with_poll_config(poll);
+        }
+        self.digital_inputs.push(input);
+        self.digital_inputs.len() - 1
+    }
+
+    pub fn add_analog_input(&mut self, id: u8) -> usize {
+        self.analog_inputs
+            .push(AnalogInput::new(id, self.sender.clone()));
+        self.analog_inputs.len() - 1
+    }
+
+    pub fn add_output(&mut self, id: u8) -> usize {
+        self.outputs
+            .push(DigitalOutput::new(id, self.sender.clone()));
+        self.outputs.len() - 1
+    }
+
     pub fn with_client<T: ToSocketAddrs>(
         addr: T,
         motors: &[MotorBuilder],
-    ) -> (
-        Self,
-        impl Future<Output = Result<(), Box<dyn Error + Send + Sync>>>,
-    ) {
-        let (tx, rx) = channel(100);
-        (Controller::new(tx, motors), client(addr, rx))
+    ) -> (Self, impl Future<Output = Result<()>>) {
+        let (controller, queues) =
+            Controller::with_queues(motors, ControllerLayout::default(), DEFAULT_CAPACITY);
+        let config = controller.client_config(ClientConfig::default());
+        (controller, client_with_config(addr, queues, config))
     }
 
-    pub fn get_motor(&self, id: usize) -> ClearCoreMotor {
-        self.motors[id].clone()
+    //Same as with_client but an unreachable controller fails a connect attempt after
+    //`connect_timeout`, the attempts are retried as ClientConfig::reconnect says
+    pub fn with_client_timeout<T: ToSocketAddrs>(
+        addr: T,
+        motors: &[MotorBuilder],
+        connect_timeout: Duration,
+    ) -> (Self, impl Future<Output = Result<()>>) {
+        let config = ClientConfig {
+            connect_timeout,
+            ..Default::default()
+        };
+        Controller::with_client_config(addr, motors, config)
+    }
+
+    //`capacity` is how many commands can wait for the client on top of the one it's working on.
+    //Once that many are queued every component call waits for room before it is even sent, so a
+    //slow link holds its callers back instead of queueing without bound. Larger suits bursts of
+    //commands from many tasks, smaller keeps a stale backlog from building up. Panics if 0.
+    pub fn with_client_capacity<T: ToSocketAddrs>(
+        addr: T,
+        motors: &[MotorBuilder],
+        capacity: usize,
+    ) -> (Self, impl Future<Output = Result<()>>) {
+        let (controller, queues) =
+            Controller::with_queues(motors, ControllerLayout::default(), capacity);
+        let config = controller.client_config(ClientConfig::default());
+        (controller, client_with_config(addr, queues, config))
+    }
+
+    pub fn with_client_config<T: ToSocketAddrs>(
+        addr: T,
+        motors: &[MotorBuilder],
+        config: ClientConfig,
+    ) -> (Self, impl Future<Output = Result<()>>) {
+        let (controller, queues) =
+            Controller::with_queues(motors, ControllerLayout::default(), DEFAULT_CAPACITY);
+        let config = controller.client_config(config);
+        (controller, client_with_config(addr, queues, config))
+    }
+
+    pub fn with_shutdown_client<T: ToSocketAddrs>(
+        addr: T,
+        motors: &[MotorBuilder],
+        config: ClientConfig,
+    ) -> (Self, impl Future<Output = Result<()>>, ShutdownHandle) {
+        let (controller, queues) =
+            Controller::with_queues(motors, ControllerLayout::default(), DEFAULT_CAPACITY);
+        let config = controller.client_config(config);
+        let (signal, shutdown) = oneshot::channel();
+        let handle = ShutdownHandle {
+            motors: controller.motors.clone(),
+            signal,
+        };
+        (
+            controller,
+            client_with_shutdown(addr, queues, config, shutdown),
+            handle,
+        )
+    }
+
+    #[cfg(feature = "serial")]
+    pub fn with_serial_client(
+        port: &str,
+        baud_rate: u32,
+        motors: &[MotorBuilder],
+    ) -> (Self, impl Future<Output = Result<()>>) {
+        let (controller, queues) =
+            Controller::with_queues(motors, ControllerLayout::default(), DEFAULT_CAPACITY);
+        let port = port.to_string();
+        let config = controller.client_config(ClientConfig::default());
+        let client = async move { serial_client(port.as_str(), baud_rate, queues, config).await };
+        (controller, client)
+    }
+
+    //Runs the client over any Transport, e.g. a SimTransport for working without hardware
+    pub fn with_transport<T: Transport>(
+        transport: T,
+        motors: &[MotorBuilder],
+        config: ClientConfig,
+    ) -> (Self, impl Future<Output = Result<()>>) {
+        let (controller, queues) =
+            Controller::with_queues(motors, ControllerLayout::default(), DEFAULT_CAPACITY);
+        let config = controller.client_config(config);
+        (controller, run_client(transport, queues, config))
+    }
+
+    pub fn from_config(config: &ControllerConfig) -> (Self, impl Future<Output = Result<()>>) {
+        let (mut controller, queues) =
+            Controller::with_queues(config.motors.as_slice(), config.layout, DEFAULT_CAPACITY);
+        for output in config.safe_outputs.iter() {
+            controller = controller.with_safe_output(output.id, output.safe_state());
+        }
+        if let Some(poll) = config.poll {
+            controller.set_poll_config(poll);
+        }
+        let defaults = ClientConfig::default();
+        let client_config = controller.client_config(ClientConfig {
+            checksum: config.checksum,
+            connect_timeout: config
+                .connect_timeout_ms
+                .map_or(defaults.connect_timeout, Duration::from_millis),
+            ..defaults
+        });
+        let addr = config.addr.clone();
+        let audit_log = config.audit_log.clone();
+        let client = async move {
+            let Some(path) = audit_log else {
+                return client_with_config(addr, queues, client_config).await;
+            };
+            //The file is opened once the client starts so a bad path fails the client, not the
+            //construction
+            let (recorder, writer) = Recorder::to_file(path).await?;
+            let client_config = ClientConfig {
+                recorder: Some(recorder),
+                ..client_config
+            };
+            //The writer finishes the trail once the client is done with the recorder
+            let (result, written) =
+                tokio::join!(client_with_config(addr, queues, client_config), writer);
+            result?;
+            Ok(written?)
+        };
+        (controller, client)
+    }
+
+    pub fn from_config_file(path: &Path) -> Result<(Self, impl Future<Output = Result<()>>)> {
+        let contents = std::fs::read_to_string(path)?;
+        let config: ControllerConfig = toml::from_str(contents.as_str())?;
+        Ok(Controller::from_config(&config))
+    }
+
+    //Controllers that start their own client get a priority queue for emergency_stop
+    fn with_queues(
+        motors: &[MotorBuilder],
+        layout: ControllerLayout,
+        capacity: usize,
+    ) -> (Self, Queues) {
+        let (tx, rx) = channel(capacity);
+        let (priority_tx, priority_rx) = channel(PRIORITY_CAPACITY);
+        let controller =
+            Controller::with_layout(tx, motors, layout).with_priority_sender(priority_tx);
+        let queues = Queues {
+            normal: rx,
+            priority: Some(priority_rx),
+        };
+        (controller, queues)
+    }
+
+    //Points the client at this controller's safe states so a reconnect re-applies them and has it
+    //report to connection_state, any on_reconnect frames and connection already in the config are
+    //replaced
+    pub fn client_config(&self, config: ClientConfig) -> ClientConfig {
+        ClientConfig {
+            on_reconnect: self.safe_states.clone(),
+            connection: self.connection.clone(),
+            ..config
+        }
+    }
+
+    //Starts out Disconnected until the client has connected. A controller built with
+    //Controller::new only sees changes from a client given its client_config.
+    pub fn connection_state(&self) -> watch::Receiver<ConnectionState> {
+        self.connection.subscribe()
+    }
+
+    //For a client wired up by hand, `priority` should feed the Queues::priority it serves
+    pub fn with_priority_sender(mut self, priority: Sender<Message>) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    pub fn with_safe_output(mut self, index: usize, state: impl Into<SafeState>) -> Self {
+        self.outputs[index] = self.outputs[index].clone().with_safe_state(state);
+        self.safe_states.set(self.safe_state_frames());
+        self
+    }
+
+    fn safe_state_frames(&self) -> Vec<Vec<u8>> {
+        self.safe_outputs()
+            .into_iter()
+            .map(|(_, frame)| frame)
+            .collect()
+    }
+
+    //Index and safe state frame of every output that has one
+    fn safe_outputs(&self) -> Vec<(usize, Vec<u8>)> {
+        self.outputs
+            .iter()
+            .enumerate()
+            .filter_map(|(index, output)| output.safe_state_frame().map(|frame| (index, frame)))
+            .collect()
+    }
+
+    //Drives every output that has a safe state to it in one write, the client also does this by
+    //itself after every reconnect. Results are tagged with each output's index, a timeout or lost
+    //connection fails the whole call.
+    pub async fn apply_safe_states(&self) -> Result<MultiResult<()>> {
+        let (indices, frames): (Vec<usize>, Vec<Vec<u8>>) = self.safe_outputs().into_iter().unzip();
+        let mut batch = self.batch();
+        for frame in frames {
+            batch = batch.push_idempotent(frame);
+        }
+        let results = flush_all(batch, "Applying safe states").await?;
+        Ok(MultiResult::indexed(indices.into_iter().zip(results)))
+    }
+
+    //Abruptly stops every motor and drives every output with a safe state to it. It all goes out
+    //as one write on the priority queue, so it overtakes anything queued and only waits for the
+    //transaction already in flight. Controllers built with Controller::new have no priority
+    //queue and this falls back to the normal one. Every command is sent even if an earlier one is
+    //rejected, only a timeout or lost connection fails the whole call.
+    pub async fn emergency_stop(&self) -> Result<EmergencyStopResult> {
+        let (indices, frames): (Vec<usize>, Vec<Vec<u8>>) = self.safe_outputs().into_iter().unzip();
+        let mut batch = Batch::new(self.priority.clone());
+        for motor in self.motors.iter() {
+            batch = batch.push_idempotent(motor.abrupt_stop_frame());
+        }
+        for frame in frames {
+            batch = batch.push_idempotent(frame);
+        }
+        let mut motors = flush_all(batch, "Emergency stop").await?;
+        let outputs = motors.split_off(self.motors.len());
+        Ok(EmergencyStopResult {
+            motors: MultiResult::new(motors),
+            outputs: MultiResult::indexed(indices.into_iter().zip(outputs)),
+        })
+    }
+
+    //Same as SendRecv::dwell, for sequences that aren't tied to one component
+    pub async fn dwell(&self, duration: Duration) -> Result<()> {
+        dwell(&self.sender, duration).await
+    }
+
+    //Collects commands to go out in a single write, see Batch for ordering and error reporting
+    pub fn batch(&self) -> Batch {
+        Batch::new(self.sender.clone())
+    }
+
+    //The firmware's identity string, e.g. "ClearCore control 1.4.2", logged and checked against
+    //MIN_FIRMWARE_VERSION with a warning when it's older or carries no version. Worth calling
+    //right after the client is spawned so a board running stale firmware shows up before the
+    //first command it doesn't understand. Firmware without the query rejects it.
+    pub async fn query_identity(&self) -> Result<String> {
+        let reply = self.send_raw(&IDENTITY_COMMAND).await?;
+        let payload = ResponseParser::DEVICE.payload(&reply)?;
+        let identity = String::from_utf8_lossy(payload).trim().to_string();
+        if identity.is_empty() {
+            return Err(ControlError::BadResponse(reply));
+        }
+        info!("Controller identifies as {identity:?}");
+        match firmware_version(&identity) {
+            Some(version) if version >= MIN_FIRMWARE_VERSION => {}
+            Some((major, minor, patch)) => {
+                let (min_major, min_minor, min_patch) = MIN_FIRMWARE_VERSION;
+                warn!(
+                    "Controller firmware {major}.{minor}.{patch} is older than \
+                     {min_major}.{min_minor}.{min_patch}, some commands may not be understood"
+                );
+            }
+            None => warn!("Controller identity {identity:?} carries no firmware version"),
+        }
+        Ok(identity)
+    }
+
+    //The outputs at `indices` driven together with one write per change, see OutputGroup
+    pub fn output_group(&self, indices: &[usize]) -> Result<OutputGroup> {
+        let outputs = indices
+            .iter()
+            .map(|&index| {
+                let output = self.outputs.get(index).ok_or_else(|| {
+                    ControlError::InvalidArgument(format!("no output at index {index}"))
+                })?;
+                Ok((index, output.clone()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(OutputGroup::new(self.sender.clone(), outputs))
+    }
+
+    //Advanced: an escape hatch for firmware commands the crate doesn't wrap. `payload` is
+    //everything between STX and CR, e.g. b"M0XY12", and the reply comes back exactly as read
+    //with nothing checked, so telling '?' apart from a value is up to the caller. The command is
+    //never retried since there's no knowing whether it's safe to run twice. A payload holding STX
+    //or CR would break the framing for everything after it and is refused.
+    pub async fn send_raw(&self, payload: &[u8]) -> Result<Vec<u8>> {
+        if payload.iter().any(|byte| *byte == STX || *byte == CR) {
+            return Err(ControlError::InvalidArgument(
+                "raw payload can't contain STX or CR".to_string(),
+            ));
+        }
+        let mut buffer = Vec::with_capacity(payload.len() + 2);
+        buffer.push(STX);
+        buffer.extend_from_slice(payload);
+        buffer.push(CR);
+        let (response, reply) = oneshot::channel();
+        let message = Message {
+            buffer,
+            response,
+            timeout: None,
+            idempotent: false,
+        };
+        self.sender
+            .send(message)
+            .await
+            .map_err(|_| ControlError::Disconnected)?;
+        reply.await.unwrap_or(Err(ControlError::Disconnected))
+    }
+
+    pub fn get_motor(&self, id: impl ComponentId) -> ClearCoreMotor {
+        self.motors[id.index()].clone()
+    }
+
+    pub fn get_motor_by_name(&self, name: &str) -> Option<&ClearCoreMotor> {
+        self.motor_names.get(name).map(|&index| &self.motors[index])
     }
 
     pub fn get_motors(&self) -> Motors {
         self.motors.clone()
     }
 
-    pub fn get_digital_input(&self, id: usize) -> DigitalInput {
-        self.digital_inputs[id].clone()
+    pub fn get_digital_input(&self, id: impl ComponentId) -> DigitalInput {
+        self.digital_inputs[id.index()].clone()
     }
 
     pub fn get_digital_inputs(&self) -> Inputs {
         self.digital_inputs.clone()
     }
 
-    pub fn get_analog_input(&self, id: usize) -> AnalogInput {
-        self.analog_inputs[id].clone()
+    pub fn get_analog_input(&self, id: impl ComponentId) -> AnalogInput {
+        self.analog_inputs[id.index()].clone()
     }
 
     pub fn get_analog_inputs(&self) -> AnalogInputs {
         self.analog_inputs.clone()
     }
-    pub fn get_output(&self, id: usize) -> DigitalOutput {
-        self.outputs[id].clone()
+    pub fn get_output(&self, id: impl ComponentId) -> DigitalOutput {
+        self.outputs[id.index()].clone()
     }
 
     pub fn get_outputs(&self) -> Outputs {
@@ -132,21 +913,440 @@ impl Controller {
     pub fn get_h_bridges(&self) -> HBridges {
         self.h_bridges.clone()
     }
+
+    pub fn motor_count(&self) -> usize {
+        self.motors.len()
+    }
+
+    pub fn digital_input_count(&self) -> usize {
+        self.digital_inputs.len()
+    }
+
+    pub fn analog_input_count(&self) -> usize {
+        self.analog_inputs.len()
+    }
+
+    pub fn output_count(&self) -> usize {
+        self.outputs.len()
+    }
+
+    //Borrowing counterparts to get_motors and friends, in index order
+    pub fn motors(&self) -> impl Iterator<Item = &ClearCoreMotor> {
+        self.motors.iter()
+    }
+
+    pub fn digital_inputs(&self) -> impl Iterator<Item = &DigitalInput> {
+        self.digital_inputs.iter()
+    }
+
+    pub fn analog_inputs(&self) -> impl Iterator<Item = &AnalogInput> {
+        self.analog_inputs.iter()
+    }
+
+    pub fn outputs(&self) -> impl Iterator<Item = &DigitalOutput> {
+        self.outputs.iter()
+    }
+
+    pub async fn enable_all_motors(&self) -> MultiResult<()> {
+        MultiResult::new(
+            join_all(
+                self.motors
+                    .iter()
+                    .map(|motor| async move { motor.enable().await.map(|_| ()) }),
+            )
+            .await,
+        )
+    }
+
+    pub async fn disable_all_motors(&self) -> MultiResult<()> {
+        MultiResult::new(join_all(self.motors.iter().map(|motor| motor.disable())).await)
+    }
+
+    pub async fn stop_all_motors(&self) -> MultiResult<()> {
+        MultiResult::new(join_all(self.motors.iter().map(|motor| motor.stop())).await)
+    }
+
+    pub async fn clear_all_faults(&self) -> MultiResult<()> {
+        MultiResult::new(join_all(self.motors.iter().map(|motor| motor.clear_fault())).await)
+    }
+
+    //Paces every motor's and digital input's waits, including those of handles taken from this
+    //controller from now on. Handles already taken keep what they had.
+    pub fn set_poll_config(&mut self, poll: PollConfig) {
+        self.poll = Some(poll);
+        for motor in self.motors.iter_mut() {
+            *motor = motor.clone().with_poll_config(poll);
+        }
+        for input in self.digital_inputs.iter_mut() {
+            *input = input.clone().with_poll_config(poll);
+        }
+    }
+
+    //Has every motor speak `protocol`, for handles taken from this controller from now on
+    pub fn set_protocol(&mut self, protocol: ProtocolMap) {
+        self.protocol = protocol;
+        for motor in self.motors.iter_mut() {
+            *motor = motor.clone().with_protocol(protocol);
+        }
+    }
+
+    pub fn poll_config(&self) -> PollConfig {
+        self.poll.unwrap_or_default()
+    }
+
+    //Spawns a task polling every motor's status as the poll config says and yields (motor index,
+    //change) whenever a fault appears or clears, motors already faulted show up on the first poll.
+    //A change drops the poll back to its base interval. A failed status read keeps the last known
+    //state. The task stops and the stream ends once the client shuts down or the stream is dropped.
+    pub fn fault_events(&self) -> impl Stream<Item = (usize, MotorFault)> {
+        let (tx, rx) = channel(self.motors.len().max(1));
+        let motors = self.motors.clone();
+        let sender = self.sender.clone();
+        let mut poller = self.poll_config().poller();
+        tokio::spawn(async move {
+            let mut faulted = vec![false; motors.len()];
+            while !sender.is_closed() && !tx.is_closed() {
+                poller.tick().await;
+                let statuses = join_all(motors.iter().map(|motor| motor.get_status())).await;
+                for (index, status) in statuses.into_iter().enumerate() {
+                    let Ok(status) = status else { continue };
+                    if status.faulted == faulted[index] {
+                        continue;
+                    }
+                    faulted[index] = status.faulted;
+                    poller.reset();
+                    let event = if status.faulted {
+                        MotorFault::Raised(status)
+                    } else {
+                        MotorFault::Cleared
+                    };
+                    if tx.send((index, event)).await.is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+        stream::unfold(rx, |mut rx| async move {
+            rx.recv().await.map(|event| (event, rx))
+        })
+    }
+
+    //Not interpolated, the drives only start together, each running its own profile. Every
+    //motor's sequence lock is held while each is checked as move_absolute would and the moves go
+    //out in one write, so no other command can land between them. Anything failing from there on
+    //abruptly stops every axis that may have started. A motor can only appear once.
+    pub async fn move_motors_synchronized(&self, moves: &[(usize, f64)]) -> Result<()> {
+        let motors = moves
+            .iter()
+            .map(|(index, position)| {
+                self.motors
+                    .get(*index)
+                    .map(|motor| (motor, *position))
+                    .ok_or_else(|| {
+                        ControlError::InvalidArgument(format!("no motor at index {index}"))
+                    })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        //Locked in index order so overlapping calls can't each hold a lock the other waits on
+        let mut order: Vec<usize> = moves.iter().map(|(index, _)| *index).collect();
+        order.sort_unstable();
+        if let Some(pair) = order.windows(2).find(|pair| pair[0] == pair[1]) {
+            return Err(ControlError::InvalidArgument(format!(
+                "motor {} is moved twice",
+                pair[0]
+            )));
+        }
+        let mut sequences = Vec::with_capacity(order.len());
+        for index in order {
+            sequences.push(self.motors[index].lock_sequence().await);
+        }
+        join_all(
+            motors
+                .iter()
+                .map(|(motor, position)| motor.check_move_absolute(*position)),
+        )
+        .await
+        .into_iter()
+        .collect::<Result<()>>()?;
+        let mut batch = self.batch();
+        for (motor, position) in motors.iter() {
+            batch = batch.push_idempotent(motor.move_absolute_frame(*position)?);
+        }
+        let replies = match batch.flush().await {
+            Ok(replies) => replies,
+            Err(e) => {
+                //No knowing which of the moves arrived
+                abrupt_stop_all(motors.iter().map(|(motor, _)| *motor)).await;
+                return Err(e);
+            }
+        };
+        drop(sequences);
+        let results: Vec<Result<()>> = motors
+            .iter()
+            .zip(replies)
+            .map(|((motor, _), reply)| reply.and_then(|reply| motor.check_reply(&reply)))
+            .collect();
+        if results.iter().any(|result| result.is_err()) {
+            abrupt_stop_all(
+                motors
+                    .iter()
+                    .zip(results.iter())
+                    .filter(|(_, result)| result.is_ok())
+                    .map(|((motor, _), _)| *motor),
+            )
+            .await;
+            return results.into_iter().collect();
+        }
+        let waits: Result<()> = join_all(
+            motors
+                .iter()
+                .map(|(motor, _)| motor.wait_for_move_complete()),
+        )
+        .await
+        .into_iter()
+        .collect();
+        if waits.is_err() {
+            abrupt_stop_all(motors.iter().map(|(motor, _)| *motor)).await;
+        }
+        waits
+    }
+
+    pub async fn read_all_digital_inputs(&self) -> MultiResult<bool> {
+        MultiResult::new(join_all(self.digital_inputs.iter().map(|input| input.get_state())).await)
+    }
+
+    pub async fn read_all_analog_inputs(&self) -> MultiResult<isize> {
+        MultiResult::new(join_all(self.analog_inputs.iter().map(|input| input.get_state())).await)
+    }
+
+    //Calibrated values in the order of `ids`, read in one batched write so the channels are
+    //sampled back to back. Any failed read fails the whole call.
+    pub async fn read_analog_inputs(&self, ids: &[usize]) -> Result<Vec<f64>> {
+        let inputs = ids
+            .iter()
+            .map(|id| {
+                self.analog_inputs.get(*id).ok_or_else(|| {
+                    ControlError::InvalidArgument(format!("no analog input at index {id}"))
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let mut batch = self.batch();
+        for input in inputs.iter() {
+            batch = batch.push_idempotent(input.read_frame());
+        }
+        inputs
+            .iter()
+            .zip(batch.flush().await?)
+            .map(|(input, reply)| input.scale_reply(&reply?))
+            .collect()
+    }
+
+    //See read_analog_inputs
+    pub async fn read_analog_pair(&self, a: usize, b: usize) -> Result<(f64, f64)> {
+        let values = self.read_analog_inputs(&[a, b]).await?;
+        Ok((values[0], values[1]))
+    }
+
+    pub async fn read_io_snapshot(&self) -> IoSnapshot {
+        let (digital_inputs, analog_inputs) = tokio::join!(
+            self.read_all_digital_inputs(),
+            self.read_all_analog_inputs()
+        );
+        IoSnapshot {
+            digital_inputs,
+            analog_inputs,
+        }
+    }
+
+    //Reads every motor's position and status and every input and output at once. Only fails
+    //when the client is gone, on a live link failed reads just come back as None.
+    pub async fn snapshot(&self) -> Result<ControllerSnapshot> {
+        if self.sender.is_closed() {
+            return Err(ControlError::Disconnected);
+        }
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_millis() as u64);
+        let mut names = vec![None; self.motors.len()];
+        for (name, &index) in self.motor_names.iter() {
+            names[index] = Some(name.clone());
+        }
+        let motors = join_all(
+            self.motors
+                .iter()
+                .zip(names)
+                .map(|(motor, name)| async move {
+                    let (position, status) = tokio::join!(motor.get_position(), motor.get_status());
+                    MotorSnapshot {
+                        id: motor.id(),
+                        name,
+                        position: position.ok(),
+                        status: status.ok(),
+                    }
+                }),
+        );
+        let outputs = join_all(self.outputs.iter().map(|output| output.get_state()));
+        let (motors, io, outputs) = tokio::join!(motors, self.read_io_snapshot(), outputs);
+        Ok(ControllerSnapshot {
+            timestamp_ms,
+            connection: self.connection.get(),
+            motors,
+            digital_inputs: io.digital_inputs.into_options(),
+            analog_inputs: io.analog_inputs.into_options(),
+            outputs: outputs.into_iter().map(|state| state.ok()).collect(),
+        })
+    }
+
+    //Queries every motor's status and reads every input and output once, for a single "is the
+    //machine ready" answer at startup. Every component is tried and ends up in the report, a
+    //motor that answers but is faulted counts as failed. Only fails when the client is gone.
+    pub async fn self_test(&self) -> Result<SelfTestReport> {
+        if self.sender.is_closed() {
+            return Err(ControlError::Disconnected);
+        }
+        let motors = join_all(self.motors.iter().map(|motor| async move {
+            match motor.get_status().await {
+                Ok(status) if status.faulted => Err(ControlError::MotorFault(motor.id())),
+                other => other,
+            }
+        }));
+        let outputs = join_all(self.outputs.iter().map(|output| output.get_state()));
+        let (motors, io, outputs) = tokio::join!(motors, self.read_io_snapshot(), outputs);
+        let checks = |results: MultiResult<_>| -> Vec<ComponentCheck> {
+            results
+                .iter()
+                .map(|(index, result)| ComponentCheck::new(index, result))
+                .collect()
+        };
+        let mut report = SelfTestReport {
+            ready: false,
+            connected: false,
+            connection: self.connection.get(),
+            motors: checks(MultiResult::new(motors)),
+            digital_inputs: checks(io.digital_inputs),
+            analog_inputs: checks(io.analog_inputs),
+            outputs: checks(MultiResult::new(outputs)),
+        };
+        let checked = report.motors.len()
+            + report.digital_inputs.len()
+            + report.analog_inputs.len()
+            + report.outputs.len();
+        let failed = report.failures().count();
+        report.connected = failed < checked;
+        report.ready = report.connected && failed == 0;
+        Ok(report)
+    }
+}
+
+//The first word of `identity` that reads as a dotted version, e.g. "1.4" or "v1.4.2", missing
+//parts count as 0
+pub fn firmware_version(identity: &str) -> Option<(u32, u32, u32)> {
+    identity.split_whitespace().find_map(|word| {
+        let word = word.strip_prefix(['v', 'V']).unwrap_or(word);
+        let mut parts = word.split('.').map(|part| part.parse::<u32>().ok());
+        let major = parts.next()??;
+        let minor = parts.next()??;
+        let patch = parts.next().unwrap_or(Some(0))?;
+        parts.next().is_none().then_some((major, minor, patch))
+    })
+}
+
+fn build_motor(motor: &MotorBuilder, tx: Sender<Message>) -> ClearCoreMotor {
+    let mut clear_core_motor = ClearCoreMotor::new(motor.id, motor.scale, tx)
+        .with_homing(motor.homing.clone())
+        .with_invert(motor.invert)
+        .with_soft_limits(SoftLimits {
+            min_position: motor.min_position,
+            max_position: motor.max_position,
+            mode: motor.limit_mode,
+        })
+        .with_limit_switches(LimitSwitches {
+            positive: motor.positive_limit,
+            negative: motor.negative_limit,
+        });
+    if let Some(velocity) = motor.velocity {
+        clear_core_motor = clear_core_motor.with_velocity_limit(velocity);
+    }
+    if let Some(acceleration) = motor.acceleration {
+        clear_core_motor = clear_core_motor.with_acceleration(acceleration);
+    }
+    if let Some(deceleration) = motor.deceleration {
+        clear_core_motor = clear_core_motor.with_deceleration(deceleration);
+    }
+    clear_core_motor
 }
 
-pub async fn get_all_motor_states(controller: Controller) -> Vec<Status> {
-    let mut statuses = Vec::with_capacity(controller.motors.len());
+//Halts the axes of a synchronized move that went wrong, a motor that won't stop is only logged
+//since the move's own error is what gets reported
+async fn abrupt_stop_all(motors: impl Iterator<Item = &ClearCoreMotor>) {
+    let motors: Vec<&ClearCoreMotor> = motors.collect();
+    let stops = join_all(motors.iter().map(|motor| motor.abrupt_stop())).await;
+    for (motor, stopped) in motors.iter().zip(stops) {
+        if let Err(e) = stopped {
+            error!("Motor {} failed to stop: {e}", motor.id());
+        }
+    }
+}
+
+//Every command in the batch is sent regardless, each failure is logged and kept in its entry
+async fn flush_all(batch: Batch, action: &str) -> Result<Vec<Result<()>>> {
+    let results = batch
+        .flush()
+        .await
+        .inspect_err(|e| error!("{action} failed to reach the controller: {e}"))?;
+    Ok(results
+        .into_iter()
+        .map(|result| {
+            result
+                .map(|_| ())
+                .inspect_err(|e| error!("{action} command failed: {e}"))
+        })
+        .collect())
+}
+
+//Reads are spawned so they run in parallel, each status is still reported against its own motor
+//index whichever order the replies arrive in
+pub async fn get_all_motor_states(controller: Controller) -> MultiResult<MotorStatus> {
     let mut set = JoinSet::new();
-    let motors = controller.get_motors();
-    motors.into_iter().for_each(|motor| {
+    for (index, motor) in controller.get_motors().iter().enumerate() {
         let motor = motor.clone();
-        set.spawn(async move { motor.get_status().await });
-    });
-
+        set.spawn(async move { (index, motor.get_status().await) });
+    }
+    let mut statuses = Vec::with_capacity(set.len());
     while let Some(result) = set.join_next().await {
         statuses.push(result.unwrap());
     }
-    statuses
+    statuses.sort_by_key(|(index, _)| *index);
+    MultiResult::new(statuses.into_iter().map(|(_, status)| status))
+}
+
+#[test]
+fn test_check_result() {
+    assert!(check_result(b"\x02M0_\r").is_ok());
+    assert!(check_result(b"\x02I31\r").is_ok());
+    assert!(check_result(b"\x02M").is_ok());
+    assert!(matches!(
+        check_result(b"\x02M0?\r"),
+        Err(ControlError::CommandRejected(_))
+    ));
+    assert_eq!(ResultCode::from_byte(b'_'), Some(ResultCode::Ack));
+    assert_eq!(ResultCode::from_byte(b'7'), None);
+}
+
+#[test]
+fn test_message_debug() {
+    let (response, _reply) = oneshot::channel();
+    let msg = Message {
+        buffer: b"\x02M0GS\r".to_vec(),
+        response,
+        timeout: Some(Duration::from_millis(20)),
+        idempotent: true,
+    };
+    assert_eq!(msg.hex_dump(), "02<STX> 4d 30 47 53 0d<CR>");
+    assert_eq!(
+        format!("{msg:?}"),
+        "Message { buffer: 02<STX> 4d 30 47 53 0d<CR>, timeout: Some(20ms), idempotent: true, .. }"
+    );
 }
 
 #[tokio::test]
@@ -154,17 +1354,33 @@ async fn test_controller() {
     let (tx, mut rx) = channel::<Message>(100);
 
     let motors = [
-        MotorBuilder { id: 0, scale: 800 },
-        MotorBuilder { id: 1, scale: 800 },
-        MotorBuilder { id: 2, scale: 800 },
-        MotorBuilder { id: 3, scale: 800 },
+        MotorBuilder {
+            id: 0,
+            scale: 800,
+            ..Default::default()
+        },
+        MotorBuilder {
+            id: 1,
+            scale: 800,
+            ..Default::default()
+        },
+        MotorBuilder {
+            id: 2,
+            scale: 800,
+            ..Default::default()
+        },
+        MotorBuilder {
+            id: 3,
+            scale: 800,
+            ..Default::default()
+        },
     ];
 
     let mock_client = tokio::spawn(async move {
         if let Some(msg) = rx.recv().await {
             assert_eq!(*msg.buffer.get(0).unwrap(), 0x02);
             assert_eq!(*msg.buffer.get(1).unwrap(), b'M');
-            if msg.response.send(msg.buffer).is_err() {
+            if msg.response.send(Ok(msg.buffer)).is_err() {
                 eprintln!("Unable to send Response");
             }
         }
@@ -181,16 +1397,786 @@ async fn test_controller() {
     controller_task_1.await.unwrap();
 }
 
+#[test]
+fn test_get_motor_by_name() {
+    let (tx, _rx) = channel::<Message>(10);
+    let motors = [
+        MotorBuilder {
+            id: 0,
+            scale: 800,
+            name: Some("gantry".to_string()),
+            ..Default::default()
+        },
+        MotorBuilder {
+            id: 1,
+            scale: 800,
+            ..Default::default()
+        },
+        MotorBuilder {
+            id: 2,
+            scale: 200,
+            name: Some("hatch".to_string()),
+            ..Default::default()
+        },
+    ];
+    let controller = Controller::new(tx, motors.as_slice());
+    assert!(controller.get_motor_by_name("gantry").is_some());
+    assert!(controller.get_motor_by_name("hatch").is_some());
+    assert!(controller.get_motor_by_name("pump").is_none());
+}
+
+#[test]
+fn test_config_round_trip() {
+    use crate::components::clear_core_motor::{HomeMode, HomingDirection};
+
+    let config: ControllerConfig =
+        toml::from_str(include_str!("../../controller.example.toml")).unwrap();
+    assert_eq!(config.addr, "192.168.1.11:8888");
+    assert_eq!(config.connect_timeout_ms, Some(2000));
+    assert_eq!(config.layout.outputs, 8);
+    assert_eq!(config.layout.digital_inputs, NO_DIGITAL_INPUTS);
+    assert_eq!(config.motors.len(), 3);
+    assert_eq!(config.motors[0].name.as_deref(), Some("gantry"));
+    assert_eq!(config.motors[2].velocity, Some(2.5));
+    assert_eq!(config.motors[1].positive_limit, Some(1));
+    let homing = &config.motors[0].homing;
+    assert_eq!(homing.direction, HomingDirection::Positive);
+    assert_eq!(homing.timeout, Duration::from_secs(20));
+    assert_eq!(
+        homing.mode,
+        HomeMode::HardStop {
+            torque_percent: 30.,
+            backoff: 0.5,
+            velocity: 2.,
+        }
+    );
+    assert_eq!(config.motors[1].homing, HomingConfig::default());
+    let poll = config.poll.unwrap();
+    assert_eq!(poll.interval, Duration::from_millis(10));
+    assert_eq!(poll.max_interval, Duration::from_millis(100));
+
+    let serialized = toml::to_string(&config).unwrap();
+    let reparsed: ControllerConfig = toml::from_str(serialized.as_str()).unwrap();
+    assert_eq!(config, reparsed);
+}
+
+#[test]
+fn test_controller_layout() {
+    let (tx, _rx) = channel::<Message>(10);
+    let layout = ControllerLayout {
+        outputs: 8,
+        ..Default::default()
+    };
+    let controller = Controller::with_layout(tx, &[], layout);
+    assert_eq!(controller.get_outputs().len(), 8);
+    assert_eq!(controller.get_digital_inputs().len(), NO_DIGITAL_INPUTS);
+    assert_eq!(controller.get_analog_inputs().len(), NO_ANALOG_INPUTS);
+}
+
+#[tokio::test]
+async fn test_snapshot() {
+    use crate::testing::MockClearCore;
+
+    let mock = MockClearCore::start().await.unwrap();
+    mock.on(b"M0GP", b"1600")
+        .on(b"M0GS", b"3233")
+        .on(b"I1", b"1")
+        .on(b"O", b"0")
+        .on(b"O2GS", b"32700");
+    let motors = [MotorBuilder {
+        id: 0,
+        scale: 800,
+        name: Some("gantry".to_string()),
+        ..Default::default()
+    }];
+    let (controller, client) = Controller::with_client(mock.addr(), motors.as_slice());
+    let handle = tokio::spawn(client);
+
+    let snapshot = controller.snapshot().await.unwrap();
+    assert!(snapshot.timestamp_ms > 0);
+    assert_eq!(snapshot.connection, ConnectionState::Connected);
+    assert_eq!(snapshot.motors[0].name.as_deref(), Some("gantry"));
+    assert_eq!(snapshot.motors[0].position, Some(2.0));
+    assert!(snapshot.motors[0].status.unwrap().at_target);
+    assert_eq!(snapshot.digital_inputs.len(), NO_DIGITAL_INPUTS);
+    assert_eq!(snapshot.digital_inputs[1], Some(true));
+    assert_eq!(snapshot.analog_inputs.len(), NO_ANALOG_INPUTS);
+    assert_eq!(snapshot.outputs.len(), NO_OUTPUTS);
+    assert_eq!(
+        snapshot.outputs[..3],
+        [Some(false), Some(false), Some(true)]
+    );
+
+    drop(controller);
+    handle.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_self_test() {
+    use crate::testing::MockClearCore;
+
+    let mock = MockClearCore::start().await.unwrap();
+    mock.on(b"M0GS", b"3233")
+        .on(b"M1GS", b"2064")
+        .on(b"I2", b"?");
+    let motors = [
+        MotorBuilder {
+            id: 0,
+            scale: 800,
+            ..Default::default()
+        },
+        MotorBuilder {
+            id: 1,
+            scale: 800,
+            ..Default::default()
+        },
+    ];
+    let (controller, client) = Controller::with_client(mock.addr(), motors.as_slice());
+    let handle = tokio::spawn(client);
+
+    let report = controller.self_test().await.unwrap();
+    assert!(report.connected);
+    assert!(!report.ready);
+    assert!(report.motors[0].ok);
+    assert_eq!(
+        report.motors[1].error.as_deref(),
+        Some("Motor 1 reported a fault")
+    );
+    assert!(!report.digital_inputs[2].ok);
+    assert_eq!(report.digital_inputs.len(), NO_DIGITAL_INPUTS);
+    let failed: Vec<usize> = report.failures().map(|check| check.index).collect();
+    assert_eq!(failed, [1, 2]);
+
+    drop(controller);
+    handle.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_controller_guard() {
+    use crate::testing::MockClearCore;
+
+    let mock = MockClearCore::start().await.unwrap();
+    let motors = [
+        MotorBuilder {
+            id: 0,
+            scale: 800,
+            ..Default::default()
+        },
+        MotorBuilder {
+            id: 1,
+            scale: 800,
+            ..Default::default()
+        },
+    ];
+    let (controller, client) = Controller::with_client(mock.addr(), motors.as_slice());
+    let handle = tokio::spawn(client);
+
+    //Disarmed, nothing goes out
+    let guard = ControllerGuard::new(controller);
+    let controller = guard.disarm();
+    assert!(mock.received().is_empty());
+
+    let guard = ControllerGuard::new(controller);
+    assert!(guard.get_motor(0).enable().await.is_ok());
+    drop(guard);
+    handle.await.unwrap().unwrap();
+    let received = mock.received();
+    assert_eq!(
+        received[received.len() - 2..],
+        [b"\x02M0ST\r".to_vec(), b"\x02M1ST\r".to_vec()]
+    );
+}
+
+#[tokio::test]
+async fn test_query_identity() {
+    use crate::testing::MockClearCore;
+
+    assert_eq!(
+        firmware_version("ClearCore control v1.4.2"),
+        Some((1, 4, 2))
+    );
+    assert_eq!(firmware_version("sketch 2.1 build 7"), Some((2, 1, 0)));
+    assert_eq!(firmware_version("ClearCore"), None);
+
+    let mock = MockClearCore::start().await.unwrap();
+    mock.on(b"VR", b"ClearCore control 0.9.1");
+    let (controller, client) = Controller::with_client(mock.addr(), &[]);
+    let handle = tokio::spawn(client);
+    assert_eq!(
+        controller.query_identity().await.unwrap(),
+        "ClearCore control 0.9.1"
+    );
+    mock.on(b"VR", b"?");
+    assert!(matches!(
+        controller.query_identity().await,
+        Err(ControlError::CommandRejected(_))
+    ));
+    assert_eq!(mock.received()[0], b"\x02VR\r");
+
+    drop(controller);
+    handle.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_read_io_snapshot() {
+    use crate::error::ControlError;
+
+    let (tx, mut rx) = channel::<Message>(10);
+    let controller = Controller::new(tx, &[]);
+    let mock = tokio::spawn(async move {
+        while let Some(msg) = rx.recv().await {
+            //Input 1 is unplugged, the rest read their own index
+            let id = msg.buffer[2];
+            let reply = if id == b'1' {
+                Err(ControlError::Timeout)
+            } else {
+                Ok(vec![STX, b'I', id, id, CR])
+            };
+            msg.response.send(reply).unwrap();
+        }
+    });
+    let snapshot = controller.read_io_snapshot().await;
+    assert_eq!(snapshot.digital_inputs.len(), NO_DIGITAL_INPUTS);
+    assert!(!*snapshot.digital_inputs[0].as_ref().unwrap());
+    assert!(matches!(
+        snapshot.digital_inputs[1],
+        Err(ControlError::Timeout)
+    ));
+    assert_eq!(*snapshot.analog_inputs[0].as_ref().unwrap(), 3);
+    assert_eq!(*snapshot.analog_inputs[3].as_ref().unwrap(), 6);
+    drop(controller);
+    mock.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_move_motors_synchronized() {
+    use crate::interface::transport::split_frames;
+
+    let (tx, mut rx) = channel::<Message>(10);
+    let motors = [
+        MotorBuilder {
+            id: 0,
+            scale: 800,
+            ..Default::default()
+        },
+        MotorBuilder {
+            id: 1,
+            scale: 800,
+            ..Default::default()
+        },
+    ];
+    let controller = Controller::new(tx, motors.as_slice());
+    let mock = tokio::spawn(async move {
+        let mut writes = Vec::new();
+        while let Some(msg) = rx.recv().await {
+            let reply: Vec<u8> = split_frames(&msg.buffer)
+                .into_iter()
+                .flat_map(|frame| match &frame[3..5] {
+                    //Ready and at target
+                    b"GS" => [&frame[..3], &b"3233\r"[..]].concat(),
+                    _ if frame == b"\x02M1AM-800\r" => vec![STX, b'M', b'1', b'?', CR],
+                    _ => vec![STX, b'M', frame[2], b'_', CR],
+                })
+                .collect();
+            writes.push(msg.buffer);
+            msg.response.send(Ok(reply)).unwrap();
+        }
+        writes
+    });
+    controller
+        .move_motors_synchronized(&[(0, 10.0), (1, -5.0)])
+        .await
+        .unwrap();
+    //A rejected move stops the axis that did start
+    assert!(matches!(
+        controller
+            .move_motors_synchronized(&[(0, 2.0), (1, -1.0)])
+            .await,
+        Err(ControlError::CommandRejected(_))
+    ));
+    assert!(matches!(
+        controller.move_motors_synchronized(&[(7, 1.0)]).await,
+        Err(ControlError::InvalidArgument(_))
+    ));
+    assert!(matches!(
+        controller
+            .move_motors_synchronized(&[(1, 1.0), (1, 2.0)])
+            .await,
+        Err(ControlError::InvalidArgument(_))
+    ));
+    drop(controller);
+    let writes = mock.await.unwrap();
+    //Both moves go out in one write between the enable checks and the completion polls
+    assert_eq!(writes[2], b"\x02M0AM8000\r\x02M1AM-4000\r");
+    assert_eq!(writes.len(), 9);
+    assert_eq!(writes[7], b"\x02M0AM1600\r\x02M1AM-800\r");
+    assert_eq!(writes[8], b"\x02M0AS\r");
+}
+
+#[tokio::test]
+async fn test_emergency_stop() {
+    use crate::testing::MockClearCore;
+
+    let mock = MockClearCore::start().await.unwrap();
+    let motors = [
+        MotorBuilder {
+            id: 0,
+            scale: 800,
+            ..Default::default()
+        },
+        MotorBuilder {
+            id: 1,
+            scale: 800,
+            ..Default::default()
+        },
+    ];
+    let (controller, client) = Controller::with_client(mock.addr(), motors.as_slice());
+    let controller = controller
+        .with_safe_output(2, false)
+        .with_safe_output(3, true);
+    let handle = tokio::spawn(client);
+
+    assert!(controller.emergency_stop().await.unwrap().is_ok());
+    assert_eq!(
+        mock.received(),
+        [
+            b"\x02M0AS\r".to_vec(),
+            b"\x02M1AS\r".to_vec(),
+            b"\x02O20\r".to_vec(),
+            b"\x02O332700\r".to_vec(),
+        ]
+    );
+
+    //A rejected stop is reported against its motor, the rest still went out
+    mock.on(b"M1AS", b"?");
+    let stopped = controller.emergency_stop().await.unwrap();
+    assert!(stopped.motors[0].is_ok());
+    assert!(matches!(
+        stopped.motors[1],
+        Err(ControlError::CommandRejected(_))
+    ));
+    assert!(stopped.outputs.is_ok());
+    let outputs: Vec<usize> = stopped.outputs.iter().map(|(index, _)| index).collect();
+    assert_eq!(outputs, [2, 3]);
+    assert_eq!(mock.received().len(), 8);
+
+    drop(controller);
+    handle.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_apply_safe_states() {
+    use crate::testing::MockClearCore;
+
+    let mock = MockClearCore::start().await.unwrap();
+    let config: ControllerConfig = toml::from_str(
+        format!(
+            r#"
+            addr = "{}"
+            motors = []
+            [[safe_outputs]]
+            id = 1
+            [[safe_outputs]]
+            id = 4
+            duty = 20
+            "#,
+            mock.addr()
+        )
+        .as_str(),
+    )
+    .unwrap();
+    let (controller, client) = Controller::from_config(&config);
+    let handle = tokio::spawn(client);
+    let applied = controller.apply_safe_states().await.unwrap();
+    assert!(applied.is_ok());
+    let outputs: Vec<usize> = applied.iter().map(|(index, _)| index).collect();
+    assert_eq!(outputs, [1, 4]);
+    assert_eq!(
+        mock.received(),
+        [b"\x02O10\r".to_vec(), b"\x02P451\r".to_vec()]
+    );
+    assert_eq!(controller.safe_states.get(), mock.received());
+
+    drop(controller);
+    handle.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_client_capacity_backpressure() {
+    use tokio::net::TcpListener;
+
+    //A controller that never answers keeps the client stuck on the first command
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        std::future::pending::<()>().await;
+        drop(stream);
+    });
+    let motors = [MotorBuilder {
+        id: 0,
+        scale: 800,
+        ..Default::default()
+    }];
+    let (controller, client) = Controller::with_client_capacity(addr, motors.as_slice(), 2);
+    let handle = tokio::spawn(client);
+
+    let mut calls = JoinSet::new();
+    for _ in 0..4 {
+        let motor = controller.get_motor(0);
+        calls.spawn(async move { motor.get_status().await });
+    }
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    //One in flight, two queued and the last caller parked in send waiting for room
+    assert_eq!(controller.sender.capacity(), 0);
+    assert!(calls.try_join_next().is_none());
+
+    calls.abort_all();
+    handle.abort();
+    server.abort();
+}
+
+#[tokio::test]
+async fn test_enable_disable_all() {
+    use crate::testing::MockClearCore;
+
+    let mock = MockClearCore::start().await.unwrap();
+    mock.on(b"M1EN", b"?");
+    let motors: Vec<MotorBuilder> = (0..3)
+        .map(|id| MotorBuilder {
+            id,
+            scale: 800,
+            ..Default::default()
+        })
+        .collect();
+    let (controller, client) = Controller::with_client(mock.addr(), motors.as_slice());
+    let handle = tokio::spawn(client);
+
+    //The motors after the one that failed are still enabled
+    let enabled = controller.enable_all_motors().await;
+    let failures: Vec<_> = enabled.failures().collect();
+    assert_eq!(failures.len(), 1);
+    assert!(matches!(failures[0], (1, ControlError::CommandRejected(_))));
+    assert!(controller.disable_all_motors().await.is_ok());
+    let mut received = mock.received();
+    received.sort();
+    assert_eq!(
+        received,
+        [
+            b"\x02M0DE\r".to_vec(),
+            b"\x02M0EN\r".to_vec(),
+            b"\x02M1DE\r".to_vec(),
+            b"\x02M1EN\r".to_vec(),
+            b"\x02M2DE\r".to_vec(),
+            b"\x02M2EN\r".to_vec(),
+        ]
+    );
+
+    drop(controller);
+    handle.await.unwrap().unwrap();
+}
+
+#[test]
+fn test_counts() {
+    let (tx, _rx) = channel::<Message>(10);
+    let motors = [
+        MotorBuilder {
+            id: 0,
+            scale: 800,
+            ..Default::default()
+        },
+        MotorBuilder {
+            id: 2,
+            scale: 200,
+            ..Default::default()
+        },
+    ];
+    let layout = ControllerLayout {
+        digital_inputs: 2,
+        analog_inputs: 1,
+        outputs: 8,
+    };
+    let controller = Controller::with_layout(tx, motors.as_slice(), layout);
+    assert_eq!(controller.motor_count(), 2);
+    assert_eq!(controller.digital_input_count(), 2);
+    assert_eq!(controller.analog_input_count(), 1);
+    assert_eq!(controller.output_count(), 8);
+    assert_eq!(
+        controller
+            .motors()
+            .map(|motor| motor.id())
+            .collect::<Vec<_>>(),
+        [0, 2]
+    );
+    assert_eq!(controller.outputs().count(), 8);
+}
+
+#[tokio::test]
+async fn test_add_components() {
+    use crate::testing::MockClearCore;
+
+    let mock = MockClearCore::start().await.unwrap();
+    mock.on(b"M5GP", b"1600").on(b"I7", b"1");
+    let motors = [MotorBuilder {
+        id: 0,
+        scale: 800,
+        ..Default::default()
+    }];
+    let (mut controller, client) = Controller::with_client(mock.addr(), motors.as_slice());
+    let handle = tokio::spawn(client);
+
+    let index = controller.add_motor(MotorBuilder {
+        id: 5,
+        scale: 400,
+        name: Some("auger".to_string()),
+        ..Default::default()
+    });
+    assert_eq!(index, 1);
+    assert_eq!(controller.motor_count(), 2);
+    assert_eq!(
+        controller.get_motor(index).get_position().await.unwrap(),
+        4.0
+    );
+    assert_eq!(controller.get_motor_by_name("auger").unwrap().id(), 5);
+
+    let input = controller.add_input(7);
+    assert_eq!(input, NO_DIGITAL_INPUTS);
+    assert!(controller
+        .get_digital_input(input)
+        .get_state()
+        .await
+        .unwrap());
+    let output = controller.add_output(9);
+    assert_eq!(output, NO_OUTPUTS);
+    controller.get_output(output).set_state(true).await;
+    assert_eq!(mock.received().last().unwrap().as_slice(), b"\x02O932700\r");
+
+    drop(controller);
+    handle.await.unwrap().unwrap();
+}
+
+#[test]
+fn test_component_ids() {
+    component_ids! {
+        enum Motor {
+            Gantry = 0,
+            Hatch = 1,
+        }
+    }
+    let (tx, _rx) = channel::<Message>(10);
+    let motors = [
+        MotorBuilder {
+            id: 4,
+            scale: 800,
+            ..Default::default()
+        },
+        MotorBuilder {
+            id: 5,
+            scale: 800,
+            ..Default::default()
+        },
+    ];
+    let controller = Controller::new(tx, motors.as_slice());
+    assert_eq!(controller.get_motor(Motor::Gantry).id(), 4);
+    assert_eq!(controller.get_motor(Motor::Hatch).id(), 5);
+    assert_eq!(controller.get_motor(1).id(), 5);
+}
+
+#[tokio::test]
+async fn test_wait_does_not_hold_other_motors() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use tokio::sync::Mutex;
+
+    let (tx, mut rx) = channel::<Message>(10);
+    let motors = [0, 1].map(|id| MotorBuilder {
+        id,
+        scale: 800,
+        ..Default::default()
+    });
+    let controller = Arc::new(Mutex::new(Controller::new(tx, motors.as_slice())));
+    let arrived = Arc::new(AtomicBool::new(false));
+    let mock = {
+        let arrived = arrived.clone();
+        tokio::spawn(async move {
+            let mut sent = Vec::new();
+            while let Some(msg) = rx.recv().await {
+                //Motor 0 keeps moving until motor 1 has been given its move
+                let reply: &[u8] = match msg.buffer.as_slice() {
+                    b"\x02M0GS\r" if !arrived.load(Ordering::Relaxed) => b"\x02M04130\r",
+                    b"\x02M0GS\r" | b"\x02M1GS\r" => b"\x02M03233\r",
+                    _ => {
+                        arrived.store(true, Ordering::Relaxed);
+                        b"\x02M1_\r"
+                    }
+                };
+                sent.push(msg.buffer.clone());
+                msg.response.send(Ok(reply.to_vec())).unwrap();
+            }
+            sent
+        })
+    };
+
+    let motor = controller
+        .lock()
+        .await
+        .get_motor(0)
+        .with_poll_interval(Duration::from_millis(5));
+    let waiting = tokio::spawn(async move { motor.wait_for_move_complete().await });
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    assert!(!waiting.is_finished());
+    let other = controller.lock().await.get_motor(1);
+    tokio::time::timeout(Duration::from_millis(100), other.move_absolute(1.0))
+        .await
+        .unwrap()
+        .unwrap();
+    waiting.await.unwrap().unwrap();
+
+    //Two tasks moving the same motor each get their status check and move out back to back
+    let first = controller.lock().await.get_motor(1);
+    let second = first.clone();
+    let (a, b) = tokio::join!(first.move_absolute(2.0), second.move_absolute(3.0));
+    a.unwrap();
+    b.unwrap();
+    drop((controller, other, first, second));
+    let sent = mock.await.unwrap();
+    let tail: Vec<&[u8]> = sent[sent.len() - 4..].iter().map(Vec::as_slice).collect();
+    assert_eq!(
+        tail,
+        [
+            &b"\x02M1GS\r"[..],
+            b"\x02M1AM1600\r",
+            b"\x02M1GS\r",
+            b"\x02M1AM2400\r"
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_fault_events() {
+    use futures::StreamExt;
+
+    let (tx, mut rx) = channel::<Message>(10);
+    let motors = [MotorBuilder {
+        id: 0,
+        scale: 800,
+        ..Default::default()
+    }];
+    let mut controller = Controller::new(tx, motors.as_slice());
+    controller.set_poll_config(PollConfig::fixed(Duration::from_millis(1)));
+    let mock = tokio::spawn(async move {
+        //Jams, stays jammed for a poll, then comes back
+        for status in [&b"3233"[..], b"2064", b"2064", b"3233", b"3233"] {
+            let msg = rx.recv().await.unwrap();
+            let mut reply = b"\x02M0".to_vec();
+            reply.extend_from_slice(status);
+            reply.push(CR);
+            msg.response.send(Ok(reply)).unwrap();
+        }
+        //Dropping the receiver is the client going away, which ends the stream
+    });
+    let events: Vec<(usize, MotorFault)> = controller.fault_events().collect().await;
+    assert_eq!(events.len(), 2);
+    assert!(matches!(events[0], (0, MotorFault::Raised(status)) if status.faulted));
+    assert_eq!(events[1], (0, MotorFault::Cleared));
+    mock.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_connection_state() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    async fn read_frame(stream: &mut tokio::net::TcpStream) {
+        let mut byte = [0; 1];
+        while stream.read(&mut byte).await.unwrap() == 1 && byte[0] != CR {}
+    }
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (go_tx, go_rx) = oneshot::channel();
+    let server = tokio::spawn(async move {
+        let (mut first, _) = listener.accept().await.unwrap();
+        read_frame(&mut first).await;
+        drop(first);
+        //Holding back the reply to the safe state keeps the client reconnecting
+        let (mut second, _) = listener.accept().await.unwrap();
+        read_frame(&mut second).await;
+        go_rx.await.unwrap();
+        second.write_all(b"\x02O0_\r").await.unwrap();
+        while second.read(&mut [0; 16]).await.is_ok_and(|n| n > 0) {}
+    });
+
+    let config = ClientConfig {
+        min_backoff: Duration::from_millis(1),
+        ..Default::default()
+    };
+    let (controller, client) = Controller::with_client_config(addr, &[], config);
+    let controller = controller.with_safe_output(0, false);
+    let mut state = controller.connection_state();
+    assert_eq!(*state.borrow(), ConnectionState::Disconnected);
+    let handle = tokio::spawn(client);
+
+    assert!(controller.get_digital_input(0).get_state().await.is_err());
+    state
+        .wait_for(|state| *state == ConnectionState::Reconnecting)
+        .await
+        .unwrap();
+    go_tx.send(()).unwrap();
+    state
+        .wait_for(|state| *state == ConnectionState::Connected)
+        .await
+        .unwrap();
+
+    drop(controller);
+    handle.await.unwrap().unwrap();
+    assert_eq!(*state.borrow(), ConnectionState::Disconnected);
+    server.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_shutdown_stops_motors() {
+    use crate::testing::MockClearCore;
+
+    let mock = MockClearCore::start().await.unwrap();
+    let motors = [
+        MotorBuilder {
+            id: 0,
+            scale: 800,
+            ..Default::default()
+        },
+        MotorBuilder {
+            id: 1,
+            scale: 800,
+            ..Default::default()
+        },
+    ];
+    let (controller, client, handle) =
+        Controller::with_shutdown_client(mock.addr(), motors.as_slice(), ClientConfig::default());
+    let client = tokio::spawn(client);
+    let results = handle.shutdown(true).await;
+    assert!(results.is_ok());
+    assert_eq!(results.len(), 2);
+    //The client ends even though the controller still holds its senders
+    client.await.unwrap().unwrap();
+    assert_eq!(
+        mock.received(),
+        vec![b"\x02M0ST\r".to_vec(), b"\x02M1ST\r".to_vec()]
+    );
+    assert!(matches!(
+        controller.get_motor(0).stop().await,
+        Err(ControlError::Disconnected)
+    ));
+}
+
 #[tokio::test]
 async fn test_controller_with_client() {
+    use crate::testing::MockClearCore;
     use env_logger::Env;
     use log::{error, info};
-    use std::net::SocketAddr;
     use std::sync::atomic::AtomicBool;
     use std::sync::Arc;
-    use tokio::io::{AsyncReadExt, AsyncWriteExt};
     use tokio::join;
-    use tokio::net::TcpListener;
     use tokio::sync::Mutex;
     use tokio::time::{sleep, Duration};
 
@@ -198,31 +2184,36 @@ async fn test_controller_with_client() {
     //We need this MotorBuilder struct to inject the motor scale into the controller, the id part is
     //Kind of unnecessary, but it might be valuable for having named ids in ryo-os
     let motors = [
-        MotorBuilder { id: 0, scale: 800 },
-        MotorBuilder { id: 1, scale: 800 },
-        MotorBuilder { id: 2, scale: 800 },
-        MotorBuilder { id: 3, scale: 800 },
+        MotorBuilder {
+            id: 0,
+            scale: 800,
+            ..Default::default()
+        },
+        MotorBuilder {
+            id: 1,
+            scale: 800,
+            ..Default::default()
+        },
+        MotorBuilder {
+            id: 2,
+            scale: 800,
+            ..Default::default()
+        },
+        MotorBuilder {
+            id: 3,
+            scale: 800,
+            ..Default::default()
+        },
     ];
 
-    let mut reply_buffer = [0; 128];
-
-    let server_task = tokio::spawn(async move {
-        let addr = SocketAddr::from(([127, 0, 0, 1], 8888));
-        let listener = TcpListener::bind(addr).await.unwrap();
-        let (mut stream, _) = listener.accept().await.unwrap();
-        stream.read(reply_buffer.as_mut_slice()).await.unwrap();
-        assert_eq!(reply_buffer[0], 0x02);
-        assert_eq!(reply_buffer[1], b'M');
-        let reply = [2, reply_buffer[1], reply_buffer[2], b'_'];
-        stream.write_all(reply.as_slice()).await.unwrap();
-    });
+    let mock = MockClearCore::start().await.unwrap();
     let shutdown = Arc::new(AtomicBool::new(false));
     signal_hook::flag::register(signal_hook::consts::SIGINT, Arc::clone(&shutdown))
         .expect("Register hook");
 
     //controller returns its rx that we can use it in its partner client actor, I'm debating whether
     //Instead of returning a rx we can return a future that can be plugged into spawn directly but
-    let (controller, client) = Controller::with_client("127.0.0.1:8888", motors.as_slice());
+    let (controller, client) = Controller::with_client(mock.addr(), motors.as_slice());
 
     let cc1 = Arc::new(Mutex::from(controller));
     let task_1_cc_1 = cc1.clone();
@@ -243,8 +2234,10 @@ async fn test_controller_with_client() {
             {
                 let input = cc1.lock().await.get_digital_input(0);
                 info!("Lock Acquired from input task");
-                let state = input.get_state().await;
-                info!("{state}");
+                match input.get_state().await {
+                    Ok(state) => info!("{state}"),
+                    Err(e) => error!("Input failed to read {:?}", e),
+                }
             }
             tokio::time::sleep(Duration::from_secs(1)).await;
         }
@@ -252,10 +2245,60 @@ async fn test_controller_with_client() {
 
     //We can start a task with the returned client ensuring that we always use the right client
     let mock_client = tokio::spawn(client);
-    let _ = join!(
-        mock_client,
-        controller_task_1,
-        controller_task_2,
-        server_task
+    let _ = join!(mock_client, controller_task_1, controller_task_2);
+}
+
+#[tokio::test]
+async fn test_send_raw() {
+    use crate::testing::MockClearCore;
+
+    let mock = MockClearCore::start().await.unwrap();
+    mock.on(b"M0XY", b"?").on(b"Z1", b"42");
+    let (controller, client) = Controller::with_client(mock.addr(), &[]);
+    let handle = tokio::spawn(client);
+
+    assert_eq!(controller.send_raw(b"Z1").await.unwrap(), b"\x02Z142\r");
+    //A rejection is handed back as is
+    assert_eq!(controller.send_raw(b"M0XY12").await.unwrap(), b"\x02M0?\r");
+    let result = controller.send_raw(b"Z1\rZ2").await;
+    assert!(matches!(result, Err(ControlError::InvalidArgument(_))));
+    assert_eq!(
+        mock.received(),
+        [b"\x02Z1\r".to_vec(), b"\x02M0XY12\r".to_vec()]
     );
+
+    drop(controller);
+    handle.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_read_analog_inputs() {
+    use crate::testing::MockClearCore;
+
+    let mock = MockClearCore::start().await.unwrap();
+    mock.on(b"I3", b"1000").on(b"I4", b"3000");
+    let (mut controller, client) = Controller::with_client(mock.addr(), &[]);
+    let handle = tokio::spawn(client);
+    //Analog input 1 is I4
+    let calibrated = 1;
+    controller.analog_inputs[calibrated] =
+        AnalogInput::new(4, controller.sender.clone()).with_calibration(0.01, -5.);
+
+    let (low, high) = controller.read_analog_pair(0, calibrated).await.unwrap();
+    assert_eq!((low, high), (1000., 25.));
+    assert_eq!(
+        mock.received(),
+        [b"\x02I3\r".to_vec(), b"\x02I4\r".to_vec()]
+    );
+
+    let result = controller.read_analog_inputs(&[0, 9]).await;
+    assert!(matches!(result, Err(ControlError::InvalidArgument(_))));
+    mock.on(b"I4", b"?");
+    assert!(matches!(
+        controller.read_analog_inputs(&[0, calibrated]).await,
+        Err(ControlError::CommandRejected(_))
+    ));
+
+    drop(controller);
+    handle.await.unwrap().unwrap();
 }
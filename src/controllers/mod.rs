@@ -1,2 +1,6 @@
+pub mod batch;
 pub mod clear_core;
 pub mod ek1100_io;
+pub mod output_group;
+pub mod registry;
+pub mod sequence;
@@ -0,0 +1,95 @@
+use crate::components::clear_core_io::DigitalOutput;
+use crate::controllers::batch::Batch;
+use crate::controllers::clear_core::Message;
+use crate::error::{ControlError, MultiResult, Result};
+use tokio::sync::mpsc::Sender;
+
+//Outputs driven together in one batched write, e.g. a bank of valves, made with
+//Controller::output_group. Results are in group order tagged with each output's index, a rejected
+//output only fails its own entry.
+#[derive(Clone)]
+pub struct OutputGroup {
+    sender: Sender<Message>,
+    outputs: Vec<(usize, DigitalOutput)>,
+}
+
+impl OutputGroup {
+    pub(crate) fn new(sender: Sender<Message>, outputs: Vec<(usize, DigitalOutput)>) -> Self {
+        Self { sender, outputs }
+    }
+
+    //In group order
+    pub fn indices(&self) -> impl Iterator<Item = usize> + '_ {
+        self.outputs.iter().map(|(index, _)| *index)
+    }
+
+    pub fn len(&self) -> usize {
+        self.outputs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.outputs.is_empty()
+    }
+
+    pub async fn set_all(&self, state: bool) -> Result<MultiResult<()>> {
+        self.send(self.outputs.iter().map(|_| state)).await
+    }
+
+    //One state per member
+    pub async fn set_pattern(&self, pattern: &[bool]) -> Result<MultiResult<()>> {
+        if pattern.len() != self.outputs.len() {
+            return Err(ControlError::InvalidArgument(format!(
+                "pattern has {} states for {} outputs",
+                pattern.len(),
+                self.outputs.len()
+            )));
+        }
+        self.send(pattern.iter().copied()).await
+    }
+
+    async fn send(&self, states: impl Iterator<Item = bool>) -> Result<MultiResult<()>> {
+        let mut batch = Batch::new(self.sender.clone());
+        for ((_, output), state) in self.outputs.iter().zip(states) {
+            batch = batch.push_idempotent(output.state_frame(state));
+        }
+        let replies = batch.flush().await?;
+        Ok(MultiResult::indexed(
+            self.indices()
+                .zip(replies)
+                .map(|(index, reply)| (index, reply.map(|_| ()))),
+        ))
+    }
+}
+
+#[tokio::test]
+async fn test_output_group() {
+    use crate::controllers::clear_core::Controller;
+    use crate::testing::MockClearCore;
+
+    let mock = MockClearCore::start().await.unwrap();
+    mock.on(b"O3", b"?");
+    let (controller, client) = Controller::with_client(mock.addr(), &[]);
+    let handle = tokio::spawn(client);
+
+    let group = controller.output_group(&[0, 3, 4]).unwrap();
+    assert!(controller.output_group(&[0, 99]).is_err());
+    let results = group.set_all(true).await.unwrap();
+    assert!(results[0].is_ok());
+    let failed: Vec<usize> = results.failures().map(|(index, _)| index).collect();
+    assert_eq!(failed, [3]);
+    assert!(group.set_pattern(&[true, false, true]).await.is_ok());
+    assert!(matches!(
+        group.set_pattern(&[true]).await,
+        Err(ControlError::InvalidArgument(_))
+    ));
+
+    let received = mock.received();
+    assert_eq!(received.len(), 6);
+    assert!(received[..3]
+        .iter()
+        .all(|frame| frame.ends_with(b"32700\r")));
+    assert_eq!(received[4], b"\x02O30\r");
+
+    drop((controller, group));
+    handle.await.unwrap().unwrap();
+}
@@ -0,0 +1,143 @@
+use crate::controllers::clear_core::{Controller, ControllerConfig};
+use crate::error::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::future::Future;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tokio::task::JoinSet;
+
+type ClientFuture = Pin<Box<dyn Future<Output = Result<()>> + Send>>;
+
+//Several ClearCore boards keyed by name, e.g. [controllers.front] and [controllers.back] each
+//holding a ControllerConfig
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RegistryConfig {
+    pub controllers: HashMap<String, ControllerConfig>,
+}
+
+//Holds every controller by name along with the client futures that haven't been spawned yet
+#[derive(Default)]
+pub struct ControllerRegistry {
+    controllers: HashMap<String, Arc<Mutex<Controller>>>,
+    clients: Vec<(String, ClientFuture)>,
+}
+
+impl ControllerRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_config(config: &RegistryConfig) -> Self {
+        let mut registry = Self::new();
+        for (name, controller_config) in config.controllers.iter() {
+            let (controller, client) = Controller::from_config(controller_config);
+            registry.insert_with_client(name.clone(), controller, client);
+        }
+        registry
+    }
+
+    pub fn from_config_file(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)?;
+        let config: RegistryConfig = toml::from_str(contents.as_str())?;
+        Ok(Self::from_config(&config))
+    }
+
+    //For controllers whose client is already running elsewhere
+    pub fn insert(
+        &mut self,
+        name: impl Into<String>,
+        controller: Controller,
+    ) -> Arc<Mutex<Controller>> {
+        let controller = Arc::new(Mutex::new(controller));
+        self.controllers.insert(name.into(), controller.clone());
+        controller
+    }
+
+    pub fn insert_with_client(
+        &mut self,
+        name: impl Into<String>,
+        controller: Controller,
+        client: impl Future<Output = Result<()>> + Send + 'static,
+    ) -> Arc<Mutex<Controller>> {
+        let name = name.into();
+        self.clients.push((name.clone(), Box::pin(client)));
+        self.insert(name, controller)
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<Mutex<Controller>>> {
+        self.controllers.get(name).cloned()
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.controllers.keys().map(String::as_str)
+    }
+
+    //Spawns every pending client, each task yields its controller's name with the client's result
+    //so a dropped board can be told apart from the others
+    pub fn spawn_clients(&mut self) -> JoinSet<(String, Result<()>)> {
+        let mut set = JoinSet::new();
+        for (name, client) in self.clients.drain(..) {
+            set.spawn(async move { (name, client.await) });
+        }
+        set
+    }
+}
+
+#[tokio::test]
+async fn test_registry_from_config() {
+    use crate::testing::MockClearCore;
+
+    let front = MockClearCore::start().await.unwrap();
+    let back = MockClearCore::start().await.unwrap();
+    front.on(b"I0", b"1");
+    let config: RegistryConfig = toml::from_str(
+        format!(
+            r#"
+            [controllers.front]
+            addr = "{}"
+            [[controllers.front.motors]]
+            id = 0
+            scale = 800
+
+            [controllers.back]
+            addr = "{}"
+            motors = []
+            "#,
+            front.addr(),
+            back.addr()
+        )
+        .as_str(),
+    )
+    .unwrap();
+    let mut registry = ControllerRegistry::from_config(&config);
+    let mut clients = registry.spawn_clients();
+
+    let mut names: Vec<&str> = registry.names().collect();
+    names.sort();
+    assert_eq!(names, ["back", "front"]);
+    assert!(registry.get("side").is_none());
+
+    let front_input = registry
+        .get("front")
+        .unwrap()
+        .lock()
+        .await
+        .get_digital_input(0);
+    let back_input = registry
+        .get("back")
+        .unwrap()
+        .lock()
+        .await
+        .get_digital_input(0);
+    assert!(front_input.get_state().await.unwrap());
+    assert!(!back_input.get_state().await.unwrap());
+
+    drop((front_input, back_input, registry));
+    while let Some(result) = clients.join_next().await {
+        let (_, result) = result.unwrap();
+        result.unwrap();
+    }
+}
@@ -0,0 +1,152 @@
+use crate::controllers::clear_core::Controller;
+use crate::error::{ControlError, Result};
+use log::{error, info};
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Step {
+    Enable(usize),
+    MoveTo(usize, f64),
+    WaitComplete(usize),
+    SetOutput(usize, bool),
+    Dwell(Duration),
+}
+
+//A recipe of steps run one after another against a Controller, motors and outputs by their index.
+//run stops at the first failed step and reports it as StepFailed with the step's index. Holds no
+//state, so the same sequence can be run any number of times.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Sequence {
+    steps: Vec<Step>,
+}
+
+impl Sequence {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn enable(self, motor: usize) -> Self {
+        self.step(Step::Enable(motor))
+    }
+
+    //Goes straight on to the next step, follow it with wait_complete to wait for the motor
+    pub fn move_to(self, motor: usize, position: f64) -> Self {
+        self.step(Step::MoveTo(motor, position))
+    }
+
+    pub fn wait_complete(self, motor: usize) -> Self {
+        self.step(Step::WaitComplete(motor))
+    }
+
+    pub fn set_output(self, output: usize, state: bool) -> Self {
+        self.step(Step::SetOutput(output, state))
+    }
+
+    pub fn dwell(self, duration: Duration) -> Self {
+        self.step(Step::Dwell(duration))
+    }
+
+    pub fn step(mut self, step: Step) -> Self {
+        self.steps.push(step);
+        self
+    }
+
+    pub fn steps(&self) -> &[Step] {
+        &self.steps
+    }
+
+    pub async fn run(&self, controller: &Controller) -> Result<()> {
+        //A bad index is caught before anything moves rather than halfway through the recipe
+        self.validate(controller)?;
+        for (index, step) in self.steps.iter().enumerate() {
+            info!("Sequence step {index}: {step:?}");
+            if let Err(e) = run_step(controller, step).await {
+                error!("Sequence step {index} ({step:?}) failed: {e}");
+                return Err(ControlError::StepFailed(index, Box::new(e)));
+            }
+        }
+        Ok(())
+    }
+
+    fn validate(&self, controller: &Controller) -> Result<()> {
+        let motors = controller.motor_count();
+        let outputs = controller.output_count();
+        for (index, step) in self.steps.iter().enumerate() {
+            let in_range = match *step {
+                Step::Enable(motor) | Step::MoveTo(motor, _) | Step::WaitComplete(motor) => {
+                    motor < motors
+                }
+                Step::SetOutput(output, _) => output < outputs,
+                Step::Dwell(_) => true,
+            };
+            if !in_range {
+                let e = ControlError::InvalidArgument(format!("{step:?} is out of range"));
+                return Err(ControlError::StepFailed(index, Box::new(e)));
+            }
+        }
+        Ok(())
+    }
+}
+
+async fn run_step(controller: &Controller, step: &Step) -> Result<()> {
+    match *step {
+        Step::Enable(motor) => controller.get_motor(motor).enable().await.map(|_| ()),
+        Step::MoveTo(motor, position) => controller.get_motor(motor).move_absolute(position).await,
+        Step::WaitComplete(motor) => controller.get_motor(motor).wait_for_move_complete().await,
+        Step::SetOutput(output, state) => controller.get_output(output).send_state(state).await,
+        Step::Dwell(duration) => controller.dwell(duration).await,
+    }
+}
+
+#[tokio::test]
+async fn test_sequence() {
+    use crate::controllers::clear_core::MotorBuilder;
+    use crate::testing::MockClearCore;
+
+    let mock = MockClearCore::start().await.unwrap();
+    mock.on(b"M0GS", b"3233");
+    let motors = [MotorBuilder {
+        id: 0,
+        scale: 800,
+        ..Default::default()
+    }];
+    let (controller, client) = Controller::with_client(mock.addr(), motors.as_slice());
+    let handle = tokio::spawn(client);
+
+    let sequence = Sequence::new()
+        .enable(0)
+        .move_to(0, 1.0)
+        .wait_complete(0)
+        .set_output(1, true)
+        .dwell(Duration::from_millis(1))
+        .set_output(1, false);
+    sequence.run(&controller).await.unwrap();
+    assert_eq!(
+        mock.received(),
+        [
+            b"\x02M0EN\r".to_vec(),
+            b"\x02M0GS\r".to_vec(),
+            b"\x02M0AM800\r".to_vec(),
+            b"\x02M0GS\r".to_vec(),
+            b"\x02O132700\r".to_vec(),
+            b"\x02O10\r".to_vec(),
+        ]
+    );
+
+    //A rejected move stops the sequence there, the output is never switched
+    mock.on(b"M0AM", b"?");
+    let result = Sequence::new()
+        .move_to(0, 2.0)
+        .set_output(1, true)
+        .run(&controller)
+        .await;
+    assert!(matches!(result, Err(ControlError::StepFailed(0, _))));
+    assert_eq!(mock.received().len(), 8);
+
+    let result = Sequence::new().enable(0).enable(3).run(&controller).await;
+    assert!(matches!(result, Err(ControlError::StepFailed(1, _))));
+    assert_eq!(mock.received().len(), 8);
+
+    drop(controller);
+    handle.await.unwrap().unwrap();
+}
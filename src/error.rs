@@ -0,0 +1,173 @@
+use std::io;
+use std::ops::Index;
+use std::time::Duration;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum ControlError {
+    #[error("Controller did not reply in time")]
+    Timeout,
+    #[error("Connection to controller was lost")]
+    Disconnected,
+    #[error("Client is shutting down")]
+    Shutdown,
+    #[error("Command queue is full")]
+    Busy,
+    #[error("Motor {0} reported a fault")]
+    MotorFault(u8),
+    #[error("Motor {0} is still faulted after clearing alerts")]
+    StillFaulted(u8),
+    #[error("Motor {0} did not report ready after enabling")]
+    EnableTimeout(u8),
+    #[error("Motor {0} got no answer to its enable within {1:?}, is the controller connected and running the sketch?")]
+    EnableUnanswered(u8, Duration),
+    #[error("Motor {0} is not enabled")]
+    NotEnabled(u8),
+    #[error("Motor {0} target {1} is outside its soft limits")]
+    OutOfBounds(u8, f64),
+    #[error("Motor {0} hit the limit switch on input {1}")]
+    LimitTripped(u8, u8),
+    #[error("Motor {0} failed to home")]
+    HomingFailed(u8),
+    #[error("Malformed response from controller: {0:?}")]
+    BadResponse(Vec<u8>),
+    #[error("Command rejected by controller: {0:?}")]
+    CommandRejected(Vec<u8>),
+    #[error("Reply ran past {0} bytes without a CR")]
+    FrameTooLong(usize),
+    #[error("Checksum mismatch in reply: {0:?}")]
+    ChecksumError(Vec<u8>),
+    #[error("Motor {0} recovery failed while {1}: {2}")]
+    RecoveryFailed(u8, &'static str, Box<ControlError>),
+    #[error("Sequence step {0} failed: {1}")]
+    StepFailed(usize, Box<ControlError>),
+    #[error("Invalid argument: {0}")]
+    InvalidArgument(String),
+    #[error("Invalid controller config: {0}")]
+    Config(#[from] toml::de::Error),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+pub type Result<T> = std::result::Result<T, ControlError>;
+
+//What came of an operation fanned out over several motors or IO points, one entry per component
+//tagged with its index on the controller. Every component is tried, so one failure never hides
+//whether the others went through. Indexing with [i] gives component i's result.
+#[derive(Debug)]
+pub struct MultiResult<T> {
+    results: Vec<(usize, Result<T>)>,
+}
+
+impl<T> MultiResult<T> {
+    //Results in component index order, the first is index 0
+    pub fn new(results: impl IntoIterator<Item = Result<T>>) -> Self {
+        Self {
+            results: results.into_iter().enumerate().collect(),
+        }
+    }
+
+    //For components that aren't numbered from 0, e.g. a subset of the outputs. Indexing with [i]
+    //then gives the i-th entry, iter() and failures() still report each one's own index.
+    pub(crate) fn indexed(results: impl IntoIterator<Item = (usize, Result<T>)>) -> Self {
+        Self {
+            results: results.into_iter().collect(),
+        }
+    }
+
+    //True when every component succeeded, including when there were none
+    pub fn is_ok(&self) -> bool {
+        self.results.iter().all(|(_, result)| result.is_ok())
+    }
+
+    pub fn len(&self) -> usize {
+        self.results.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.results.is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&Result<T>> {
+        self.results.get(index).map(|(_, result)| result)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &Result<T>)> {
+        self.results.iter().map(|(index, result)| (*index, result))
+    }
+
+    pub fn successes(&self) -> impl Iterator<Item = (usize, &T)> {
+        self.iter()
+            .filter_map(|(index, result)| result.as_ref().ok().map(|value| (index, value)))
+    }
+
+    pub fn failures(&self) -> impl Iterator<Item = (usize, &ControlError)> {
+        self.iter()
+            .filter_map(|(index, result)| result.as_ref().err().map(|e| (index, e)))
+    }
+
+    //Every value in index order with None wherever that component failed
+    pub fn into_options(self) -> Vec<Option<T>> {
+        self.results
+            .into_iter()
+            .map(|(_, result)| result.ok())
+            .collect()
+    }
+
+    //Every value in index order, or the failed components' indices and errors if any failed
+    pub fn into_result(self) -> std::result::Result<Vec<T>, Vec<(usize, ControlError)>> {
+        let mut values = Vec::with_capacity(self.results.len());
+        let mut failures = Vec::new();
+        for (index, result) in self.results {
+            match result {
+                Ok(value) => values.push(value),
+                Err(e) => failures.push((index, e)),
+            }
+        }
+        if failures.is_empty() {
+            Ok(values)
+        } else {
+            Err(failures)
+        }
+    }
+}
+
+impl<T> Index<usize> for MultiResult<T> {
+    type Output = Result<T>;
+
+    fn index(&self, index: usize) -> &Self::Output {
+        &self.results[index].1
+    }
+}
+
+impl<T> IntoIterator for MultiResult<T> {
+    type Item = (usize, Result<T>);
+    type IntoIter = std::vec::IntoIter<(usize, Result<T>)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.results.into_iter()
+    }
+}
+
+#[test]
+fn test_multi_result() {
+    let results = MultiResult::new([Ok(1), Err(ControlError::Timeout), Ok(3)]);
+    assert!(!results.is_ok());
+    assert_eq!(results.len(), 3);
+    assert!(matches!(results[1], Err(ControlError::Timeout)));
+    assert_eq!(results.successes().collect::<Vec<_>>(), [(0, &1), (2, &3)]);
+    assert_eq!(
+        results
+            .failures()
+            .map(|(index, _)| index)
+            .collect::<Vec<_>>(),
+        [1]
+    );
+    let failures = results.into_result().unwrap_err();
+    assert!(matches!(failures[..], [(1, ControlError::Timeout)]));
+
+    let results = MultiResult::new([Ok(()), Ok(())]);
+    assert!(results.is_ok());
+    assert_eq!(results.into_result().unwrap().len(), 2);
+    assert!(MultiResult::<()>::new([]).is_ok());
+}
@@ -0,0 +1,194 @@
+use crate::util::utils::to_hex;
+use std::future::Future;
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::fs::OpenOptions;
+use tokio::io::{AsyncWrite, AsyncWriteExt, BufWriter};
+use tokio::sync::mpsc;
+use tracing::warn;
+
+//Records that can wait for the writer before new ones are dropped, a slow disk never holds up the
+//client
+const CAPACITY: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+impl Direction {
+    fn as_str(self) -> &'static str {
+        match self {
+            Direction::Sent => "sent",
+            Direction::Received => "received",
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Record {
+    ts: SystemTime,
+    direction: Direction,
+    bytes: Vec<u8>,
+    latency: Option<Duration>,
+}
+
+impl Record {
+    //{"ts": unix millis, "direction": "sent" or "received", "bytes_hex": "02 4d ...",
+    //"latency_ms": millis since the command went out, null for sent frames}
+    fn to_json(&self) -> String {
+        let ts = self
+            .ts
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        let latency = match self.latency {
+            Some(latency) => format!("{:.3}", latency.as_secs_f64() * 1000.),
+            None => "null".to_string(),
+        };
+        format!(
+            r#"{{"ts":{ts},"direction":"{}","bytes_hex":"{}","latency_ms":{latency}}}"#,
+            self.direction.as_str(),
+            to_hex(&self.bytes)
+        )
+    }
+}
+
+//An audit trail of every frame the client writes and every reply it reads, as newline delimited
+//JSON, for when tracing output isn't durable enough. Hand one to ClientConfig::recorder. The
+//client only copies the bytes into a queue, formatting and writing happen on the writer task. If
+//the writer falls CAPACITY records behind new ones are dropped and counted in dropped(). Bytes are
+//as they went over the wire, checksums included, replies to sequence id commands without the id.
+#[derive(Debug, Clone)]
+pub struct Recorder {
+    tx: mpsc::Sender<Record>,
+    dropped: Arc<AtomicUsize>,
+}
+
+impl Recorder {
+    //The returned future is the writer task and has to be spawned or driven alongside the client.
+    //Records are flushed whenever the queue runs dry, and the task ends once every clone of the
+    //recorder is gone and the rest is written out.
+    pub fn new<W: AsyncWrite + Unpin + Send>(
+        writer: W,
+    ) -> (Self, impl Future<Output = io::Result<()>> + Send) {
+        let (tx, rx) = mpsc::channel(CAPACITY);
+        let recorder = Self {
+            tx,
+            dropped: Arc::new(AtomicUsize::new(0)),
+        };
+        (recorder, write_records(rx, writer))
+    }
+
+    //Appends to `path`, creating it if needed, so restarts add to the same trail
+    pub async fn to_file(
+        path: impl AsRef<Path>,
+    ) -> io::Result<(Self, impl Future<Output = io::Result<()>> + Send)> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await?;
+        Ok(Self::new(file))
+    }
+
+    //Records lost because the writer couldn't keep up or had failed
+    pub fn dropped(&self) -> usize {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn sent(&self, bytes: &[u8]) {
+        self.record(Direction::Sent, bytes, None);
+    }
+
+    pub(crate) fn received(&self, bytes: &[u8], latency: Option<Duration>) {
+        self.record(Direction::Received, bytes, latency);
+    }
+
+    fn record(&self, direction: Direction, bytes: &[u8], latency: Option<Duration>) {
+        let record = Record {
+            ts: SystemTime::now(),
+            direction,
+            bytes: bytes.to_vec(),
+            latency,
+        };
+        if self.tx.try_send(record).is_err() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+async fn write_records<W: AsyncWrite + Unpin>(
+    mut rx: mpsc::Receiver<Record>,
+    writer: W,
+) -> io::Result<()> {
+    let mut writer = BufWriter::new(writer);
+    while let Some(record) = rx.recv().await {
+        let mut record = Some(record);
+        while let Some(next) = record {
+            let mut line = next.to_json();
+            line.push('\n');
+            if let Err(e) = writer.write_all(line.as_bytes()).await {
+                warn!(error = %e, "Audit trail write failed, recording stopped");
+                return Err(e);
+            }
+            record = rx.try_recv().ok();
+        }
+        writer.flush().await?;
+    }
+    writer.flush().await
+}
+
+#[tokio::test]
+async fn test_recorder() {
+    use crate::controllers::clear_core::{Message, CR, STX};
+    use crate::interface::tcp::{client_with_config, ClientConfig};
+    use crate::testing::MockClearCore;
+    use tokio::sync::oneshot;
+
+    let path = std::env::temp_dir().join(format!("audit-{}.ndjson", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let mock = MockClearCore::start().await.unwrap();
+    mock.on(b"I1", b"1");
+    let (recorder, writer) = Recorder::to_file(&path).await.unwrap();
+    let writer = tokio::spawn(writer);
+    let (tx, rx) = mpsc::channel::<Message>(10);
+    let config = ClientConfig {
+        recorder: Some(recorder.clone()),
+        ..Default::default()
+    };
+    let client_handle = tokio::spawn(client_with_config(mock.addr(), rx, config));
+
+    let (response, reply) = oneshot::channel();
+    tx.send(Message {
+        buffer: vec![STX, b'I', b'1', CR],
+        response,
+        timeout: None,
+        idempotent: false,
+    })
+    .await
+    .unwrap();
+    reply.await.unwrap().unwrap();
+    drop(tx);
+    client_handle.await.unwrap().unwrap();
+    assert_eq!(recorder.dropped(), 0);
+    drop(recorder);
+    writer.await.unwrap().unwrap();
+
+    let contents = std::fs::read_to_string(&path).unwrap();
+    let lines: Vec<&str> = contents.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0].starts_with(r#"{"ts":"#));
+    assert!(
+        lines[0].ends_with(r#","direction":"sent","bytes_hex":"02 49 31 0d","latency_ms":null}"#)
+    );
+    assert!(
+        lines[1].contains(r#""direction":"received","bytes_hex":"02 49 31 31 0d","latency_ms":"#)
+    );
+    assert!(!lines[1].ends_with("null}"));
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,419 @@
+use crate::controllers::clear_core::{Message, CR};
+use crate::error::{ControlError, Result};
+use crate::interface::transport::{
+    drain_queues, encode, frames, heartbeat, hex_digit, recover, recv_priority, verify_checksum,
+    ClientConfig, Queues, Transport, WriteSpacing, READ_CHUNK,
+};
+use crate::telemetry;
+use crate::util::utils::to_hex;
+use std::collections::HashMap;
+use std::future::Future;
+use std::io;
+use tokio::sync::oneshot;
+use tokio::time::{sleep_until, Instant};
+use tracing::{debug, info, warn};
+
+//Caps how many commands can be waiting on a reply before new ones are left in the queue
+const MAX_IN_FLIGHT: usize = 16;
+//Frames carry their id as '#' and four hex digits just ahead of the checksum and CR
+const SEQUENCE_MARK: u8 = b'#';
+const SEQUENCE_LEN: usize = 5;
+
+//A message waiting on its replies, a batch holds one id per frame and completes with the last
+struct InFlight {
+    response: oneshot::Sender<Result<Vec<u8>>>,
+    ids: Vec<u16>,
+    replies: Vec<Option<Vec<u8>>>,
+    deadline: Instant,
+    buffer: Vec<u8>,
+    sent_at: Instant,
+}
+
+#[derive(Default)]
+struct Pending {
+    //Keyed by the id of the message's first frame
+    messages: HashMap<u16, InFlight>,
+    //Every outstanding frame id to its message and its index within it
+    frames: HashMap<u16, (u16, usize)>,
+    next_id: u16,
+}
+
+impl Pending {
+    fn next_id(&mut self) -> u16 {
+        while self.frames.contains_key(&self.next_id) {
+            self.next_id = self.next_id.wrapping_add(1);
+        }
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        id
+    }
+
+    fn sent_at(&self, id: u16) -> Option<Instant> {
+        let (first, _) = self.frames.get(&id)?;
+        self.messages.get(first).map(|message| message.sent_at)
+    }
+
+    fn next_deadline(&self) -> Option<Instant> {
+        self.messages.values().map(|message| message.deadline).min()
+    }
+
+    fn remove(&mut self, first: u16) -> Option<InFlight> {
+        let message = self.messages.remove(&first)?;
+        for id in message.ids.iter() {
+            self.frames.remove(id);
+        }
+        Some(message)
+    }
+
+    fn complete(&mut self, id: u16, reply: Vec<u8>) {
+        let Some(&(first, index)) = self.frames.get(&id) else {
+            warn!(id, reply = %to_hex(&reply), "Reply for an unknown or expired sequence id");
+            return;
+        };
+        let Some(message) = self.messages.get_mut(&first) else {
+            return;
+        };
+        message.replies[index] = Some(reply);
+        if message.replies.iter().any(Option::is_none) {
+            return;
+        }
+        let message = self.remove(first).unwrap();
+        let reply: Vec<u8> = message.replies.into_iter().flatten().flatten().collect();
+        debug!(id, reply = %to_hex(&reply), "Received reply");
+        telemetry::record_command(&message.buffer, message.sent_at.elapsed());
+        if message.response.send(Ok(reply)).is_err() {
+            warn!("Failed to send via channel");
+        }
+    }
+
+    fn expire(&mut self, now: Instant) {
+        let expired: Vec<u16> = self
+            .messages
+            .iter()
+            .filter(|(_, message)| message.deadline <= now)
+            .map(|(&first, _)| first)
+            .collect();
+        for first in expired {
+            if let Some(message) = self.remove(first) {
+                //No reset needed, a late reply is recognised by its id and dropped
+                warn!(id = first, "No reply in time");
+                telemetry::record_timeout();
+                let _ = message.response.send(Err(ControlError::Timeout));
+            }
+        }
+    }
+
+    fn fail_all(&mut self, error: fn() -> ControlError) {
+        self.frames.clear();
+        for (_, message) in self.messages.drain() {
+            let _ = message.response.send(Err(error()));
+        }
+    }
+}
+
+enum Event {
+    Shutdown,
+    Message(Message),
+    PriorityClosed,
+    Closed,
+    Read(io::Result<usize>),
+    Expired,
+    Heartbeat,
+    //The gap after the last write is up
+    Spaced,
+}
+
+//Counterpart to the strict ordering client for firmware that echoes sequence ids, commands go out
+//as soon as they're queued and each reply is routed to its waiter by id in whatever order it
+//arrives. A timed out command just fails on its own, the connection is only reset when it is
+//actually lost. On shutdown commands still waiting on a reply are failed with Shutdown.
+pub(crate) async fn serve_correlated<T: Transport>(
+    mut transport: T,
+    queues: Queues,
+    config: ClientConfig,
+    shutdown: impl Future<Output = ()> + Send,
+) -> Result<()> {
+    let mut read_buffer = Vec::with_capacity(READ_CHUNK);
+    let mut outgoing = Vec::with_capacity(READ_CHUNK);
+    let mut chunk = [0; READ_CHUNK];
+    let mut pending = Pending::default();
+    let mut last_activity = Instant::now();
+    let mut spacing = WriteSpacing::new(config.min_write_interval);
+    let mut closed = false;
+    let Queues {
+        normal: mut msg,
+        mut priority,
+    } = queues;
+    tokio::pin!(shutdown);
+    loop {
+        if closed && pending.messages.is_empty() {
+            break;
+        }
+        //Replies keep being read while a write waits out its gap
+        let next_write = spacing.next_write();
+        let room = pending.messages.len() < MAX_IN_FLIGHT && next_write.is_none();
+        let deadline = pending.next_deadline();
+        let heartbeat_due = config
+            .heartbeat
+            .filter(|_| pending.messages.is_empty())
+            .map(|interval| last_activity + interval);
+        let event = tokio::select! {
+            biased;
+            _ = &mut shutdown => Event::Shutdown,
+            message = recv_priority(priority.as_mut()), if room => {
+                message.map_or(Event::PriorityClosed, Event::Message)
+            }
+            message = msg.recv(), if room && !closed => message.map_or(Event::Closed, Event::Message),
+            read = transport.read(&mut chunk) => Event::Read(read),
+            _ = sleep_until_some(deadline) => Event::Expired,
+            _ = sleep_until_some(heartbeat_due) => Event::Heartbeat,
+            _ = sleep_until_some(next_write) => Event::Spaced,
+        };
+        let failure = match event {
+            Event::Shutdown => {
+                pending.fail_all(|| ControlError::Shutdown);
+                drain_queues(&mut msg, priority.as_mut()).await;
+                info!("Client shut down");
+                return Ok(());
+            }
+            Event::PriorityClosed => {
+                priority = None;
+                None
+            }
+            Event::Closed => {
+                closed = true;
+                None
+            }
+            Event::Message(message) => {
+                last_activity = Instant::now();
+                spacing.wrote();
+                send(
+                    &mut transport,
+                    &config,
+                    &mut pending,
+                    &mut outgoing,
+                    message,
+                )
+                .await
+                .err()
+            }
+            Event::Read(Ok(0)) => Some(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Connection closed by server",
+            )),
+            Event::Read(Ok(n)) => {
+                last_activity = Instant::now();
+                read_buffer.extend_from_slice(&chunk[..n]);
+                let mut frame = Vec::new();
+                while config.framing.take_frame_into(&mut read_buffer, &mut frame) {
+                    route(&config, &mut pending, std::mem::take(&mut frame));
+                }
+                //There's no telling whose reply it was, its waiter times out
+                config
+                    .framing
+                    .discard_oversized(&mut read_buffer, config.max_frame_len);
+                None
+            }
+            Event::Read(Err(e)) => Some(e),
+            Event::Expired => {
+                pending.expire(Instant::now());
+                None
+            }
+            Event::Spaced => None,
+            Event::Heartbeat => {
+                //Only sent while nothing is outstanding, so the plain ordered probe is safe
+                spacing.wait().await;
+                spacing.wrote();
+                last_activity = Instant::now();
+                heartbeat(&mut transport, &config, &mut read_buffer)
+                    .await
+                    .err()
+            }
+        };
+        if let Some(e) = failure {
+            warn!(error = %e, "Lost connection");
+            pending.fail_all(|| ControlError::Disconnected);
+            recover(&mut transport, &config, &mut read_buffer, e).await?;
+            last_activity = Instant::now();
+        }
+    }
+    Ok(())
+}
+
+async fn sleep_until_some(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
+async fn send<T: Transport>(
+    transport: &mut T,
+    config: &ClientConfig,
+    pending: &mut Pending,
+    outgoing: &mut Vec<u8>,
+    message: Message,
+) -> io::Result<()> {
+    let mut ids = Vec::new();
+    let mut tagged = Vec::new();
+    for frame in frames(&message.buffer) {
+        let id = pending.next_id();
+        ids.push(id);
+        tag(frame, id, &mut tagged);
+    }
+    let Some(&first) = ids.first() else {
+        let _ = message.response.send(Err(ControlError::InvalidArgument(
+            "message holds no complete frame".to_string(),
+        )));
+        return Ok(());
+    };
+    encode(config, &tagged, outgoing);
+    let timeout = message
+        .timeout
+        .unwrap_or(config.command_timeout * ids.len() as u32);
+    debug!(id = first, frame = %to_hex(outgoing), "Sending frame");
+    let sent_at = Instant::now();
+    if let Err(e) = transport.write(outgoing).await {
+        let _ = message.response.send(Err(ControlError::Disconnected));
+        return Err(e);
+    }
+    if let Some(recorder) = &config.recorder {
+        recorder.sent(outgoing);
+    }
+    for (index, &id) in ids.iter().enumerate() {
+        pending.frames.insert(id, (first, index));
+    }
+    pending.messages.insert(
+        first,
+        InFlight {
+            response: message.response,
+            replies: vec![None; ids.len()],
+            ids,
+            deadline: sent_at + timeout,
+            buffer: message.buffer,
+            sent_at,
+        },
+    );
+    Ok(())
+}
+
+fn route(config: &ClientConfig, pending: &mut Pending, frame: Vec<u8>) {
+    let frame = if config.checksum {
+        //A corrupted id can't be trusted, its waiter times out instead
+        match verify_checksum(frame) {
+            Ok(frame) => frame,
+            Err(_) => return,
+        }
+    } else {
+        frame
+    };
+    match untag(frame) {
+        Ok((id, reply)) => {
+            if let Some(recorder) = &config.recorder {
+                let latency = pending.sent_at(id).map(|sent_at| sent_at.elapsed());
+                recorder.received(&reply, latency);
+            }
+            pending.complete(id, reply)
+        }
+        Err(frame) => warn!(frame = %to_hex(&frame), "Reply without a sequence id"),
+    }
+}
+
+//Appends the frame with "#XXXX" inserted ahead of its CR
+fn tag(frame: &[u8], id: u16, out: &mut Vec<u8>) {
+    let body = frame.strip_suffix(&[CR]).unwrap_or(frame);
+    out.extend_from_slice(body);
+    out.push(SEQUENCE_MARK);
+    out.extend(
+        (0..4)
+            .rev()
+            .map(|shift| hex_digit((id >> (shift * 4)) as u8 & 0x0f)),
+    );
+    out.push(CR);
+}
+
+//Splits the id back off a reply, handing the frame back when it doesn't carry one
+fn untag(mut frame: Vec<u8>) -> std::result::Result<(u16, Vec<u8>), Vec<u8>> {
+    let Some(mark) = frame.len().checked_sub(SEQUENCE_LEN + 1) else {
+        return Err(frame);
+    };
+    if frame[mark] != SEQUENCE_MARK {
+        return Err(frame);
+    }
+    let id = std::str::from_utf8(&frame[mark + 1..mark + SEQUENCE_LEN])
+        .ok()
+        .and_then(|digits| u16::from_str_radix(digits, 16).ok());
+    match id {
+        Some(id) => {
+            frame.drain(mark..mark + SEQUENCE_LEN);
+            Ok((id, frame))
+        }
+        None => Err(frame),
+    }
+}
+
+#[test]
+fn test_tag_round_trip() {
+    let mut tagged = Vec::new();
+    tag(b"\x02M0GS\r", 0x1a2b, &mut tagged);
+    assert_eq!(tagged, b"\x02M0GS#1A2B\r");
+    assert_eq!(untag(tagged).unwrap(), (0x1a2b, b"\x02M0GS\r".to_vec()));
+    assert!(untag(b"\x02M0_\r".to_vec()).is_err());
+}
+
+#[tokio::test]
+async fn test_interleaved_replies() {
+    use crate::interface::tcp::client_with_config;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = tokio::spawn(async move {
+        //Both commands are outstanding before either is answered, then they're answered in
+        //reverse order with the ids echoed back
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut received = Vec::new();
+        let mut chunk = [0; 64];
+        while received.iter().filter(|&&byte| byte == CR).count() < 2 {
+            let n = stream.read(&mut chunk).await.unwrap();
+            received.extend_from_slice(&chunk[..n]);
+        }
+        assert_eq!(received, b"\x02M0GP#0000\r\x02I1#0001\r");
+        stream
+            .write_all(b"\x02I11#0001\r\x02M0800#0000\r")
+            .await
+            .unwrap();
+        //Keep the connection up until the client is done
+        while stream.read(&mut chunk).await.is_ok_and(|n| n > 0) {}
+    });
+
+    let (tx, rx) = mpsc::channel::<Message>(10);
+    let config = ClientConfig {
+        sequence_ids: true,
+        ..Default::default()
+    };
+    let client_handle = tokio::spawn(client_with_config(addr, rx, config));
+    let send = move |buffer: &[u8]| {
+        let (response, reply) = oneshot::channel();
+        let msg = Message {
+            buffer: buffer.to_vec(),
+            response,
+            timeout: None,
+            idempotent: false,
+        };
+        let tx = tx.clone();
+        async move {
+            tx.send(msg).await.unwrap();
+            reply.await.unwrap()
+        }
+    };
+    let (position, input) = tokio::join!(send(b"\x02M0GP\r"), send(b"\x02I1\r"));
+    assert_eq!(position.unwrap(), b"\x02M0800\r");
+    assert_eq!(input.unwrap(), b"\x02I11\r");
+
+    drop(send);
+    client_handle.await.unwrap().unwrap();
+    server.await.unwrap();
+}
@@ -0,0 +1,222 @@
+use crate::controllers::clear_core::{CR, STX};
+use crate::interface::transport::frames;
+use crate::util::utils::to_hex;
+use tracing::warn;
+
+//The bytes that delimit a frame on the wire. Everything above the client builds and reads frames
+//as STX..CR, a Framing only swaps the delimiters on the way out and back, so components don't
+//care which one the firmware speaks. Without a start byte a frame is everything up to `end`, and
+//with `end` as '\n' a CR right before it is dropped too so CRLF lines read the same.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Framing {
+    pub start: Option<u8>,
+    pub end: u8,
+}
+
+impl Framing {
+    //What the stock ClearCore sketch speaks
+    pub const STX_CR: Framing = Framing {
+        start: Some(STX),
+        end: CR,
+    };
+    //Newline terminated ASCII, e.g. "M0GS\n" answered with "M03233\n"
+    pub const ASCII_LINE: Framing = Framing {
+        start: None,
+        end: b'\n',
+    };
+
+    pub(crate) fn is_stx_cr(&self) -> bool {
+        *self == Self::STX_CR
+    }
+
+    //Appends the STX..CR frames in `buffer` to `out` with this framing's delimiters. Bytes after
+    //the last CR aren't a frame and are only kept with STX_CR, where the buffer goes out as is.
+    pub(crate) fn encode_into(&self, buffer: &[u8], out: &mut Vec<u8>) {
+        if self.is_stx_cr() {
+            out.extend_from_slice(buffer);
+            return;
+        }
+        for frame in frames(buffer) {
+            let body = &frame[..frame.len() - 1];
+            let body = body.strip_prefix(&[STX]).unwrap_or(body);
+            out.extend(self.start);
+            out.extend_from_slice(body);
+            out.push(self.end);
+        }
+    }
+
+    //Pulls the first complete frame out of `buffer` and appends it to `out` as STX..CR, returns
+    //whether there was one. With a start byte anything ahead of it and frames cut off by a new one
+    //before their end are discarded, a trailing partial frame is kept so the next read can
+    //complete it.
+    pub(crate) fn take_frame_into(&self, buffer: &mut Vec<u8>, out: &mut Vec<u8>) -> bool {
+        match self.start {
+            Some(start) => self.take_delimited(start, buffer, out),
+            None => self.take_line(buffer, out),
+        }
+    }
+
+    fn take_delimited(&self, start_byte: u8, buffer: &mut Vec<u8>, out: &mut Vec<u8>) -> bool {
+        loop {
+            match buffer.iter().position(|&byte| byte == start_byte) {
+                Some(start) => {
+                    if start > 0 {
+                        warn!(garbage = %to_hex(&buffer[..start]), "Discarding bytes before a frame");
+                    }
+                    buffer.drain(..start);
+                }
+                None => {
+                    if !buffer.is_empty() {
+                        warn!(garbage = %to_hex(buffer), "Discarding unframed bytes");
+                    }
+                    buffer.clear();
+                    return false;
+                }
+            }
+            let Some(end) = buffer.iter().position(|&byte| byte == self.end) else {
+                return false;
+            };
+            match buffer[1..end].iter().position(|&byte| byte == start_byte) {
+                Some(restart) => {
+                    warn!(frame = %to_hex(&buffer[..=restart]), "Discarding frame cut off by another");
+                    buffer.drain(..=restart);
+                }
+                None => {
+                    out.push(STX);
+                    out.extend_from_slice(&buffer[1..end]);
+                    out.push(CR);
+                    buffer.drain(..=end);
+                    return true;
+                }
+            }
+        }
+    }
+
+    fn take_line(&self, buffer: &mut Vec<u8>, out: &mut Vec<u8>) -> bool {
+        loop {
+            let Some(end) = buffer.iter().position(|&byte| byte == self.end) else {
+                return false;
+            };
+            let mut body = &buffer[..end];
+            if self.end == b'\n' {
+                body = body.strip_suffix(&[CR]).unwrap_or(body);
+            }
+            //Blank lines between frames carry nothing
+            if body.is_empty() {
+                buffer.drain(..=end);
+                continue;
+            }
+            out.push(STX);
+            out.extend_from_slice(body);
+            out.push(CR);
+            buffer.drain(..=end);
+            return true;
+        }
+    }
+
+    //Called once take_frame_into has nothing, so whatever is buffered is a single unterminated
+    //frame. Past `max` bytes it is dropped up to the next start byte, which may be the start of a
+    //good frame, or all of it without a start byte. Returns whether anything was dropped.
+    pub(crate) fn discard_oversized(&self, buffer: &mut Vec<u8>, max: usize) -> bool {
+        if buffer.len() <= max {
+            return false;
+        }
+        let next = match self.start {
+            Some(start) => buffer[1..]
+                .iter()
+                .position(|&byte| byte == start)
+                .map_or(buffer.len(), |next| next + 1),
+            None => buffer.len(),
+        };
+        warn!(
+            frame = %to_hex(&buffer[..next.min(max)]),
+            len = next,
+            "Discarding frame without an end"
+        );
+        buffer.drain(..next);
+        true
+    }
+}
+
+impl Default for Framing {
+    fn default() -> Self {
+        Self::STX_CR
+    }
+}
+
+#[test]
+fn test_ascii_line_framing() {
+    let framing = Framing::ASCII_LINE;
+    let mut out = Vec::new();
+    framing.encode_into(b"\x02M0GS\r\x02I1\r\x00", &mut out);
+    assert_eq!(out, b"M0GS\nI1\n");
+
+    let mut buffer = b"M03233\r\n\nI11\nM0".to_vec();
+    let mut frames = Vec::new();
+    while framing.take_frame_into(&mut buffer, &mut frames) {}
+    assert_eq!(frames, b"\x02M03233\r\x02I11\r");
+    assert_eq!(buffer, b"M0");
+    assert!(!framing.discard_oversized(&mut buffer, 4));
+    buffer.extend_from_slice(b"12345");
+    assert!(framing.discard_oversized(&mut buffer, 4));
+    assert!(buffer.is_empty());
+
+    //Custom delimiters come back as STX..CR all the same
+    let framing = Framing {
+        start: Some(b'<'),
+        end: b'>',
+    };
+    out.clear();
+    framing.encode_into(b"\x02M0GS\r", &mut out);
+    assert_eq!(out, b"<M0GS>");
+    let mut buffer = b"x<M0<M0_>".to_vec();
+    let mut frame = Vec::new();
+    assert!(framing.take_frame_into(&mut buffer, &mut frame));
+    assert_eq!(frame, b"\x02M0_\r");
+    assert!(buffer.is_empty());
+}
+
+#[tokio::test]
+async fn test_ascii_line_client() {
+    use crate::controllers::clear_core::{Controller, MotorBuilder};
+    use crate::interface::tcp::ClientConfig;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let (read, mut write) = stream.into_split();
+        let mut lines = BufReader::new(read).lines();
+        let mut received = Vec::new();
+        while let Some(line) = lines.next_line().await.unwrap() {
+            let reply = match line.as_str() {
+                "M0GS" => "M03233\r\n",
+                "I1" => "I11\r\n",
+                _ => "M0_\r\n",
+            };
+            write.write_all(reply.as_bytes()).await.unwrap();
+            received.push(line);
+        }
+        received
+    });
+
+    let config = ClientConfig {
+        framing: Framing::ASCII_LINE,
+        ..Default::default()
+    };
+    let motors = [MotorBuilder {
+        id: 0,
+        scale: 800,
+        ..Default::default()
+    }];
+    let (controller, client) = Controller::with_client_config(addr, motors.as_slice(), config);
+    let handle = tokio::spawn(client);
+    assert!(controller.get_digital_input(1).get_state().await.unwrap());
+    controller.get_motor(0).move_absolute(1.0).await.unwrap();
+
+    drop(controller);
+    handle.await.unwrap().unwrap();
+    assert_eq!(server.await.unwrap(), ["I1", "M0GS", "M0AM800"]);
+}
@@ -1 +1,17 @@
+pub mod audit;
+mod correlated;
+pub mod framing;
+#[cfg(feature = "mqtt")]
+pub mod mqtt;
+pub mod replay;
+#[cfg(feature = "serial")]
+pub mod serial;
+#[cfg(feature = "server")]
+pub mod server;
+#[cfg(any(test, feature = "sim"))]
+pub mod sim;
+#[cfg(any(feature = "server", feature = "mqtt"))]
+pub mod state;
+pub mod stream;
 pub mod tcp;
+pub mod transport;
@@ -0,0 +1,270 @@
+use crate::controllers::clear_core::Controller;
+use crate::error::{ControlError, Result};
+use crate::interface::state::StateSnapshot;
+use rumqttc::{AsyncClient, Event, MqttOptions, Packet, QoS};
+use serde::Deserialize;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::task::JoinSet;
+use tokio::time::MissedTickBehavior;
+use tracing::{debug, info, warn};
+
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+//Requests rumqttc buffers for the event loop, telemetry that doesn't fit while the broker is away
+//is dropped rather than held up
+const REQUEST_CAPACITY: usize = 64;
+const MIN_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+//Each topic gets a JSON array with an entry per component in controller order, a read that failed
+//is null
+#[derive(Debug, Clone, PartialEq)]
+pub struct MqttTopics {
+    pub motors: String,
+    pub digital_inputs: String,
+    pub analog_inputs: String,
+    pub outputs: String,
+    //Where MqttCommands are taken from, None leaves the bridge publish only
+    pub commands: Option<String>,
+}
+
+impl MqttTopics {
+    //`prefix`/motors, `prefix`/digital_inputs and so on, without a command topic
+    pub fn with_prefix(prefix: &str) -> Self {
+        Self {
+            motors: format!("{prefix}/motors"),
+            digital_inputs: format!("{prefix}/digital_inputs"),
+            analog_inputs: format!("{prefix}/analog_inputs"),
+            outputs: format!("{prefix}/outputs"),
+            commands: None,
+        }
+    }
+}
+
+impl Default for MqttTopics {
+    fn default() -> Self {
+        Self::with_prefix("clear_core")
+    }
+}
+
+//Payloads on the command topic, e.g. {"command": "set_output", "output": 1, "state": true}.
+//Motors and outputs are referred to by their index on the controller.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum MqttCommand {
+    SetOutput { output: usize, state: bool },
+    Enable { motor: usize },
+    Disable { motor: usize },
+    MoveAbsolute { motor: usize, position: f64 },
+    MoveRelative { motor: usize, delta: f64 },
+    Stop { motor: usize },
+}
+
+pub struct MqttBridge {
+    controller: Arc<Mutex<Controller>>,
+    options: MqttOptions,
+    topics: MqttTopics,
+    poll_interval: Duration,
+}
+
+impl MqttBridge {
+    pub fn new(controller: Arc<Mutex<Controller>>, options: MqttOptions) -> Self {
+        Self {
+            controller,
+            options,
+            topics: MqttTopics::default(),
+            poll_interval: DEFAULT_POLL_INTERVAL,
+        }
+    }
+
+    pub fn with_topics(mut self, topics: MqttTopics) -> Self {
+        self.topics = topics;
+        self
+    }
+
+    //How often the controller is read and its state published
+    pub fn with_poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    //Runs until dropped. A lost broker is retried with a backoff, polling carries on meanwhile and
+    //whatever telemetry can't be queued is dropped so a stale backlog isn't flushed on reconnect.
+    //The command topic is subscribed again on every connect.
+    pub async fn run(self) {
+        let (client, mut event_loop) = AsyncClient::new(self.options.clone(), REQUEST_CAPACITY);
+        let mut tasks = JoinSet::new();
+        tasks.spawn(publish_telemetry(
+            self.controller.clone(),
+            client.clone(),
+            self.topics.clone(),
+            self.poll_interval,
+        ));
+        let mut backoff = MIN_BACKOFF;
+        loop {
+            match event_loop.poll().await {
+                Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                    info!(broker = ?self.options.broker_address(), "Connected to MQTT broker");
+                    backoff = MIN_BACKOFF;
+                    if let Some(topic) = &self.topics.commands {
+                        if let Err(e) = client.try_subscribe(topic.as_str(), QoS::AtLeastOnce) {
+                            warn!(error = %e, topic = %topic, "Failed to subscribe to MQTT commands");
+                        }
+                    }
+                }
+                Ok(Event::Incoming(Packet::Publish(publish))) => {
+                    if let Err(e) = apply_command(&self.controller, &publish.payload).await {
+                        warn!(error = %e, topic = %publish.topic, "MQTT command failed");
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    warn!(error = %e, "MQTT connection lost, retrying in {backoff:?}");
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                }
+            }
+        }
+    }
+}
+
+async fn publish_telemetry(
+    controller: Arc<Mutex<Controller>>,
+    client: AsyncClient,
+    topics: MqttTopics,
+    poll_interval: Duration,
+) {
+    let mut tick_interval = tokio::time::interval(poll_interval);
+    tick_interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    loop {
+        tick_interval.tick().await;
+        let snapshot = StateSnapshot::read(&controller).await;
+        for (topic, payload) in payloads(&snapshot, &topics) {
+            let payload = match payload {
+                Ok(payload) => payload,
+                Err(e) => {
+                    warn!(error = %e, topic, "Failed to serialize MQTT telemetry");
+                    continue;
+                }
+            };
+            if let Err(e) = client.try_publish(topic, QoS::AtMostOnce, false, payload) {
+                debug!(error = %e, topic, "Dropping MQTT telemetry");
+            }
+        }
+    }
+}
+
+fn payloads<'a>(
+    snapshot: &StateSnapshot,
+    topics: &'a MqttTopics,
+) -> [(&'a str, serde_json::Result<Vec<u8>>); 4] {
+    [
+        (topics.motors.as_str(), serde_json::to_vec(&snapshot.motors)),
+        (
+            topics.digital_inputs.as_str(),
+            serde_json::to_vec(&snapshot.digital_inputs),
+        ),
+        (
+            topics.analog_inputs.as_str(),
+            serde_json::to_vec(&snapshot.analog_inputs),
+        ),
+        (
+            topics.outputs.as_str(),
+            serde_json::to_vec(&snapshot.outputs),
+        ),
+    ]
+}
+
+async fn apply_command(controller: &Mutex<Controller>, payload: &[u8]) -> Result<()> {
+    let command: MqttCommand = serde_json::from_slice(payload)
+        .map_err(|e| ControlError::InvalidArgument(format!("bad MQTT command: {e}")))?;
+    info!("MQTT command: {command:?}");
+    let (motors, outputs) = {
+        let controller = controller.lock().await;
+        (controller.get_motors(), controller.get_outputs())
+    };
+    let motor = |index: usize| {
+        motors
+            .get(index)
+            .ok_or_else(|| ControlError::InvalidArgument(format!("no motor {index}")))
+    };
+    match command {
+        MqttCommand::SetOutput { output, state } => {
+            outputs
+                .get(output)
+                .ok_or_else(|| ControlError::InvalidArgument(format!("no output {output}")))?
+                .send_state(state)
+                .await
+        }
+        MqttCommand::Enable { motor: index } => motor(index)?.enable().await.map(|_| ()),
+        MqttCommand::Disable { motor: index } => motor(index)?.disable().await,
+        MqttCommand::MoveAbsolute {
+            motor: index,
+            position,
+        } => motor(index)?.move_absolute(position).await,
+        MqttCommand::MoveRelative {
+            motor: index,
+            delta,
+        } => motor(index)?.move_relative(delta).await,
+        MqttCommand::Stop { motor: index } => motor(index)?.stop().await,
+    }
+}
+
+#[tokio::test]
+async fn test_apply_command() {
+    use crate::controllers::clear_core::MotorBuilder;
+    use crate::testing::MockClearCore;
+
+    let mock = MockClearCore::start().await.unwrap();
+    mock.on(b"M0GS", b"3233")
+        .on(b"M0GP", b"800")
+        .on(b"O1GS", b"32700");
+    let motors = [MotorBuilder {
+        id: 0,
+        scale: 800,
+        ..Default::default()
+    }];
+    let (controller, client) = Controller::with_client(mock.addr(), motors.as_slice());
+    let handle = tokio::spawn(client);
+    let controller = Arc::new(Mutex::new(controller));
+
+    apply_command(
+        &controller,
+        br#"{"command": "set_output", "output": 1, "state": true}"#,
+    )
+    .await
+    .unwrap();
+    apply_command(
+        &controller,
+        br#"{"command": "move_absolute", "motor": 0, "position": 2.0}"#,
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        mock.received(),
+        [
+            b"\x02O132700\r".to_vec(),
+            b"\x02M0GS\r".to_vec(),
+            b"\x02M0AM1600\r".to_vec(),
+        ]
+    );
+
+    let result = apply_command(&controller, br#"{"command": "stop", "motor": 3}"#).await;
+    assert!(matches!(result, Err(ControlError::InvalidArgument(_))));
+    let result = apply_command(&controller, b"not json").await;
+    assert!(matches!(result, Err(ControlError::InvalidArgument(_))));
+    assert_eq!(mock.received().len(), 3);
+
+    let snapshot = StateSnapshot::read(&controller).await;
+    assert_eq!(snapshot.motors[0].position, Some(1.0));
+    assert_eq!(snapshot.outputs[1], Some(true));
+    let published = payloads(&snapshot, &MqttTopics::with_prefix("line1"));
+    assert_eq!(published[0].0, "line1/motors");
+    let motors: serde_json::Value =
+        serde_json::from_slice(published[0].1.as_ref().unwrap()).unwrap();
+    assert_eq!(motors[0]["position"], 1.0);
+
+    drop(controller);
+    handle.await.unwrap().unwrap();
+}
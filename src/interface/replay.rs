@@ -0,0 +1,160 @@
+use crate::controllers::clear_core::Controller;
+use crate::error::{ControlError, Result};
+use crate::interface::transport::split_frames;
+use crate::util::utils::hex_dump;
+use std::path::Path;
+use std::time::Duration;
+use tracing::info;
+
+//How replay re-issues a Recorder log. With preserve_timing each command waits out the gap it had
+//after the previous one in the log, divided by speed, so 2.0 replays twice as fast. Otherwise the
+//commands go out back to back. A dry run prints every frame it would send and sends nothing.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReplayOptions {
+    pub preserve_timing: bool,
+    pub speed: f64,
+    pub dry_run: bool,
+}
+
+impl Default for ReplayOptions {
+    fn default() -> Self {
+        Self {
+            preserve_timing: false,
+            speed: 1.,
+            dry_run: false,
+        }
+    }
+}
+
+//Reads an audit log written by Recorder and sends its recorded commands through `controller` in
+//order with send_raw, e.g. to reproduce a field failure against a SimController. Replies in the log
+//are skipped, whatever the controller answers now is only logged. Frames go out as recorded, so a
+//log taken with checksums or sequence ids on has those bytes in the payload already and should be
+//replayed through a client with both off. Returns how many frames were replayed.
+pub async fn replay(
+    log_path: impl AsRef<Path>,
+    controller: &Controller,
+    options: ReplayOptions,
+) -> Result<usize> {
+    if options.speed.is_nan() || options.speed <= 0. {
+        return Err(ControlError::InvalidArgument(format!(
+            "replay speed must be positive, got {}",
+            options.speed
+        )));
+    }
+    let log = tokio::fs::read_to_string(log_path).await?;
+    let mut commands = Vec::new();
+    for (index, line) in log.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (ts, direction, bytes) = parse_line(line).ok_or_else(|| {
+            ControlError::InvalidArgument(format!("audit log line {} is malformed", index + 1))
+        })?;
+        if direction == "sent" {
+            commands.push((ts, bytes));
+        }
+    }
+
+    let mut replayed = 0;
+    let mut previous = None;
+    for (ts, bytes) in commands {
+        if let Some(previous) = previous.filter(|_| options.preserve_timing) {
+            let gap = Duration::from_millis(ts.saturating_sub(previous));
+            tokio::time::sleep(gap.div_f64(options.speed)).await;
+        }
+        previous = Some(ts);
+        for frame in split_frames(&bytes) {
+            if options.dry_run {
+                println!("{}", hex_dump(&frame));
+            } else {
+                let reply = controller.send_raw(&frame[1..frame.len() - 1]).await?;
+                info!(frame = %hex_dump(&frame), reply = %hex_dump(&reply), "Replayed");
+            }
+            replayed += 1;
+        }
+    }
+    Ok(replayed)
+}
+
+//Only has to read back what Recorder writes, one flat object with fixed keys per line
+fn parse_line(line: &str) -> Option<(u64, &str, Vec<u8>)> {
+    let ts = field(line, "ts")?.parse().ok()?;
+    let direction = field(line, "direction")?.trim_matches('"');
+    let bytes = field(line, "bytes_hex")?
+        .trim_matches('"')
+        .split_whitespace()
+        .map(|byte| u8::from_str_radix(byte, 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    Some((ts, direction, bytes))
+}
+
+fn field<'a>(line: &'a str, key: &str) -> Option<&'a str> {
+    let start = line.find(&format!("\"{key}\":"))? + key.len() + 3;
+    let rest = &line[start..];
+    let end = if rest.starts_with('"') {
+        rest[1..].find('"')? + 2
+    } else {
+        rest.find([',', '}'])?
+    };
+    Some(&rest[..end])
+}
+
+#[tokio::test]
+async fn test_replay() {
+    use crate::testing::MockClearCore;
+    use tokio::time::Instant;
+
+    let path = std::env::temp_dir().join(format!("replay-{}.ndjson", std::process::id()));
+    let log = [
+        r#"{"ts":1000,"direction":"sent","bytes_hex":"02 4d 30 45 4e 0d","latency_ms":null}"#,
+        r#"{"ts":1002,"direction":"received","bytes_hex":"02 4d 30 5f 0d","latency_ms":2.000}"#,
+        r#"{"ts":1100,"direction":"sent","bytes_hex":"02 49 31 0d 02 49 32 0d","latency_ms":null}"#,
+    ];
+    std::fs::write(&path, log.join("\n")).unwrap();
+    let mock = MockClearCore::start().await.unwrap();
+    let (controller, client) = Controller::with_client(mock.addr(), &[]);
+    let handle = tokio::spawn(client);
+
+    let dry_run = ReplayOptions {
+        dry_run: true,
+        ..Default::default()
+    };
+    assert_eq!(replay(&path, &controller, dry_run).await.unwrap(), 3);
+    assert!(mock.received().is_empty());
+
+    let timed = ReplayOptions {
+        preserve_timing: true,
+        speed: 2.,
+        ..Default::default()
+    };
+    let start = Instant::now();
+    assert_eq!(replay(&path, &controller, timed).await.unwrap(), 3);
+    assert!(start.elapsed() >= Duration::from_millis(50));
+    assert_eq!(
+        mock.received(),
+        [
+            b"\x02M0EN\r".to_vec(),
+            b"\x02I1\r".to_vec(),
+            b"\x02I2\r".to_vec(),
+        ]
+    );
+
+    let stalled = ReplayOptions {
+        speed: 0.,
+        ..Default::default()
+    };
+    assert!(matches!(
+        replay(&path, &controller, stalled).await,
+        Err(ControlError::InvalidArgument(_))
+    ));
+    std::fs::write(&path, "not a record").unwrap();
+    assert!(matches!(
+        replay(&path, &controller, ReplayOptions::default()).await,
+        Err(ControlError::InvalidArgument(_))
+    ));
+
+    std::fs::remove_file(&path).unwrap();
+    drop(controller);
+    handle.await.unwrap().unwrap();
+}
@@ -0,0 +1,52 @@
+use crate::error::Result;
+use crate::interface::transport::{run_client, ClientConfig, Queues, Transport};
+use std::io;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio_serial::{SerialPortBuilderExt, SerialStream};
+
+//ClearCore over its USB or RS-232 port, the ASCII framing is the same as over Ethernet
+pub struct SerialTransport {
+    path: String,
+    baud_rate: u32,
+    port: SerialStream,
+}
+
+impl SerialTransport {
+    pub fn open(path: &str, baud_rate: u32) -> io::Result<Self> {
+        let port = tokio_serial::new(path, baud_rate).open_native_async()?;
+        Ok(Self {
+            path: path.to_string(),
+            baud_rate,
+            port,
+        })
+    }
+}
+
+impl Transport for SerialTransport {
+    async fn write(&mut self, buffer: &[u8]) -> io::Result<()> {
+        self.port.write_all(buffer).await
+    }
+
+    async fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        self.port.read(buffer).await
+    }
+
+    async fn reopen(&mut self) -> io::Result<()> {
+        self.port = tokio_serial::new(self.path.as_str(), self.baud_rate).open_native_async()?;
+        Ok(())
+    }
+
+    fn peer(&self) -> String {
+        self.path.clone()
+    }
+}
+
+pub async fn serial_client(
+    path: &str,
+    baud_rate: u32,
+    msg: impl Into<Queues>,
+    config: ClientConfig,
+) -> Result<()> {
+    let transport = SerialTransport::open(path, baud_rate)?;
+    run_client(transport, msg, config).await
+}
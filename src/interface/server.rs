@@ -0,0 +1,89 @@
+use crate::controllers::clear_core::Controller;
+use crate::error::Result;
+pub use crate::interface::state::{MotorState, StateSnapshot};
+use axum::extract::ws::{Message as WsMessage, WebSocket, WebSocketUpgrade};
+use axum::extract::State;
+use axum::response::Response;
+use axum::routing::get;
+use axum::{Json, Router};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::{TcpListener, ToSocketAddrs};
+use tokio::sync::{watch, Mutex};
+use tokio::time::MissedTickBehavior;
+use tracing::{debug, info, warn};
+
+//GET /state answers with the latest snapshot as JSON, /ws is a websocket that gets the current
+//snapshot on connect and then every one that differs from the last. Spawns the task polling the
+//controller every `poll_interval`, it stops once the router and every websocket are gone.
+pub fn router(controller: Arc<Mutex<Controller>>, poll_interval: Duration) -> Router {
+    let (tx, rx) = watch::channel(StateSnapshot::default());
+    tokio::spawn(poll(controller, poll_interval, tx));
+    Router::new()
+        .route("/state", get(state))
+        .route("/ws", get(stream))
+        .with_state(rx)
+}
+
+pub async fn serve(
+    controller: Arc<Mutex<Controller>>,
+    addr: impl ToSocketAddrs,
+    poll_interval: Duration,
+) -> Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    info!(addr = %listener.local_addr()?, "Serving controller state");
+    axum::serve(listener, router(controller, poll_interval)).await?;
+    Ok(())
+}
+
+async fn poll(
+    controller: Arc<Mutex<Controller>>,
+    poll_interval: Duration,
+    tx: watch::Sender<StateSnapshot>,
+) {
+    let mut tick_interval = tokio::time::interval(poll_interval);
+    tick_interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    while !tx.is_closed() {
+        tick_interval.tick().await;
+        let snapshot = StateSnapshot::read(&controller).await;
+        //Unchanged snapshots don't wake the websockets
+        tx.send_if_modified(|current| {
+            if *current == snapshot {
+                return false;
+            }
+            *current = snapshot;
+            true
+        });
+    }
+}
+
+async fn state(State(rx): State<watch::Receiver<StateSnapshot>>) -> Json<StateSnapshot> {
+    Json(rx.borrow().clone())
+}
+
+async fn stream(
+    ws: WebSocketUpgrade,
+    State(rx): State<watch::Receiver<StateSnapshot>>,
+) -> Response {
+    ws.on_upgrade(move |socket| push_changes(socket, rx))
+}
+
+async fn push_changes(mut socket: WebSocket, mut rx: watch::Receiver<StateSnapshot>) {
+    loop {
+        let json = serde_json::to_string(&*rx.borrow_and_update());
+        let json = match json {
+            Ok(json) => json,
+            Err(e) => {
+                warn!(error = %e, "Failed to serialize controller state");
+                return;
+            }
+        };
+        if socket.send(WsMessage::Text(json)).await.is_err() {
+            debug!("Dashboard websocket closed");
+            return;
+        }
+        if rx.changed().await.is_err() {
+            return;
+        }
+    }
+}
@@ -0,0 +1,355 @@
+use crate::controllers::clear_core::{CR, STX};
+use crate::interface::transport::{take_frame, Transport};
+use crate::util::utils::{ascii_to_int, num_to_bytes};
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
+
+//Ramps a drive starts out with until SV/SA/SD say otherwise, in counts
+const DEFAULT_VELOCITY: f64 = 10_000.;
+const DEFAULT_ACCELERATION: f64 = 100_000.;
+//Kinematics are integrated in fixed steps so results don't depend on how often the sim is asked
+const STEP: Duration = Duration::from_millis(1);
+
+//Status values in the StatusRegMotor layout MotorStatus::from_bits reads
+const STATUS_DISABLED: u32 = 0;
+//Ready, enabled, HLFB asserted and at target
+const STATUS_IDLE: u32 = (3 << 10) | (1 << 5) | (1 << 7) | 1;
+//Moving, enabled and steps active
+const STATUS_MOVING: u32 = (4 << 10) | (1 << 5) | (1 << 1);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Motion {
+    Idle,
+    //Trapezoidal move to an absolute position in counts
+    Position(f64),
+    //Ramps to and holds a velocity in counts/sec, a stop is a ramp to 0
+    Velocity(f64),
+}
+
+#[derive(Debug, Clone)]
+struct SimMotor {
+    enabled: bool,
+    position: f64,
+    velocity: f64,
+    velocity_limit: f64,
+    acceleration: f64,
+    deceleration: f64,
+    motion: Motion,
+    updated: Instant,
+}
+
+impl SimMotor {
+    fn new(now: Instant) -> Self {
+        Self {
+            enabled: false,
+            position: 0.,
+            velocity: 0.,
+            velocity_limit: DEFAULT_VELOCITY,
+            acceleration: DEFAULT_ACCELERATION,
+            deceleration: DEFAULT_ACCELERATION,
+            motion: Motion::Idle,
+            updated: now,
+        }
+    }
+
+    fn advance(&mut self, now: Instant) {
+        if self.motion == Motion::Idle {
+            self.updated = now;
+            return;
+        }
+        while self.updated + STEP <= now && self.motion != Motion::Idle {
+            self.step(STEP.as_secs_f64());
+            self.updated += STEP;
+        }
+        if self.motion == Motion::Idle {
+            self.updated = now;
+        }
+    }
+
+    fn step(&mut self, dt: f64) {
+        match self.motion {
+            Motion::Idle => {}
+            Motion::Position(target) => {
+                let remaining = target - self.position;
+                let direction = remaining.signum();
+                let stopping = self.velocity * self.velocity / (2. * self.deceleration);
+                let approaching = self.velocity * direction > 0.;
+                self.velocity = if approaching && stopping >= remaining.abs() {
+                    self.ramp(0., dt)
+                } else {
+                    self.ramp(direction * self.velocity_limit, dt)
+                };
+                let next = self.position + self.velocity * dt;
+                //Reaching or passing the target ends the move on it
+                if (target - next) * direction <= 0. || remaining.abs() < 0.5 {
+                    self.position = target;
+                    self.velocity = 0.;
+                    self.motion = Motion::Idle;
+                } else {
+                    self.position = next;
+                }
+            }
+            Motion::Velocity(target) => {
+                self.velocity = self.ramp(target, dt);
+                self.position += self.velocity * dt;
+                if target == 0. && self.velocity == 0. {
+                    self.motion = Motion::Idle;
+                }
+            }
+        }
+    }
+
+    //One step of the velocity toward `target`, speeding up with the acceleration and slowing
+    //down with the deceleration
+    fn ramp(&self, target: f64, dt: f64) -> f64 {
+        let slowing = target.abs() < self.velocity.abs() || target * self.velocity < 0.;
+        let rate = if slowing {
+            self.deceleration
+        } else {
+            self.acceleration
+        };
+        let change = (target - self.velocity).clamp(-rate * dt, rate * dt);
+        self.velocity + change
+    }
+
+    fn status(&self) -> u32 {
+        match (self.enabled, self.motion) {
+            (false, _) => STATUS_DISABLED,
+            (true, Motion::Idle) => STATUS_IDLE,
+            (true, _) => STATUS_MOVING,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct SimState {
+    motors: HashMap<u8, SimMotor>,
+    inputs: HashMap<u8, isize>,
+    outputs: HashMap<u8, isize>,
+}
+
+impl SimState {
+    fn motor(&mut self, id: u8, now: Instant) -> &mut SimMotor {
+        let motor = self.motors.entry(id).or_insert_with(|| SimMotor::new(now));
+        motor.advance(now);
+        motor
+    }
+
+    //None is the '?' the firmware answers commands it turns down with
+    fn execute(&mut self, device: u8, id: u8, command: &[u8]) -> Option<Option<isize>> {
+        let now = Instant::now();
+        let value = || ascii_to_int(command.get(2..).unwrap_or_default());
+        match device {
+            b'M' => {
+                let motor = self.motor(id, now);
+                let code = command.get(..2)?;
+                let enabled = motor.enabled;
+                match code {
+                    b"EN" => motor.enabled = true,
+                    b"DE" => {
+                        motor.enabled = false;
+                        motor.velocity = 0.;
+                        motor.motion = Motion::Idle;
+                    }
+                    b"AM" | b"RM" | b"JG" | b"HM" if !enabled => return None,
+                    b"AM" => motor.motion = Motion::Position(value() as f64),
+                    b"RM" => {
+                        let from = match motor.motion {
+                            Motion::Position(target) => target,
+                            _ => motor.position,
+                        };
+                        motor.motion = Motion::Position(from + value() as f64);
+                    }
+                    b"JG" => motor.motion = Motion::Velocity(value() as f64),
+                    //Homes on the spot, there's no switch to seek out
+                    b"HM" => {
+                        motor.position = 0.;
+                        motor.velocity = 0.;
+                        motor.motion = Motion::Idle;
+                    }
+                    b"ST" if motor.motion != Motion::Idle => motor.motion = Motion::Velocity(0.),
+                    b"ST" => {}
+                    b"AS" => {
+                        motor.velocity = 0.;
+                        motor.motion = Motion::Idle;
+                    }
+                    b"SV" => motor.velocity_limit = value().max(1) as f64,
+                    b"SA" => motor.acceleration = value().max(1) as f64,
+                    b"SD" => motor.deceleration = value().max(1) as f64,
+                    b"SP" => motor.position = value() as f64,
+                    b"CA" | b"CI" => {}
+                    b"GS" => return Some(Some(motor.status() as isize)),
+                    b"GP" => return Some(Some(motor.position.round() as isize)),
+                    b"GV" => return Some(Some(motor.velocity.round() as isize)),
+                    //Profiles are followed perfectly
+                    b"GT" | b"GE" => return Some(Some(0)),
+                    //Nothing ever gets latched
+                    _ => return None,
+                }
+                Some(None)
+            }
+            b'I' if command.is_empty() => Some(Some(*self.inputs.get(&id).unwrap_or(&0))),
+            b'O' if command == b"GS" => Some(Some(*self.outputs.get(&id).unwrap_or(&0))),
+            b'O' | b'P' if command.iter().all(u8::is_ascii_digit) && !command.is_empty() => {
+                self.outputs.insert(id, value());
+                Some(None)
+            }
+            _ => None,
+        }
+    }
+}
+
+//A ClearCore in memory for running recipes without hardware. Motors follow trapezoidal profiles
+//with whatever ramps they were sent, honour enable and disable and answer status, position and
+//velocity queries with where the profile has them. Inputs read whatever set_input last gave them
+//and output levels can be read back with output. Homing completes instantly where the motor is and
+//position capture never latches. Checksums and sequence ids aren't supported.
+#[derive(Debug, Clone, Default)]
+pub struct SimController {
+    state: Arc<Mutex<SimState>>,
+}
+
+impl SimController {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    //A link to this controller for the client, every transport shares the same simulated board
+    pub fn transport(&self) -> SimTransport {
+        SimTransport {
+            state: self.state.clone(),
+            pending: Vec::new(),
+            replies: VecDeque::new(),
+        }
+    }
+
+    pub fn set_input(&self, id: u8, value: isize) {
+        self.state.lock().unwrap().inputs.insert(id, value);
+    }
+
+    pub fn output(&self, id: u8) -> isize {
+        *self.state.lock().unwrap().outputs.get(&id).unwrap_or(&0)
+    }
+
+    //In counts, as the drive would report it
+    pub fn motor_position(&self, id: u8) -> f64 {
+        self.state
+            .lock()
+            .unwrap()
+            .motor(id, Instant::now())
+            .position
+    }
+
+    pub fn motor_enabled(&self, id: u8) -> bool {
+        self.state.lock().unwrap().motor(id, Instant::now()).enabled
+    }
+}
+
+pub struct SimTransport {
+    state: Arc<Mutex<SimState>>,
+    //Written bytes that don't make up a whole frame yet
+    pending: Vec<u8>,
+    replies: VecDeque<u8>,
+}
+
+impl Transport for SimTransport {
+    async fn write(&mut self, buffer: &[u8]) -> io::Result<()> {
+        self.pending.extend_from_slice(buffer);
+        let mut state = self.state.lock().unwrap();
+        while let Some(frame) = take_frame(&mut self.pending) {
+            let body = &frame[1..frame.len() - 1];
+            let [device, id, command @ ..] = body else {
+                continue;
+            };
+            let reply = match state.execute(*device, id.wrapping_sub(b'0'), command) {
+                Some(Some(value)) => num_to_bytes(value),
+                Some(None) => vec![b'_'],
+                None => vec![b'?'],
+            };
+            self.replies.extend([STX, *device, *id]);
+            self.replies.extend(reply);
+            self.replies.push_back(CR);
+        }
+        Ok(())
+    }
+
+    //Every reply is ready as soon as its command is written, with none waiting this never resolves
+    async fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        if self.replies.is_empty() {
+            std::future::pending::<()>().await;
+        }
+        let n = buffer.len().min(self.replies.len());
+        for (slot, byte) in buffer.iter_mut().zip(self.replies.drain(..n)) {
+            *slot = byte;
+        }
+        Ok(n)
+    }
+
+    //The board lives on, only the link is fresh
+    async fn reopen(&mut self) -> io::Result<()> {
+        self.pending.clear();
+        self.replies.clear();
+        Ok(())
+    }
+
+    fn peer(&self) -> String {
+        "simulated ClearCore".to_string()
+    }
+}
+
+#[tokio::test]
+async fn test_sim_controller() {
+    use crate::controllers::clear_core::{Controller, MotorBuilder};
+    use crate::error::ControlError;
+    use crate::interface::tcp::ClientConfig;
+
+    let sim = SimController::new();
+    sim.set_input(1, 1);
+    let motors = [MotorBuilder {
+        id: 0,
+        scale: 800,
+        ..Default::default()
+    }];
+    let (controller, client) =
+        Controller::with_transport(sim.transport(), motors.as_slice(), ClientConfig::default());
+    let handle = tokio::spawn(client);
+    let motor = controller.get_motor(0);
+
+    assert!(matches!(
+        motor.move_absolute(1.0).await,
+        Err(ControlError::NotEnabled(0))
+    ));
+    motor.enable().await.unwrap();
+    assert!(sim.motor_enabled(0));
+
+    //800 counts at the default ramps is a triangular profile of about 180ms
+    let start = Instant::now();
+    motor.move_absolute(1.0).await.unwrap();
+    let status = motor.get_status().await.unwrap();
+    assert!(status.moving && !status.at_target);
+    motor.wait_for_move_complete().await.unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(150));
+    assert_eq!(motor.get_position().await.unwrap(), 1.0);
+
+    motor.move_velocity(2.0).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(motor.get_velocity().await.unwrap() > 0.);
+    motor.stop().await.unwrap();
+    motor.wait_for_move_complete().await.unwrap();
+    assert_eq!(motor.get_velocity().await.unwrap(), 0.);
+    assert!(motor.get_position().await.unwrap() > 1.0);
+
+    assert!(controller.get_digital_input(1).get_state().await.unwrap());
+    controller.get_output(2).set_state(true).await;
+    assert_eq!(sim.output(2), 32700);
+    assert!(controller.get_output(2).get_state().await.unwrap());
+
+    motor.disable().await.unwrap();
+    assert!(!sim.motor_enabled(0));
+
+    drop((controller, motor));
+    handle.await.unwrap().unwrap();
+}
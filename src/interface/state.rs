@@ -0,0 +1,87 @@
+use crate::components::clear_core_motor::MotorStatus;
+use crate::controllers::clear_core::Controller;
+use futures::future::join_all;
+use serde::Serialize;
+use tokio::sync::Mutex;
+
+//What the dashboard server and the MQTT bridge publish. A read that failed shows up as null so one
+//unplugged sensor doesn't blank the whole dashboard.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MotorState {
+    pub position: Option<f64>,
+    pub status: Option<MotorStatus>,
+}
+
+//An entry per component in controller order
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct StateSnapshot {
+    pub motors: Vec<MotorState>,
+    pub digital_inputs: Vec<Option<bool>>,
+    pub analog_inputs: Vec<Option<isize>>,
+    pub outputs: Vec<Option<bool>>,
+}
+
+impl StateSnapshot {
+    pub async fn read(controller: &Mutex<Controller>) -> Self {
+        //Only the handles are cloned under the lock so other users aren't held up by the reads
+        let (motors, digital_inputs, analog_inputs, outputs) = {
+            let controller = controller.lock().await;
+            (
+                controller.get_motors(),
+                controller.get_digital_inputs(),
+                controller.get_analog_inputs(),
+                controller.get_outputs(),
+            )
+        };
+        let motors = join_all(motors.iter().map(|motor| async move {
+            let (position, status) = tokio::join!(motor.get_position(), motor.get_status());
+            MotorState {
+                position: position.ok(),
+                status: status.ok(),
+            }
+        }));
+        let digital_inputs = join_all(digital_inputs.iter().map(|input| input.get_state()));
+        let analog_inputs = join_all(analog_inputs.iter().map(|input| input.get_state()));
+        let outputs = join_all(outputs.iter().map(|output| output.get_state()));
+        let (motors, digital_inputs, analog_inputs, outputs) =
+            tokio::join!(motors, digital_inputs, analog_inputs, outputs);
+        Self {
+            motors,
+            digital_inputs: digital_inputs.into_iter().map(|state| state.ok()).collect(),
+            analog_inputs: analog_inputs.into_iter().map(|state| state.ok()).collect(),
+            outputs: outputs.into_iter().map(|state| state.ok()).collect(),
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_state_snapshot() {
+    use crate::controllers::clear_core::MotorBuilder;
+    use crate::testing::MockClearCore;
+    use std::sync::Arc;
+
+    let mock = MockClearCore::start().await.unwrap();
+    mock.on(b"M0GP", b"1600")
+        .on(b"M0GS", b"3233")
+        .on(b"I1", b"1")
+        .on(b"O1GS", b"32700");
+    let motors = [MotorBuilder {
+        id: 0,
+        scale: 800,
+        ..Default::default()
+    }];
+    let (controller, client) = Controller::with_client(mock.addr(), motors.as_slice());
+    let handle = tokio::spawn(client);
+    let controller = Arc::new(Mutex::new(controller));
+
+    let snapshot = StateSnapshot::read(&controller).await;
+    assert_eq!(snapshot.motors[0].position, Some(2.0));
+    assert!(snapshot.motors[0].status.unwrap().at_target);
+    assert_eq!(snapshot.digital_inputs[..2], [Some(false), Some(true)]);
+    assert_eq!(snapshot.outputs[1], Some(true));
+    let json = serde_json::to_value(&snapshot).unwrap();
+    assert_eq!(json["motors"][0]["position"], 2.0);
+
+    drop(controller);
+    handle.await.unwrap().unwrap();
+}
@@ -0,0 +1,85 @@
+use crate::error::Result;
+use crate::interface::transport::{run_client, ClientConfig, Queues, Reconnect, Transport};
+use std::io;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+//Any byte stream as the link, e.g. one end of tokio::io::duplex so tests run without binding a
+//port. There's nothing to reconnect to once the stream closes, so reopen always fails and a
+//client over one should be run with Reconnect::Never.
+pub struct StreamTransport<S> {
+    stream: S,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send> StreamTransport<S> {
+    pub fn new(stream: S) -> Self {
+        Self { stream }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send> Transport for StreamTransport<S> {
+    async fn write(&mut self, buffer: &[u8]) -> io::Result<()> {
+        self.stream.write_all(buffer).await
+    }
+
+    async fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        self.stream.read(buffer).await
+    }
+
+    async fn reopen(&mut self) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::NotConnected,
+            "a stream transport can't be reopened",
+        ))
+    }
+
+    fn peer(&self) -> String {
+        "stream".to_string()
+    }
+}
+
+//The client over `stream` instead of a TcpStream, ends with an error as soon as the link fails
+pub async fn client_with_stream<S: AsyncRead + AsyncWrite + Unpin + Send>(
+    stream: S,
+    msg: impl Into<Queues>,
+) -> Result<()> {
+    let config = ClientConfig {
+        reconnect: Reconnect::Never,
+        ..Default::default()
+    };
+    client_with_stream_config(stream, msg, config).await
+}
+
+pub async fn client_with_stream_config<S: AsyncRead + AsyncWrite + Unpin + Send>(
+    stream: S,
+    msg: impl Into<Queues>,
+    config: ClientConfig,
+) -> Result<()> {
+    run_client(StreamTransport::new(stream), msg, config).await
+}
+
+#[tokio::test]
+async fn test_client_with_stream() {
+    use crate::controllers::clear_core::{Message, CR, STX};
+    use crate::testing::MockClearCore;
+    use tokio::sync::{mpsc, oneshot};
+
+    let mock = MockClearCore::in_memory();
+    mock.on(b"I1", b"1");
+    let (tx, rx) = mpsc::channel::<Message>(10);
+    let handle = tokio::spawn(client_with_stream(mock.stream(), rx));
+
+    let (response, reply) = oneshot::channel();
+    tx.send(Message {
+        buffer: vec![STX, b'I', b'1', CR],
+        response,
+        timeout: None,
+        idempotent: true,
+    })
+    .await
+    .unwrap();
+    assert_eq!(reply.await.unwrap().unwrap(), b"\x02I11\r");
+    assert_eq!(mock.received(), [b"\x02I1\r".to_vec()]);
+
+    drop(tx);
+    handle.await.unwrap().unwrap();
+}
@@ -1,41 +1,527 @@
-use crate::controllers::clear_core::Message;
-use log::{error, info};
-use std::error::Error;
+use crate::error::Result;
+use crate::interface::transport::{
+    back_off, run_client, run_client_until, Queues, Transport, DEFAULT_CONNECT_TIMEOUT,
+};
+pub use crate::interface::transport::{ClientConfig, ConnectionState, Reconnect};
+use std::io;
+use std::net::SocketAddr;
 use std::time::Duration;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::{TcpStream, ToSocketAddrs};
-use tokio::sync::mpsc;
-use tokio::time::MissedTickBehavior;
+use tokio::net::{lookup_host, TcpStream, ToSocketAddrs};
+use tokio::sync::oneshot;
 
-pub async fn client<T: ToSocketAddrs>(
-    addr: T,
-    mut msg: mpsc::Receiver<Message>,
-) -> Result<(), Box<dyn Error + Send + Sync>> {
-    let mut stream = TcpStream::connect(addr).await?;
-    let peer_addr = stream.peer_addr().expect(" Peer not connected");
-    info!("Client connected with peer address: {peer_addr}");
-    println!("DEBUG: peer address is {peer_addr}");
-    let mut tick_interval = tokio::time::interval(Duration::from_millis(5));
-    tick_interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
-    while let Some(message) = msg.recv().await {
-        stream.write_all(&message.buffer).await?;
-        stream.readable().await?;
-        let mut buffer = [0; 100];
-        match stream.read(&mut buffer).await {
-            Ok(0) => {
-                error!("Connection closed by server");
-            }
-            Ok(_) => {
-                if message.response.send(buffer.to_vec()).is_err() {
-                    error!("Failed to send via channel");
+pub struct TcpTransport {
+    addrs: Vec<SocketAddr>,
+    stream: TcpStream,
+    connect_timeout: Duration,
+}
+
+impl TcpTransport {
+    pub async fn connect<T: ToSocketAddrs>(addr: T) -> io::Result<Self> {
+        Self::connect_timeout(addr, DEFAULT_CONNECT_TIMEOUT).await
+    }
+
+    //A single attempt that fails with TimedOut after `timeout`, reconnects get the same limit
+    pub async fn connect_timeout<T: ToSocketAddrs>(addr: T, timeout: Duration) -> io::Result<Self> {
+        //Resolve once so that we can keep reconnecting to the same peer without needing T: Clone
+        let addrs: Vec<SocketAddr> = lookup_host(addr).await?.collect();
+        let stream = open(&addrs, timeout).await?;
+        Ok(Self {
+            addrs,
+            stream,
+            connect_timeout: timeout,
+        })
+    }
+
+    //Retries the first connect by `config.reconnect`, so a client started before the controller
+    //has booted waits for it rather than failing
+    async fn connect_with_config<T: ToSocketAddrs>(
+        addr: T,
+        config: &ClientConfig,
+    ) -> io::Result<Self> {
+        let addrs: Vec<SocketAddr> = lookup_host(addr).await?.collect();
+        let mut backoff = config.min_backoff;
+        let mut attempts = 0;
+        loop {
+            match open(&addrs, config.connect_timeout).await {
+                Ok(stream) => {
+                    return Ok(Self {
+                        addrs,
+                        stream,
+                        connect_timeout: config.connect_timeout,
+                    })
+                }
+                Err(e) => {
+                    attempts += 1;
+                    if !back_off(config, attempts, &mut backoff, &e).await {
+                        return Err(e);
+                    }
                 }
             }
-            Err(e) => {
-                error!("Failed to read from stream: {}", e);
-                break;
+        }
+    }
+}
+
+async fn open(addrs: &[SocketAddr], timeout: Duration) -> io::Result<TcpStream> {
+    match tokio::time::timeout(timeout, TcpStream::connect(addrs)).await {
+        Ok(stream) => stream,
+        Err(_) => Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            format!("No connection to {addrs:?} within {timeout:?}"),
+        )),
+    }
+}
+
+impl Transport for TcpTransport {
+    async fn write(&mut self, buffer: &[u8]) -> io::Result<()> {
+        self.stream.write_all(buffer).await
+    }
+
+    async fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        self.stream.read(buffer).await
+    }
+
+    async fn reopen(&mut self) -> io::Result<()> {
+        self.stream = open(&self.addrs, self.connect_timeout).await?;
+        Ok(())
+    }
+
+    fn peer(&self) -> String {
+        self.stream
+            .peer_addr()
+            .map(|addr| addr.to_string())
+            .unwrap_or_else(|_| format!("{:?}", self.addrs))
+    }
+}
+
+pub async fn client<T: ToSocketAddrs>(addr: T, msg: impl Into<Queues>) -> Result<()> {
+    client_with_config(addr, msg, ClientConfig::default()).await
+}
+
+pub async fn client_with_config<T: ToSocketAddrs>(
+    addr: T,
+    msg: impl Into<Queues>,
+    config: ClientConfig,
+) -> Result<()> {
+    let transport = TcpTransport::connect_with_config(addr, &config).await?;
+    run_client(transport, msg, config).await
+}
+
+//Like client_with_config but also stops when `shutdown` fires, dropping its sender does nothing
+pub async fn client_with_shutdown<T: ToSocketAddrs>(
+    addr: T,
+    msg: impl Into<Queues>,
+    config: ClientConfig,
+    shutdown: oneshot::Receiver<()>,
+) -> Result<()> {
+    let transport = TcpTransport::connect_with_config(addr, &config).await?;
+    let shutdown = async {
+        if shutdown.await.is_err() {
+            std::future::pending::<()>().await;
+        }
+    };
+    run_client_until(transport, msg, config, shutdown).await
+}
+
+#[tokio::test]
+async fn test_command_timeout() {
+    use crate::controllers::clear_core::{Message, CR, STX};
+    use crate::error::ControlError;
+    use std::time::Duration;
+    use tokio::net::TcpListener;
+    use tokio::sync::{mpsc, oneshot};
+    use tokio::time::sleep;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = tokio::spawn(async move {
+        //Hold the connection open without ever replying
+        let (stream, _) = listener.accept().await.unwrap();
+        sleep(Duration::from_secs(1)).await;
+        drop(stream);
+    });
+
+    let (tx, rx) = mpsc::channel(10);
+    let config = ClientConfig {
+        reconnect: Reconnect::Never,
+        command_timeout: Duration::from_millis(50),
+        ..Default::default()
+    };
+    let client_handle = tokio::spawn(client_with_config(addr, rx, config));
+    let (resp_tx, resp_rx) = oneshot::channel();
+    let msg = Message {
+        buffer: vec![STX, b'M', b'0', b'G', b'S', CR],
+        response: resp_tx,
+        timeout: None,
+        idempotent: false,
+    };
+    tx.send(msg).await.unwrap();
+    assert!(matches!(resp_rx.await.unwrap(), Err(ControlError::Timeout)));
+    assert!(client_handle.await.unwrap().is_err());
+    server.abort();
+}
+
+#[tokio::test]
+async fn test_priority_queue() {
+    use crate::controllers::clear_core::{Message, CR, STX};
+    use crate::testing::MockClearCore;
+    use tokio::sync::mpsc;
+
+    let mock = MockClearCore::start().await.unwrap();
+    let (tx, rx) = mpsc::channel::<Message>(10);
+    let (priority_tx, priority_rx) = mpsc::channel::<Message>(10);
+    let mut replies = Vec::new();
+    //The normal queue is already backed up when the priority message arrives
+    for frame in [
+        vec![STX, b'I', b'0', CR],
+        vec![STX, b'I', b'1', CR],
+        vec![STX, b'M', b'0', b'A', b'S', CR],
+    ] {
+        let (response, reply) = oneshot::channel();
+        let sender = if frame[1] == b'M' { &priority_tx } else { &tx };
+        sender
+            .send(Message {
+                buffer: frame,
+                response,
+                timeout: None,
+                idempotent: false,
+            })
+            .await
+            .unwrap();
+        replies.push(reply);
+    }
+    let queues = Queues {
+        normal: rx,
+        priority: Some(priority_rx),
+    };
+    let client_handle = tokio::spawn(client(mock.addr(), queues));
+    for reply in replies {
+        reply.await.unwrap().unwrap();
+    }
+    assert_eq!(mock.received()[0], [STX, b'M', b'0', b'A', b'S', CR]);
+
+    //The client keeps serving the normal queue once every priority sender is gone
+    drop(priority_tx);
+    let (response, reply) = oneshot::channel();
+    tx.send(Message {
+        buffer: vec![STX, b'I', b'2', CR],
+        response,
+        timeout: None,
+        idempotent: false,
+    })
+    .await
+    .unwrap();
+    reply.await.unwrap().unwrap();
+    drop(tx);
+    client_handle.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_safe_states_restored_on_reconnect() {
+    use crate::controllers::clear_core::Message;
+    use crate::error::ControlError;
+    use crate::interface::transport::ReconnectFrames;
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = tokio::spawn(async move {
+        //The first connection dies, the second has to see the safe states before anything else
+        let (first, _) = listener.accept().await.unwrap();
+        drop(first);
+        let (mut second, _) = listener.accept().await.unwrap();
+        let mut received = Vec::new();
+        let mut chunk = [0; 64];
+        while received.iter().filter(|&&byte| byte == b'\r').count() < 3 {
+            let n = second.read(&mut chunk).await.unwrap();
+            received.extend_from_slice(&chunk[..n]);
+        }
+        second
+            .write_all(b"\x02O1_\r\x02P4_\r\x02I0_\r")
+            .await
+            .unwrap();
+        received
+    });
+
+    let on_reconnect = ReconnectFrames::default();
+    on_reconnect.set(vec![b"\x02O10\r".to_vec(), b"\x02P451\r".to_vec()]);
+    let config = ClientConfig {
+        min_backoff: std::time::Duration::from_millis(1),
+        on_reconnect,
+        ..Default::default()
+    };
+    let (tx, rx) = mpsc::channel::<Message>(10);
+    let client_handle = tokio::spawn(client_with_config(addr, rx, config));
+    let send = move |buffer: &[u8]| {
+        let (response, reply) = oneshot::channel();
+        let msg = Message {
+            buffer: buffer.to_vec(),
+            response,
+            timeout: None,
+            idempotent: false,
+        };
+        let tx = tx.clone();
+        async move {
+            tx.send(msg).await.unwrap();
+            reply.await.unwrap()
+        }
+    };
+    assert!(matches!(
+        send(b"\x02I0\r").await,
+        Err(ControlError::Disconnected)
+    ));
+    assert_eq!(send(b"\x02I0\r").await.unwrap(), b"\x02I0_\r");
+    assert_eq!(server.await.unwrap(), b"\x02O10\r\x02P451\r\x02I0\r");
+    drop(send);
+    client_handle.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_retry_idempotent() {
+    use crate::controllers::clear_core::Message;
+    use crate::error::ControlError;
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::sync::mpsc;
+
+    //Reads one frame, or returns false if the client hung up first
+    async fn read_frame(stream: &mut TcpStream) -> bool {
+        let mut byte = [0; 1];
+        loop {
+            match stream.read(&mut byte).await.unwrap() {
+                0 => return false,
+                _ if byte[0] == b'\r' => return true,
+                _ => {}
             }
         }
-        tick_interval.tick().await;
     }
-    Ok(())
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = tokio::spawn(async move {
+        //Every connection but the second dies on its first command
+        let (mut first, _) = listener.accept().await.unwrap();
+        assert!(read_frame(&mut first).await);
+        drop(first);
+        let (mut second, _) = listener.accept().await.unwrap();
+        assert!(read_frame(&mut second).await);
+        second.write_all(b"\x02M03233\r").await.unwrap();
+        assert!(read_frame(&mut second).await);
+        drop(second);
+        let (mut third, _) = listener.accept().await.unwrap();
+        assert!(!read_frame(&mut third).await);
+    });
+
+    let config = ClientConfig {
+        min_backoff: std::time::Duration::from_millis(1),
+        retries: 1,
+        ..Default::default()
+    };
+    let (tx, rx) = mpsc::channel::<Message>(10);
+    let client_handle = tokio::spawn(client_with_config(addr, rx, config));
+    let send = move |buffer: &[u8], idempotent: bool| {
+        let (response, reply) = oneshot::channel();
+        let msg = Message {
+            buffer: buffer.to_vec(),
+            response,
+            timeout: None,
+            idempotent,
+        };
+        let tx = tx.clone();
+        async move {
+            tx.send(msg).await.unwrap();
+            reply.await.unwrap()
+        }
+    };
+    //The status read is resent on the new connection, the relative move is not
+    assert_eq!(send(b"\x02M0GS\r", true).await.unwrap(), b"\x02M03233\r");
+    assert!(matches!(
+        send(b"\x02M0RM800\r", false).await,
+        Err(ControlError::Disconnected)
+    ));
+    drop(send);
+    client_handle.await.unwrap().unwrap();
+    server.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_runaway_reply() {
+    use crate::controllers::clear_core::Message;
+    use crate::error::ControlError;
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut chunk = [0; 64];
+        //Answers the first command with a flood of bytes that never ends in a CR
+        assert!(stream.read(&mut chunk).await.unwrap() > 0);
+        let mut runaway = vec![0x02];
+        runaway.extend_from_slice(&[b'7'; 500]);
+        stream.write_all(&runaway).await.unwrap();
+        //The link is still usable afterwards
+        assert!(stream.read(&mut chunk).await.unwrap() > 0);
+        stream.write_all(b"\x02I11\r").await.unwrap();
+        while stream.read(&mut chunk).await.is_ok_and(|n| n > 0) {}
+    });
+
+    let (tx, rx) = mpsc::channel::<Message>(10);
+    let client_handle = tokio::spawn(client_with_config(addr, rx, ClientConfig::default()));
+    let send = move |buffer: &[u8]| {
+        let (response, reply) = oneshot::channel();
+        let msg = Message {
+            buffer: buffer.to_vec(),
+            response,
+            timeout: None,
+            idempotent: false,
+        };
+        let tx = tx.clone();
+        async move {
+            tx.send(msg).await.unwrap();
+            reply.await.unwrap()
+        }
+    };
+    assert!(matches!(
+        send(b"\x02I0\r").await,
+        Err(ControlError::FrameTooLong(64))
+    ));
+    assert_eq!(send(b"\x02I1\r").await.unwrap(), b"\x02I11\r");
+    drop(send);
+    client_handle.await.unwrap().unwrap();
+    server.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_heartbeat() {
+    use crate::controllers::clear_core::{Message, CR, STX};
+    use crate::testing::MockClearCore;
+    use std::time::Duration;
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
+
+    let mock = MockClearCore::start().await.unwrap();
+    let (tx, rx) = mpsc::channel::<Message>(10);
+    let config = ClientConfig {
+        heartbeat: Some(Duration::from_millis(10)),
+        ..Default::default()
+    };
+    let client_handle = tokio::spawn(client_with_config(mock.addr(), rx, config));
+    tokio::time::sleep(Duration::from_millis(60)).await;
+    let received = mock.received();
+    assert!(received.len() >= 2);
+    assert!(received.iter().all(|frame| frame == &[STX, b'I', b'0', CR]));
+    drop(tx);
+    client_handle.await.unwrap().unwrap();
+
+    //A peer that accepts but has gone quiet is caught without sending any command
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        drop(stream);
+    });
+    let (_tx, rx) = mpsc::channel::<Message>(10);
+    let config = ClientConfig {
+        reconnect: Reconnect::Never,
+        command_timeout: Duration::from_millis(20),
+        heartbeat: Some(Duration::from_millis(10)),
+        ..Default::default()
+    };
+    assert!(client_with_config(addr, rx, config).await.is_err());
+    server.abort();
+}
+
+#[tokio::test]
+async fn test_min_write_interval() {
+    use crate::controllers::clear_core::{Message, CR, STX};
+    use std::time::Duration;
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
+    use tokio::time::Instant;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut arrivals = Vec::new();
+        let mut buffer = [0; 16];
+        for _ in 0..3 {
+            stream.read(&mut buffer).await.unwrap();
+            arrivals.push(Instant::now());
+            stream.write_all(b"\x02I00\r").await.unwrap();
+        }
+        arrivals
+    });
+
+    let (tx, rx) = mpsc::channel::<Message>(1);
+    let config = ClientConfig {
+        min_write_interval: Some(Duration::from_millis(30)),
+        ..Default::default()
+    };
+    let client_handle = tokio::spawn(client_with_config(addr, rx, config));
+    //The queue holds one message, so the sends back up behind the spacing without getting stuck
+    let mut replies = Vec::new();
+    for _ in 0..3 {
+        let (response, reply) = oneshot::channel();
+        tx.send(Message {
+            buffer: vec![STX, b'I', b'0', CR],
+            response,
+            timeout: None,
+            idempotent: false,
+        })
+        .await
+        .unwrap();
+        replies.push(reply);
+    }
+    for reply in replies {
+        assert_eq!(reply.await.unwrap().unwrap(), b"\x02I00\r");
+    }
+    let arrivals = server.await.unwrap();
+    for pair in arrivals.windows(2) {
+        assert!(pair[1] - pair[0] >= Duration::from_millis(25));
+    }
+    drop(tx);
+    client_handle.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_connect_timeout() {
+    use crate::controllers::clear_core::Message;
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
+    use tokio::time::Instant;
+
+    //Nothing answers there, depending on the network the attempt times out or is refused outright
+    let (_tx, rx) = mpsc::channel::<Message>(10);
+    let config = ClientConfig {
+        reconnect: Reconnect::Never,
+        connect_timeout: Duration::from_millis(50),
+        ..Default::default()
+    };
+    let start = Instant::now();
+    assert!(client_with_config("10.255.255.1:8888", rx, config)
+        .await
+        .is_err());
+    assert!(start.elapsed() < Duration::from_secs(1));
+
+    //A controller that comes up after the client is waited for
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    drop(listener);
+    let (tx, rx) = mpsc::channel::<Message>(10);
+    let config = ClientConfig {
+        reconnect: Reconnect::MaxRetries(50),
+        min_backoff: Duration::from_millis(10),
+        max_backoff: Duration::from_millis(10),
+        ..Default::default()
+    };
+    let client_handle = tokio::spawn(client_with_config(addr, rx, config));
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let listener = TcpListener::bind(addr).await.unwrap();
+    let (_stream, _) = listener.accept().await.unwrap();
+    drop(tx);
+    client_handle.await.unwrap().unwrap();
 }
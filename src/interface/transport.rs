@@ -0,0 +1,769 @@
+use crate::controllers::clear_core::{Message, CR, STX};
+use crate::error::{ControlError, Result};
+use crate::interface::audit::Recorder;
+use crate::interface::correlated::serve_correlated;
+use crate::interface::framing::Framing;
+use crate::telemetry;
+use crate::util::utils::to_hex;
+use serde::Serialize;
+use std::future::Future;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{mpsc, watch};
+use tokio::time::{sleep, sleep_until, Instant, MissedTickBehavior};
+use tracing::{debug, error, info, info_span, warn, Instrument};
+
+pub(crate) const READ_CHUNK: usize = 128;
+//Reading input 0 is about the cheapest thing the firmware answers
+const HEARTBEAT_FRAME: [u8; 4] = [STX, b'I', b'0', CR];
+pub(crate) const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reconnect {
+    Never,
+    MaxRetries(usize),
+    Forever,
+}
+
+//Frames written straight after every reconnect before anything else is served, e.g. the outputs'
+//safe states. Shared so the owner can change them while the client runs.
+#[derive(Debug, Clone, Default)]
+pub struct ReconnectFrames(Arc<Mutex<Vec<Vec<u8>>>>);
+
+impl ReconnectFrames {
+    pub fn set(&self, frames: Vec<Vec<u8>>) {
+        *self.0.lock().unwrap() = frames;
+    }
+
+    pub fn get(&self) -> Vec<Vec<u8>> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub enum ConnectionState {
+    Connected,
+    //The link was lost and the client is trying to get it back, commands fail until it does
+    Reconnecting,
+    //Not connected yet, or the client gave up or exited
+    #[default]
+    Disconnected,
+}
+
+//Where the client publishes its ConnectionState, shared so the owner can hand out receivers
+#[derive(Debug, Clone)]
+pub struct ConnectionStatus(Arc<watch::Sender<ConnectionState>>);
+
+impl Default for ConnectionStatus {
+    fn default() -> Self {
+        Self(Arc::new(watch::Sender::new(ConnectionState::default())))
+    }
+}
+
+impl ConnectionStatus {
+    pub fn subscribe(&self) -> watch::Receiver<ConnectionState> {
+        self.0.subscribe()
+    }
+
+    pub fn get(&self) -> ConnectionState {
+        *self.0.borrow()
+    }
+
+    //Receivers only wake up for an actual change
+    pub(crate) fn set(&self, state: ConnectionState) {
+        self.0.send_if_modified(|current| {
+            if *current == state {
+                return false;
+            }
+            *current = state;
+            true
+        });
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ClientConfig {
+    pub reconnect: Reconnect,
+    pub min_backoff: Duration,
+    pub max_backoff: Duration,
+    //How long one attempt at opening a TCP connection may take before it counts as failed, an
+    //unreachable host otherwise ties it up for as long as the OS allows. The first connect is
+    //retried by `reconnect` the same as a reconnect is.
+    pub connect_timeout: Duration,
+    //Used for any message that doesn't carry its own timeout
+    pub command_timeout: Duration,
+    //Only turn this on for firmware that checksums its frames too
+    pub checksum: bool,
+    //How frames are delimited on the wire, replies are handed back as STX..CR whatever this is
+    pub framing: Framing,
+    //Probe the link after this long without traffic, a probe that fails or times out is handled
+    //like any other dead connection. None turns it off.
+    pub heartbeat: Option<Duration>,
+    pub on_reconnect: ReconnectFrames,
+    //Tag every frame with a sequence id and match replies by it rather than by order, which lets
+    //several commands be outstanding at once. Only for firmware that echoes the ids back.
+    pub sequence_ids: bool,
+    //How many times a transient failure (timeout, lost connection, garbled reply) is retried for
+    //commands marked idempotent, others always fail straight away since they may already have
+    //run once. A lost connection is re-established before the retry. Sequence ids don't retry.
+    pub retries: usize,
+    //A reply that runs this long without its CR is thrown away up to the next STX and the waiting
+    //command fails with FrameTooLong, so a garbled stream can't grow the read buffer forever
+    pub max_frame_len: usize,
+    //Holds every write at least this long after the previous one for firmware that drops frames
+    //sent back to back. Commands wait their turn in the queue meanwhile, so senders only see it
+    //fill up. None writes as soon as a command is taken off the queue.
+    pub min_write_interval: Option<Duration>,
+    //Where every command frame and reply gets logged for an audit trail, see Recorder
+    pub recorder: Option<Recorder>,
+    pub connection: ConnectionStatus,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        Self {
+            reconnect: Reconnect::Forever,
+            min_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+            command_timeout: Duration::from_millis(500),
+            checksum: false,
+            framing: Framing::STX_CR,
+            heartbeat: None,
+            on_reconnect: ReconnectFrames::default(),
+            sequence_ids: false,
+            retries: 0,
+            max_frame_len: 64,
+            min_write_interval: None,
+            recorder: None,
+            connection: ConnectionStatus::default(),
+        }
+    }
+}
+
+//Keeps track of when the link may next be written to under ClientConfig::min_write_interval
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct WriteSpacing {
+    interval: Option<Duration>,
+    last: Option<Instant>,
+}
+
+impl WriteSpacing {
+    pub(crate) fn new(interval: Option<Duration>) -> Self {
+        Self {
+            interval,
+            last: None,
+        }
+    }
+
+    //None when a write can go out right away
+    pub(crate) fn next_write(&self) -> Option<Instant> {
+        let due = self.last? + self.interval?;
+        (due > Instant::now()).then_some(due)
+    }
+
+    pub(crate) async fn wait(&self) {
+        if let Some(due) = self.next_write() {
+            sleep_until(due).await;
+        }
+    }
+
+    pub(crate) fn wrote(&mut self) {
+        if self.interval.is_some() {
+            self.last = Some(Instant::now());
+        }
+    }
+}
+
+//The receiving ends a client serves. Anything on `priority` goes out ahead of whatever is queued
+//on `normal`, a transaction that is already in flight still finishes first since its reply has
+//to be read before the next frame can be matched up.
+pub struct Queues {
+    pub normal: mpsc::Receiver<Message>,
+    pub priority: Option<mpsc::Receiver<Message>>,
+}
+
+impl From<mpsc::Receiver<Message>> for Queues {
+    fn from(normal: mpsc::Receiver<Message>) -> Self {
+        Self {
+            normal,
+            priority: None,
+        }
+    }
+}
+
+//A byte link to a ClearCore. The client owns framing, timeouts and retries, so an implementation
+//only has to move bytes and be able to re-establish itself after a failure.
+pub trait Transport: Send {
+    fn write(&mut self, buffer: &[u8]) -> impl Future<Output = io::Result<()>> + Send;
+    //Same contract as AsyncRead, Ok(0) means the other end closed the link. With sequence ids on
+    //a pending read gets dropped whenever a new command goes out, so it must be cancel safe.
+    fn read(&mut self, buffer: &mut [u8]) -> impl Future<Output = io::Result<usize>> + Send;
+    fn reopen(&mut self) -> impl Future<Output = io::Result<()>> + Send;
+    //Shown in logs to tell links apart, e.g. the peer address or the serial port path
+    fn peer(&self) -> String;
+}
+
+pub async fn run_client<T: Transport>(
+    transport: T,
+    msg: impl Into<Queues>,
+    config: ClientConfig,
+) -> Result<()> {
+    run_client_until(transport, msg, config, std::future::pending()).await
+}
+
+//Runs until every sender is gone or `shutdown` resolves. On shutdown whatever is mid-transaction
+//finishes, anything still queued is failed with Shutdown and the transport is dropped.
+pub async fn run_client_until<T: Transport>(
+    transport: T,
+    msg: impl Into<Queues>,
+    config: ClientConfig,
+    shutdown: impl Future<Output = ()> + Send,
+) -> Result<()> {
+    let peer = transport.peer();
+    info!(%peer, "Client connected");
+    let connection = config.connection.clone();
+    connection.set(ConnectionState::Connected);
+    let span = info_span!("clear_core_client", %peer);
+    let result = if config.sequence_ids {
+        serve_correlated(transport, msg.into(), config, shutdown)
+            .instrument(span)
+            .await
+    } else {
+        serve(transport, msg.into(), config, shutdown)
+            .instrument(span)
+            .await
+    };
+    connection.set(ConnectionState::Disconnected);
+    result
+}
+
+async fn serve<T: Transport>(
+    mut transport: T,
+    queues: Queues,
+    config: ClientConfig,
+    shutdown: impl Future<Output = ()> + Send,
+) -> Result<()> {
+    let mut tick_interval = tokio::time::interval(Duration::from_millis(5));
+    tick_interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    //Both buffers live as long as the client so the hot path doesn't allocate for them
+    let mut read_buffer = Vec::with_capacity(READ_CHUNK);
+    let mut outgoing = Vec::with_capacity(READ_CHUNK);
+    let mut last_activity = Instant::now();
+    let mut spacing = WriteSpacing::new(config.min_write_interval);
+    let Queues {
+        normal: mut msg,
+        mut priority,
+    } = queues;
+    tokio::pin!(shutdown);
+    loop {
+        let heartbeat_due = config.heartbeat.map(|interval| last_activity + interval);
+        let idle = async move {
+            match heartbeat_due {
+                Some(deadline) => sleep_until(deadline).await,
+                None => std::future::pending().await,
+            }
+        };
+        let message = tokio::select! {
+            biased;
+            _ = &mut shutdown => {
+                drain_queues(&mut msg, priority.as_mut()).await;
+                info!("Client shut down");
+                return Ok(());
+            }
+            message = recv_priority(priority.as_mut()) => match message {
+                Some(message) => message,
+                //Every priority sender is gone, carry on with the normal queue alone
+                None => {
+                    priority = None;
+                    continue;
+                }
+            },
+            message = msg.recv() => match message {
+                Some(message) => message,
+                None => break,
+            },
+            _ = idle => {
+                spacing.wait().await;
+                spacing.wrote();
+                if let Err(e) = heartbeat(&mut transport, &config, &mut read_buffer).await {
+                    recover(&mut transport, &config, &mut read_buffer, e).await?;
+                }
+                last_activity = Instant::now();
+                continue;
+            }
+        };
+        //A buffer holding several frames is a batch, it goes out in one write and the replies
+        //come back concatenated in the same order
+        let expected = frames(&message.buffer).count().max(1);
+        let timeout = message
+            .timeout
+            .unwrap_or(config.command_timeout * expected as u32);
+        //Field values are only evaluated when debug is enabled, so the hex dumps cost nothing
+        //otherwise
+        debug!(frame = %to_hex(&message.buffer), "Sending frame");
+        let frame: &[u8] = if config.checksum || !config.framing.is_stx_cr() {
+            encode(&config, &message.buffer, &mut outgoing);
+            &outgoing
+        } else {
+            &message.buffer
+        };
+        let mut attempt = 0;
+        loop {
+            //Until the gap is up the message waits here and the rest wait in the queue
+            spacing.wait().await;
+            spacing.wrote();
+            if let Some(recorder) = &config.recorder {
+                recorder.sent(frame);
+            }
+            let sent_at = Instant::now();
+            let reply = tokio::time::timeout(
+                timeout,
+                transact(&mut transport, frame, expected, &mut read_buffer, &config),
+            )
+            .await;
+            let (reply, failure) = match reply {
+                Ok(Ok(reply)) => {
+                    debug!(reply = %to_hex(&reply), "Received reply");
+                    if let Some(recorder) = &config.recorder {
+                        recorder.received(&reply, Some(sent_at.elapsed()));
+                    }
+                    telemetry::record_command(&message.buffer, sent_at.elapsed());
+                    if config.checksum {
+                        (verify_checksum(reply), None)
+                    } else {
+                        (Ok(reply), None)
+                    }
+                }
+                //The runaway bytes are already gone, the link itself is fine
+                Ok(Err(e)) if e.kind() == io::ErrorKind::InvalidData => {
+                    warn!(error = %e, "Discarded oversized reply");
+                    (Err(ControlError::FrameTooLong(config.max_frame_len)), None)
+                }
+                Ok(Err(e)) => {
+                    error!(error = %e, "Lost connection");
+                    (Err(ControlError::Disconnected), Some(e))
+                }
+                Err(_) => {
+                    //A late reply would be read as the answer to the next message, so the only
+                    //safe way to keep framing intact is to start over on a fresh connection
+                    warn!(?timeout, "No reply in time, resetting connection");
+                    telemetry::record_timeout();
+                    let e = io::Error::new(io::ErrorKind::TimedOut, "Command timed out");
+                    (Err(ControlError::Timeout), Some(e))
+                }
+            };
+            let retry = message.idempotent
+                && attempt < config.retries
+                && reply.as_ref().is_err_and(is_transient);
+            if retry {
+                attempt += 1;
+                warn!(attempt, "Retrying command");
+                if let Some(e) = failure {
+                    recover(&mut transport, &config, &mut read_buffer, e).await?;
+                }
+                continue;
+            }
+            //The caller hears back before a reconnect rather than after it
+            if message.response.send(reply).is_err() {
+                error!("Failed to send via channel");
+            }
+            if let Some(e) = failure {
+                recover(&mut transport, &config, &mut read_buffer, e).await?;
+            }
+            break;
+        }
+        last_activity = Instant::now();
+        tick_interval.tick().await;
+    }
+    Ok(())
+}
+
+//Failures where the command may never have reached the controller or its reply got mangled on
+//the way back, as opposed to the controller turning it down
+fn is_transient(e: &ControlError) -> bool {
+    matches!(
+        e,
+        ControlError::Timeout
+            | ControlError::Disconnected
+            | ControlError::ChecksumError(_)
+            | ControlError::FrameTooLong(_)
+    )
+}
+
+//Closes the queues and fails everything still in them with Shutdown
+pub(crate) async fn drain_queues(
+    msg: &mut mpsc::Receiver<Message>,
+    priority: Option<&mut mpsc::Receiver<Message>>,
+) {
+    for queue in std::iter::once(msg).chain(priority) {
+        queue.close();
+        while let Some(message) = queue.recv().await {
+            let _ = message.response.send(Err(ControlError::Shutdown));
+        }
+    }
+}
+
+pub(crate) async fn recv_priority(
+    priority: Option<&mut mpsc::Receiver<Message>>,
+) -> Option<Message> {
+    match priority {
+        Some(priority) => priority.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+pub(crate) async fn heartbeat<T: Transport>(
+    transport: &mut T,
+    config: &ClientConfig,
+    read_buffer: &mut Vec<u8>,
+) -> io::Result<()> {
+    let mut frame = Vec::with_capacity(HEARTBEAT_FRAME.len() + 2);
+    encode(config, &HEARTBEAT_FRAME, &mut frame);
+    match tokio::time::timeout(
+        config.command_timeout,
+        transact(transport, &frame, 1, read_buffer, config),
+    )
+    .await
+    {
+        Ok(Ok(_)) => {
+            debug!("Heartbeat answered");
+            Ok(())
+        }
+        Ok(Err(e)) => {
+            error!(error = %e, "Heartbeat failed");
+            Err(e)
+        }
+        Err(_) => {
+            warn!("Heartbeat went unanswered, treating the link as dead");
+            telemetry::record_timeout();
+            Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "Heartbeat timed out",
+            ))
+        }
+    }
+}
+
+pub(crate) async fn recover<T: Transport>(
+    transport: &mut T,
+    config: &ClientConfig,
+    read_buffer: &mut Vec<u8>,
+    e: io::Error,
+) -> Result<()> {
+    if config.reconnect == Reconnect::Never {
+        return Err(ControlError::Io(e));
+    }
+    config.connection.set(ConnectionState::Reconnecting);
+    loop {
+        //Whatever was buffered belongs to the dead connection
+        read_buffer.clear();
+        reopen(transport, config).await?;
+        info!("Client reconnected");
+        telemetry::record_reconnect();
+        match restore(transport, config, read_buffer).await {
+            //Only counts as connected once the safe states are back in place
+            Ok(()) => {
+                config.connection.set(ConnectionState::Connected);
+                return Ok(());
+            }
+            Err(e) => warn!(error = %e, "Failed to restore safe states, reconnecting again"),
+        }
+    }
+}
+
+//The board may have sat in whatever state it was in while the link was down, so the reconnect
+//frames go out before any queued command
+async fn restore<T: Transport>(
+    transport: &mut T,
+    config: &ClientConfig,
+    read_buffer: &mut Vec<u8>,
+) -> io::Result<()> {
+    let frames = config.on_reconnect.get();
+    if frames.is_empty() {
+        return Ok(());
+    }
+    warn!(
+        frames = frames.len(),
+        "Re-applying safe states after reconnect"
+    );
+    let mut outgoing = Vec::new();
+    encode(config, &frames.concat(), &mut outgoing);
+    let timeout = config.command_timeout * frames.len() as u32;
+    if let Some(recorder) = &config.recorder {
+        recorder.sent(&outgoing);
+    }
+    let sent_at = Instant::now();
+    match tokio::time::timeout(
+        timeout,
+        transact(transport, &outgoing, frames.len(), read_buffer, config),
+    )
+    .await
+    {
+        Ok(reply) => {
+            let reply = reply?;
+            if let Some(recorder) = &config.recorder {
+                recorder.received(&reply, Some(sent_at.elapsed()));
+            }
+            Ok(())
+        }
+        Err(_) => Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            "Restoring safe states timed out",
+        )),
+    }
+}
+
+//Writes the buffer in one go and reads back `expected` reply frames, concatenated into the one
+//Vec that is handed to the caller
+async fn transact<T: Transport>(
+    transport: &mut T,
+    buffer: &[u8],
+    expected: usize,
+    read_buffer: &mut Vec<u8>,
+    config: &ClientConfig,
+) -> io::Result<Vec<u8>> {
+    transport.write(buffer).await?;
+    let mut reply = Vec::new();
+    let mut received = 0;
+    let mut chunk = [0; READ_CHUNK];
+    while received < expected {
+        if config.framing.take_frame_into(read_buffer, &mut reply) {
+            received += 1;
+            continue;
+        }
+        if config
+            .framing
+            .discard_oversized(read_buffer, config.max_frame_len)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Reply too long without a CR",
+            ));
+        }
+        match transport.read(&mut chunk).await? {
+            0 => {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "Connection closed by server",
+                ))
+            }
+            n => read_buffer.extend_from_slice(&chunk[..n]),
+        }
+    }
+    Ok(reply)
+}
+
+//Pulls the first complete STX..=CR frame out of the buffer, see Framing::take_frame_into
+pub(crate) fn take_frame(buffer: &mut Vec<u8>) -> Option<Vec<u8>> {
+    let mut frame = Vec::new();
+    Framing::STX_CR
+        .take_frame_into(buffer, &mut frame)
+        .then_some(frame)
+}
+
+//The STX..CR frames in `buffer` as they go on the wire, checksummed if the config says so and
+//delimited by its framing, in place of whatever `out` held
+pub(crate) fn encode(config: &ClientConfig, buffer: &[u8], out: &mut Vec<u8>) {
+    out.clear();
+    if !config.checksum {
+        config.framing.encode_into(buffer, out);
+    } else if config.framing.is_stx_cr() {
+        for frame in frames(buffer) {
+            append_checksum_into(frame, out);
+        }
+    } else {
+        let mut checked = Vec::with_capacity(buffer.len() + 2);
+        for frame in frames(buffer) {
+            append_checksum_into(frame, &mut checked);
+        }
+        config.framing.encode_into(&checked, out);
+    }
+}
+
+//The CR-terminated frames in a buffer that is known to be well formed, e.g. an outgoing batch.
+//Anything after the last CR, like the padding on the output off command, isn't a frame.
+pub(crate) fn frames(buffer: &[u8]) -> impl Iterator<Item = &[u8]> {
+    buffer
+        .split_inclusive(|&byte| byte == CR)
+        .filter(|frame| frame.last() == Some(&CR))
+}
+
+pub(crate) fn split_frames(buffer: &[u8]) -> Vec<Vec<u8>> {
+    let mut pending = buffer.to_vec();
+    std::iter::from_fn(|| take_frame(&mut pending)).collect()
+}
+
+//XOR of everything between STX and CR, sent as two uppercase hex digits so it can never collide
+//with STX or CR
+fn checksum(payload: &[u8]) -> [u8; 2] {
+    let sum = payload.iter().fold(0, |acc, byte| acc ^ byte);
+    [hex_digit(sum >> 4), hex_digit(sum & 0x0f)]
+}
+
+pub(crate) fn hex_digit(nibble: u8) -> u8 {
+    b"0123456789ABCDEF"[nibble as usize]
+}
+
+#[cfg(test)]
+pub(crate) fn append_checksum(frame: &[u8]) -> Vec<u8> {
+    let mut checked = Vec::with_capacity(frame.len() + 2);
+    append_checksum_into(frame, &mut checked);
+    checked
+}
+
+pub(crate) fn append_checksum_into(frame: &[u8], out: &mut Vec<u8>) {
+    match frame.split_last() {
+        Some((&CR, body)) if !body.is_empty() => {
+            out.extend_from_slice(body);
+            out.extend_from_slice(&checksum(&body[1..]));
+            out.push(CR);
+        }
+        _ => out.extend_from_slice(frame),
+    }
+}
+
+//Checks and strips the checksum of every frame in the reply so callers see the same reply as
+//without one
+pub(crate) fn verify_checksum(reply: Vec<u8>) -> Result<Vec<u8>> {
+    for frame in frames(&reply) {
+        if frame.len() < 4 {
+            return Err(ControlError::ChecksumError(reply));
+        }
+        let sum_idx = frame.len() - 3;
+        if frame[sum_idx..sum_idx + 2] != checksum(&frame[1..sum_idx]) {
+            warn!(frame = %to_hex(frame), "Checksum mismatch");
+            return Err(ControlError::ChecksumError(reply));
+        }
+    }
+    //Stripped in place back to front so the offsets of the earlier frames stay put
+    let mut stripped = reply;
+    let mut end = stripped.len();
+    while end > 0 {
+        let start = stripped[..end - 1]
+            .iter()
+            .rposition(|&byte| byte == CR)
+            .map_or(0, |idx| idx + 1);
+        stripped.drain(end - 3..end - 1);
+        end = start;
+    }
+    Ok(stripped)
+}
+
+async fn reopen<T: Transport>(transport: &mut T, config: &ClientConfig) -> io::Result<()> {
+    let mut backoff = config.min_backoff;
+    let mut attempts = 0;
+    loop {
+        match transport.reopen().await {
+            Ok(()) => return Ok(()),
+            Err(e) => {
+                attempts += 1;
+                if !back_off(config, attempts, &mut backoff, &e).await {
+                    return Err(e);
+                }
+            }
+        }
+    }
+}
+
+//Waits out `backoff` after a failed connect attempt and doubles it, or returns false once
+//`config.reconnect` has run out of attempts. Never runs out straight away.
+pub(crate) async fn back_off(
+    config: &ClientConfig,
+    attempts: usize,
+    backoff: &mut Duration,
+    e: &io::Error,
+) -> bool {
+    let give_up = match config.reconnect {
+        Reconnect::Never => true,
+        Reconnect::MaxRetries(max) => attempts >= max,
+        Reconnect::Forever => false,
+    };
+    if give_up {
+        error!(attempts, error = %e, "Giving up on connecting");
+        return false;
+    }
+    warn!(attempts, error = %e, ?backoff, "Connect attempt failed");
+    sleep(*backoff).await;
+    *backoff = (*backoff * 2).min(config.max_backoff);
+    true
+}
+
+#[test]
+fn test_take_frame_split_across_reads() {
+    let mut buffer = vec![STX, b'M', b'0'];
+    assert_eq!(take_frame(&mut buffer), None);
+    buffer.extend_from_slice(&[b'_', CR]);
+    assert_eq!(
+        take_frame(&mut buffer),
+        Some(vec![STX, b'M', b'0', b'_', CR])
+    );
+    assert!(buffer.is_empty());
+}
+
+#[test]
+fn test_take_frame_coalesced_and_garbage() {
+    let mut buffer = vec![0, 0, b'x', STX, b'I', b'1', b'1', CR, STX, b'I', b'2'];
+    assert_eq!(
+        take_frame(&mut buffer),
+        Some(vec![STX, b'I', b'1', b'1', CR])
+    );
+    assert_eq!(take_frame(&mut buffer), None);
+    assert_eq!(buffer, vec![STX, b'I', b'2']);
+
+    let mut buffer = vec![STX, b'M', STX, b'M', b'1', b'?', CR];
+    assert_eq!(
+        take_frame(&mut buffer),
+        Some(vec![STX, b'M', b'1', b'?', CR])
+    );
+
+    let mut buffer = vec![b'g', b'a', b'r', CR];
+    assert_eq!(take_frame(&mut buffer), None);
+    assert!(buffer.is_empty());
+}
+
+#[test]
+fn test_discard_oversized() {
+    let framing = Framing::STX_CR;
+    let mut buffer = vec![STX, b'M', b'0'];
+    assert!(!framing.discard_oversized(&mut buffer, 8));
+    buffer.extend_from_slice(&[b'x'; 8]);
+    buffer.extend_from_slice(&[STX, b'I', b'1']);
+    assert!(framing.discard_oversized(&mut buffer, 8));
+    //Resynced on the next STX, which can still complete
+    assert_eq!(buffer, vec![STX, b'I', b'1']);
+
+    let mut buffer = [&[STX][..], &[b'x'; 20]].concat();
+    assert!(framing.discard_oversized(&mut buffer, 8));
+    assert!(buffer.is_empty());
+}
+
+#[test]
+fn test_frames_ignores_padding() {
+    let off = [STX, b'O', b'1', b'0', CR, 0, 0, 0, 0];
+    assert_eq!(frames(&off).count(), 1);
+    assert_eq!(frames(b"\x02M0SV2000\r\x02M0AM800\r").count(), 2);
+}
+
+#[test]
+fn test_checksum() {
+    let frame = append_checksum(b"\x02M0GS\r");
+    //'M' ^ '0' ^ 'G' ^ 'S' = 0x69
+    assert_eq!(frame, b"\x02M0GS69\r");
+    assert_eq!(verify_checksum(frame.clone()).unwrap(), b"\x02M0GS\r");
+    //Batched replies carry one checksum per frame
+    let batch = [frame.clone(), append_checksum(b"\x02I1\r")].concat();
+    assert_eq!(verify_checksum(batch).unwrap(), b"\x02M0GS\r\x02I1\r");
+
+    let mut corrupted = frame;
+    corrupted[3] = b'T';
+    assert!(matches!(
+        verify_checksum(corrupted),
+        Err(ControlError::ChecksumError(_))
+    ));
+    assert!(matches!(
+        verify_checksum(vec![STX, CR]),
+        Err(ControlError::ChecksumError(_))
+    ));
+}
@@ -1,5 +1,11 @@
+#[cfg(feature = "blocking")]
+pub mod blocking;
 pub mod components;
 pub mod controllers;
+pub mod error;
 pub mod interface;
 pub mod subsystems;
+mod telemetry;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod testing;
 pub mod util;
@@ -1,5 +1,6 @@
 use crate::components::clear_core_io::{DigitalInput, HBridgeState};
 use crate::components::clear_core_motor::{ClearCoreMotor, Status};
+use crate::error::ControlError;
 use crate::subsystems::linear_actuator::SimpleLinearActuator;
 use log::error;
 use std::error::Error;
@@ -45,7 +46,7 @@ impl BagGripper {
 
     pub async fn rip_bag(&self) -> Result<(), Box<dyn Error>> {
         for pos in self.positions.as_slice() {
-            self.motor.absolute_move(*pos).await.unwrap();
+            self.motor.move_absolute(*pos).await.unwrap();
             self.motor
                 .wait_for_move(Duration::from_millis(150))
                 .await
@@ -66,25 +67,25 @@ impl BagDispenser {
     }
     pub async fn dispense(&self) -> Result<(), Box<dyn Error>> {
         let mut interval = interval(Duration::from_millis(100));
-        self.motor.set_velocity(3.0).await;
-        let _ = self.motor.relative_move(100.0).await;
-        while !self.photo_eye.photo_eye.get_state().await {
+        self.motor.set_velocity_limit(3.0).await?;
+        let _ = self.motor.move_relative(100.0).await;
+        while !self.photo_eye.photo_eye.get_state().await? {
             interval.tick().await;
         }
-        self.motor.abrupt_stop().await;
+        self.motor.abrupt_stop().await?;
         Ok(())
     }
     pub async fn pull_back(&self) -> Result<(), Box<dyn Error>> {
         let mut interval = interval(Duration::from_millis(100));
-        self.motor.set_velocity(1.5).await;
-        self.motor.relative_move(-4.6).await.unwrap();
-        while self.motor.get_status().await == Status::Moving {
+        self.motor.set_velocity_limit(1.5).await?;
+        self.motor.move_relative(-4.6).await.unwrap();
+        while self.motor.get_status().await?.state == Status::Moving {
             interval.tick().await;
         }
         Ok(())
     }
     
-    pub async fn check_photo_eye(&self) -> BagSensorState {
+    pub async fn check_photo_eye(&self) -> Result<BagSensorState, ControlError> {
         // TODO: i think this may be inverted?
         self.photo_eye.check().await
     }
@@ -102,17 +103,21 @@ impl BagSensor {
         Self { photo_eye }
     }
 
-    pub async fn check(&self) -> BagSensorState {
-        match self.photo_eye.get_state().await {
-            true => BagSensorState::Bagless,
-            false => BagSensorState::Bagful,
+    pub async fn check(&self) -> Result<BagSensorState, ControlError> {
+        match self.photo_eye.get_state().await? {
+            true => Ok(BagSensorState::Bagless),
+            false => Ok(BagSensorState::Bagful),
         }
     }
 
     pub async fn watcher(photo_eye: DigitalInput, tx: Sender<BagSensorState>) {
         let sensor = Self::new(photo_eye);
         loop {
-            tx.clone().send(sensor.check().await).await.unwrap();
+            //A failed read is skipped rather than reported as either state
+            match sensor.check().await {
+                Ok(state) => tx.clone().send(state).await.unwrap(),
+                Err(e) => error!("Photo eye failed to read: {e}"),
+            }
             sleep(Duration::from_millis(50)).await;
         }
     }
@@ -233,7 +238,7 @@ pub enum BagError {
 //         let gantry = ClearCoreMotor::new(0, 800, tx);
 //
 //         tokio::time::sleep(Duration::from_millis(100)).await;
-//         gantry.relative_move(25.0).await.unwrap();
+//         gantry.move_relative(25.0).await.unwrap();
 //     });
 //     let (_, _, _) = tokio::join!(task, cc1_handler, cc2_handler);
 // }
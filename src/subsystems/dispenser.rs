@@ -102,16 +102,13 @@ impl Dispenser {
         if current_time - last_cmd_time > Duration::from_millis(200) {
             let new_speed = error * self.parameters.motor_speed;
             if new_speed >= 0.1 {
-                self.motor
-                    .set_velocity(if new_speed > self.parameters.motor_speed {
-                        self.parameters.motor_speed
-                    } else {
-                        new_speed
-                    })
-                    .await;
+                let speed = new_speed.min(self.parameters.motor_speed);
+                if let Err(e) = self.motor.set_velocity_limit(speed).await {
+                    error!("Failed to set velocity: {e}");
+                }
             }
             self.motor
-                .relative_move(20.)
+                .move_relative(20.)
                 .await
                 .expect("Motor faulted or not enabled");
             Some(Instant::now())
@@ -123,7 +120,7 @@ impl Dispenser {
     async fn retract_before(&self) {
         if let Some(retract) = self.parameters.retract_before {
             self.motor
-                .relative_move(-retract)
+                .move_relative(-retract)
                 .await
                 .expect("Motor faulted");
             self.motor
@@ -136,7 +133,7 @@ impl Dispenser {
     async fn retract_after(&self) {
         if let Some(retract) = self.parameters.retract_after {
             self.motor
-                .relative_move(-retract)
+                .move_relative(-retract)
                 .await
                 .expect("Motor faulted");
         }
@@ -167,9 +164,15 @@ impl Dispenser {
                 let target_weight = init_weight - w.setpoint;
 
                 // Starting motor moves
-                self.motor.set_velocity(self.parameters.motor_speed).await;
+                if let Err(e) = self
+                    .motor
+                    .set_velocity_limit(self.parameters.motor_speed)
+                    .await
+                {
+                    error!("Failed to set velocity: {e}");
+                }
                 self.retract_before().await;
-                self.motor.relative_move(100.).await.expect("Motor faulted");
+                self.motor.move_relative(100.).await.expect("Motor faulted");
 
                 let shutdown = Arc::new(AtomicBool::new(false));
                 signal_hook::flag::register(signal_hook::consts::SIGINT, Arc::clone(&shutdown))
@@ -178,13 +181,17 @@ impl Dispenser {
                 // not at setpoint
                 let end_condition = loop {
                     if shutdown.load(Ordering::Relaxed) {
-                        self.motor.abrupt_stop().await;
+                        if let Err(e) = self.motor.abrupt_stop().await {
+                            error!("Motor failed to stop: {e}");
+                        }
                         break DispenseEndCondition::Failed;
                     }
 
                     let current_time = Instant::now();
                     if current_time - init_time > timeout {
-                        self.motor.abrupt_stop().await;
+                        if let Err(e) = self.motor.abrupt_stop().await {
+                            error!("Motor failed to stop: {e}");
+                        }
                         error!("Dispense timed out!");
                         break DispenseEndCondition::Timeout(init_weight - curr_weight);
                     }
@@ -196,28 +203,40 @@ impl Dispenser {
 
                     if curr_weight < target_weight + self.parameters.check_offset {
                         info!("Check offset reached");
-                        self.motor.abrupt_stop().await;
+                        if let Err(e) = self.motor.abrupt_stop().await {
+                            error!("Motor failed to stop: {e}");
+                        }
                         let check_weight = self
                             .get_median_weight(30, self.parameters.sample_rate)
                             .await;
                         if check_weight < target_weight + self.parameters.stop_offset {
                             break DispenseEndCondition::WeightAchieved(init_weight - check_weight);
                         }
-                        self.motor.relative_move(10.).await.unwrap();
+                        self.motor.move_relative(10.).await.unwrap();
                         tokio::time::sleep(Duration::from_millis(200)).await;
                     }
                     interval.tick().await;
                 };
-                self.motor.abrupt_stop().await;
+                if let Err(e) = self.motor.abrupt_stop().await {
+                    error!("Motor failed to stop: {e}");
+                }
                 self.retract_after().await;
                 info!("End Condition: {:?}", end_condition);
             }
             Setpoint::Timed(d) => {
-                self.motor.set_velocity(self.parameters.motor_speed).await;
+                if let Err(e) = self
+                    .motor
+                    .set_velocity_limit(self.parameters.motor_speed)
+                    .await
+                {
+                    error!("Failed to set velocity: {e}");
+                }
                 self.retract_before().await;
-                self.motor.relative_move(100.).await.expect("Motor faulted");
+                self.motor.move_relative(100.).await.expect("Motor faulted");
                 tokio::time::sleep(*d).await;
-                self.motor.abrupt_stop().await;
+                if let Err(e) = self.motor.abrupt_stop().await {
+                    error!("Motor failed to stop: {e}");
+                }
                 self.retract_after().await;
             }
         }
@@ -232,7 +251,7 @@ pub enum DispenseEndCondition {
 
 #[tokio::test]
 async fn dispense() {
-    let (cc, cl) = Controller::with_client("192.168.1.12", &[MotorBuilder { id: 0, scale: 800}]);
+    let (cc, cl) = Controller::with_client("192.168.1.12", &[MotorBuilder { id: 0, scale: 800, ..Default::default() }]);
     tokio::spawn(cl);
     let mut scale = Scale::new(716692);
     scale = scale.connect().unwrap();
@@ -20,17 +20,17 @@ pub async fn gantry(motor: ClearCoreMotor, mut rx: Receiver<GantryCommand>) {
     while let Some(cmd) = rx.recv().await {
         match cmd {
             GantryCommand::GetPosition(sender) => {
-                let pos = motor.get_position().await;
+                let pos = motor.get_position().await.unwrap();
                 info!("Motor at pos: {pos}");
                 sender.send(pos).unwrap();
             }
             GantryCommand::GoTo(cmd) => {
-                motor.absolute_move(cmd.pos).await.unwrap();
+                motor.move_absolute(cmd.pos).await.unwrap();
                 info!("Motor absolute move commanded: {}", cmd.pos);
-                while motor.get_status().await == Status::Moving {
+                while motor.get_status().await.unwrap().state == Status::Moving {
                     tokio::time::sleep(Duration::from_secs_f64(0.25)).await;
                 }
-                let pos = motor.get_position().await;
+                let pos = motor.get_position().await.unwrap();
                 info!("Motor absolute move complete, current pos: {pos}");
                 cmd.resp.send(pos).unwrap()
             }
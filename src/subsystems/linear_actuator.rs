@@ -31,7 +31,7 @@ impl SimpleLinearActuator {
     }
     pub async fn get_feedback(&self) -> Option<isize> {
         if let Some(fb) = self.feedback.as_ref() {
-            Some(fb.get_state().await)
+            Some(fb.get_state().await.expect("Failed to read feedback"))
         } else {
             None
         }
@@ -88,9 +88,14 @@ impl RelayHBridge {
     }
 
     pub async fn get_feedback(&self) -> isize {
-        let mut position = self.fb_pair.0.get_state().await;
+        let mut position = self
+            .fb_pair
+            .0
+            .get_state()
+            .await
+            .expect("Failed to read feedback");
         if let Some(fb) = &self.fb_pair.1 {
-            let pos_b = fb.get_state().await;
+            let pos_b = fb.get_state().await.expect("Failed to read feedback");
             position = (position + pos_b) / 2
         }
         // warn!("Output {:?} at position {:?}", self.output_pair, position);
@@ -1,5 +1,6 @@
 use crate::components::clear_core_motor::ClearCoreMotor;
 use crate::components::scale::Scale;
+use log::error;
 use serde::Deserialize;
 use std::error::Error;
 use tokio::sync::mpsc::Receiver;
@@ -101,9 +102,15 @@ impl Node {
                                           // motor_speed: f64,
     ) -> (Scale, Vec<Duration>, Vec<f64>) {
         // Prime conveyor
-        self.motor.set_velocity(2. * parameters.motor_speed).await;
+        if let Err(e) = self
+            .motor
+            .set_velocity_limit(2. * parameters.motor_speed)
+            .await
+        {
+            error!("Failed to set velocity: {e}");
+        }
 
-        self.motor.relative_move(-10000.).await.unwrap();
+        self.motor.move_relative(-10000.).await.unwrap();
 
         // Set LP filter values
         let filter_period = 1. / parameters.sample_rate;
@@ -130,14 +137,18 @@ impl Node {
         let mut times: Vec<Duration> = Vec::new();
         let mut weights: Vec<f64> = Vec::new();
 
-        self.motor.set_velocity(parameters.motor_speed).await;
+        if let Err(e) = self.motor.set_velocity_limit(parameters.motor_speed).await {
+            error!("Failed to set velocity: {e}");
+        }
         self.motor
-            .relative_move(10000.)
+            .move_relative(10000.)
             .await
             .expect("Failed to send move command");
         let (scale, dispensed) = loop {
             if curr_weight < target_weight - parameters.check_offset {
-                self.motor.abrupt_stop().await;
+                if let Err(e) = self.motor.abrupt_stop().await {
+                    error!("Motor failed to stop: {e}");
+                }
                 (scale, final_weight) = self
                     .read_scale_median(scale, Duration::from_secs(2), 50)
                     .await;
@@ -148,7 +159,9 @@ impl Node {
             let curr_time = Instant::now();
             if curr_time - init_time > timeout {
                 // TODO: maybe violently run in reverse for a couple seconds and let it keep running?
-                self.motor.abrupt_stop().await;
+                if let Err(e) = self.motor.abrupt_stop().await {
+                    error!("Motor failed to stop: {e}");
+                }
                 println!("WARNING: Dispense timed out!");
                 break (scale, init_weight - curr_weight);
             }
@@ -163,10 +176,12 @@ impl Node {
                 let err = (curr_weight - target_weight) / parameters.serving_weight.unwrap();
                 let new_motor_speed = err * parameters.motor_speed;
                 if new_motor_speed >= 0.1 {
-                    self.motor.set_velocity(new_motor_speed).await;
+                    if let Err(e) = self.motor.set_velocity_limit(new_motor_speed).await {
+                        error!("Failed to set velocity: {e}");
+                    }
                 }
                 self.motor
-                    .relative_move(10000.0)
+                    .move_relative(10000.0)
                     .await
                     .expect("Failed to update");
             }
@@ -197,16 +212,20 @@ impl Node {
         // Data tracking
         let mut times = Vec::new();
         let mut weights = Vec::new();
-        self.motor.set_velocity(parameters.motor_speed).await;
+        if let Err(e) = self.motor.set_velocity_limit(parameters.motor_speed).await {
+            error!("Failed to set velocity: {e}");
+        }
 
         self.motor
-            .relative_move(10000.0)
+            .move_relative(10000.0)
             .await
             .expect("Failed to update");
         loop {
             let curr_time = Instant::now();
             if curr_time - init_time > parameters.timeout.unwrap() {
-                self.motor.abrupt_stop().await;
+                if let Err(e) = self.motor.abrupt_stop().await {
+                    error!("Motor failed to stop: {e}");
+                }
                 break;
             }
             (scale, reading) = self.read_scale(scale).await;
@@ -218,7 +237,7 @@ impl Node {
             if curr_time - last_sent_motor > send_command_delay {
                 last_sent_motor = Instant::now();
                 self.motor
-                    .relative_move(10000.0)
+                    .move_relative(10000.0)
                     .await
                     .expect("Failed to update");
             }
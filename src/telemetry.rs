@@ -0,0 +1,61 @@
+//Metrics hooks for the client and motors. With the metrics feature off every function here is an
+//empty inline no-op, so call sites don't need their own cfg gates.
+use std::time::Duration;
+
+#[cfg(feature = "metrics")]
+use metrics::{counter, gauge, histogram};
+
+//Motor frames are labelled by their two letter command, everything else by its device letter
+#[cfg(feature = "metrics")]
+fn command_type(frame: &[u8]) -> String {
+    match frame.get(1) {
+        Some(b'M') => String::from_utf8_lossy(frame.get(3..5).unwrap_or_default()).into_owned(),
+        Some(&device) => (device as char).to_string(),
+        None => "unknown".to_string(),
+    }
+}
+
+#[cfg(feature = "metrics")]
+pub(crate) fn record_command(frame: &[u8], latency: Duration) {
+    counter!("clear_core_commands_total", "command" => command_type(frame)).increment(1);
+    histogram!("clear_core_response_seconds").record(latency.as_secs_f64());
+}
+
+#[cfg(not(feature = "metrics"))]
+#[inline(always)]
+pub(crate) fn record_command(_frame: &[u8], _latency: Duration) {}
+
+#[cfg(feature = "metrics")]
+pub(crate) fn record_timeout() {
+    counter!("clear_core_timeouts_total").increment(1);
+}
+
+#[cfg(not(feature = "metrics"))]
+#[inline(always)]
+pub(crate) fn record_timeout() {}
+
+#[cfg(feature = "metrics")]
+pub(crate) fn record_reconnect() {
+    counter!("clear_core_reconnects_total").increment(1);
+}
+
+#[cfg(not(feature = "metrics"))]
+#[inline(always)]
+pub(crate) fn record_reconnect() {}
+
+#[cfg(feature = "metrics")]
+pub(crate) fn record_motor_position(id: u8, position: f64) {
+    gauge!("clear_core_motor_position", "motor" => id.to_string()).set(position);
+}
+
+#[cfg(not(feature = "metrics"))]
+#[inline(always)]
+pub(crate) fn record_motor_position(_id: u8, _position: f64) {}
+
+#[cfg(feature = "metrics")]
+#[test]
+fn test_command_type() {
+    assert_eq!(command_type(b"\x02M0GS\r"), "GS");
+    assert_eq!(command_type(b"\x02I3\r"), "I");
+    assert_eq!(command_type(b""), "unknown");
+}
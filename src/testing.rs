@@ -0,0 +1,185 @@
+use crate::controllers::clear_core::{CR, STX};
+use crate::interface::transport::take_frame;
+use log::{error, info};
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, DuplexStream};
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+
+type Responses = Arc<Mutex<HashMap<Vec<u8>, Vec<u8>>>>;
+type Received = Arc<Mutex<Vec<Vec<u8>>>>;
+
+//Stand-in for a ClearCore on a local socket. Replies are looked up by the longest registered
+//prefix of the frame body (everything between STX and CR), so "M0GS" answers motor 0's status
+//and "I" answers every input. Anything without a canned reply gets the plain '_' acknowledgement.
+//Every connection is served on its own task and frames on a connection are answered in order,
+//like the real controller. One made with in_memory has no socket and is only reachable through
+//stream().
+pub struct MockClearCore {
+    addr: Option<SocketAddr>,
+    responses: Responses,
+    received: Received,
+    server: Option<JoinHandle<()>>,
+}
+
+impl MockClearCore {
+    pub async fn start() -> io::Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let responses: Responses = Arc::default();
+        let received: Received = Arc::default();
+        let server = tokio::spawn(serve(listener, responses.clone(), received.clone()));
+        Ok(Self {
+            addr: Some(addr),
+            responses,
+            received,
+            server: Some(server),
+        })
+    }
+
+    pub fn in_memory() -> Self {
+        Self {
+            addr: None,
+            responses: Arc::default(),
+            received: Arc::default(),
+            server: None,
+        }
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+            .expect("an in memory mock has no address, connect through stream()")
+    }
+
+    //A new connection over an in memory pipe, for client_with_stream
+    pub fn stream(&self) -> DuplexStream {
+        let (client, server) = tokio::io::duplex(4096);
+        tokio::spawn(handle(
+            server,
+            self.responses.clone(),
+            self.received.clone(),
+        ));
+        client
+    }
+
+    //The reply payload goes after the device prefix, e.g. on(b"M0GS", b"3233") answers with
+    //"\x02M03233\r"
+    pub fn on(&self, command: &[u8], reply: &[u8]) -> &Self {
+        self.responses
+            .lock()
+            .unwrap()
+            .insert(command.to_vec(), reply.to_vec());
+        self
+    }
+
+    //Every complete frame received so far, STX and CR included
+    pub fn received(&self) -> Vec<Vec<u8>> {
+        self.received.lock().unwrap().clone()
+    }
+}
+
+impl Drop for MockClearCore {
+    fn drop(&mut self) {
+        if let Some(server) = &self.server {
+            server.abort();
+        }
+    }
+}
+
+async fn serve(listener: TcpListener, responses: Responses, received: Received) {
+    let mut connections = tokio::task::JoinSet::new();
+    loop {
+        match listener.accept().await {
+            Ok((stream, peer)) => {
+                info!("Mock ClearCore accepted {peer}");
+                connections.spawn(handle(stream, responses.clone(), received.clone()));
+            }
+            Err(e) => {
+                error!("Mock ClearCore failed to accept: {e}");
+                return;
+            }
+        }
+    }
+}
+
+async fn handle<S: AsyncRead + AsyncWrite + Unpin>(
+    mut stream: S,
+    responses: Responses,
+    received: Received,
+) {
+    let mut buffer = Vec::new();
+    let mut chunk = [0; 128];
+    loop {
+        while let Some(frame) = take_frame(&mut buffer) {
+            let reply = reply_for(&frame, &responses);
+            received.lock().unwrap().push(frame);
+            if stream.write_all(reply.as_slice()).await.is_err() {
+                return;
+            }
+        }
+        match stream.read(&mut chunk).await {
+            Ok(0) | Err(_) => return,
+            Ok(n) => buffer.extend_from_slice(&chunk[..n]),
+        }
+    }
+}
+
+fn reply_for(frame: &[u8], responses: &Responses) -> Vec<u8> {
+    let body = &frame[1..frame.len() - 1];
+    let responses = responses.lock().unwrap();
+    let payload = responses
+        .iter()
+        .filter(|(command, _)| body.starts_with(command))
+        .max_by_key(|(command, _)| command.len())
+        .map(|(_, reply)| reply.as_slice())
+        .unwrap_or(b"_");
+    let mut reply = vec![STX];
+    reply.extend_from_slice(&body[..body.len().min(2)]);
+    reply.extend_from_slice(payload);
+    reply.push(CR);
+    reply
+}
+
+#[tokio::test]
+async fn test_mock_clear_core() {
+    use crate::controllers::clear_core::{Controller, MotorBuilder};
+    use crate::interface::tcp::client;
+
+    let mock = MockClearCore::start().await.unwrap();
+    mock.on(b"M0GS", b"3233")
+        .on(b"M0GP", b"-800")
+        .on(b"I1", b"1");
+    let motors = [MotorBuilder {
+        id: 0,
+        scale: 800,
+        ..Default::default()
+    }];
+    let (controller, client_future) = Controller::with_client(mock.addr(), motors.as_slice());
+    let handle = tokio::spawn(client_future);
+
+    let motor = controller.get_motor(0);
+    let input = controller.get_digital_input(1);
+    let (position, state, moved) = tokio::join!(
+        motor.get_position(),
+        input.get_state(),
+        motor.move_relative(1.0)
+    );
+    assert_eq!(position.unwrap(), -1.0);
+    assert!(state.unwrap());
+    moved.unwrap();
+    assert!(motor.get_status().await.unwrap().at_target);
+    assert_eq!(mock.received().len(), 4);
+
+    //A second client on its own connection is served alongside the first
+    let (tx, rx) = tokio::sync::mpsc::channel(10);
+    let second = tokio::spawn(client(mock.addr(), rx));
+    let other = Controller::new(tx, &[]).get_digital_input(2);
+    assert!(!other.get_state().await.unwrap());
+
+    drop((controller, motor, input, other));
+    handle.await.unwrap().unwrap();
+    second.await.unwrap().unwrap();
+}
@@ -1 +1,3 @@
+pub mod number;
+pub mod poll;
 pub mod utils;
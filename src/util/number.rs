@@ -0,0 +1,72 @@
+use crate::util::utils::{ascii_to_int, num_to_bytes};
+
+//How the firmware writes the numbers in command values and value replies. The stock sketch
+//speaks ASCII decimal. The binary formats are a two's complement i32 in four bytes, and since
+//replies are still cut at the first CR a value with a 0x0d byte in it can't make it through
+//intact, so they're only for firmware that keeps its values clear of the framing bytes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NumberFormat {
+    #[default]
+    AsciiDecimal,
+    LittleEndian,
+    BigEndian,
+}
+
+impl NumberFormat {
+    //Binary values past the range of an i32 are saturated rather than wrapped
+    pub fn encode(&self, value: isize) -> Vec<u8> {
+        let binary = value.clamp(i32::MIN as isize, i32::MAX as isize) as i32;
+        match self {
+            NumberFormat::AsciiDecimal => num_to_bytes(value),
+            NumberFormat::LittleEndian => binary.to_le_bytes().to_vec(),
+            NumberFormat::BigEndian => binary.to_be_bytes().to_vec(),
+        }
+    }
+
+    //None when `payload` can't be a number in this format, an ASCII payload without a digit or
+    //a binary one that isn't exactly four bytes, so garbage never reads as a value
+    pub fn decode(&self, payload: &[u8]) -> Option<isize> {
+        match self {
+            NumberFormat::AsciiDecimal => payload
+                .iter()
+                .any(u8::is_ascii_digit)
+                .then(|| ascii_to_int(payload)),
+            NumberFormat::LittleEndian => {
+                Some(i32::from_le_bytes(payload.try_into().ok()?) as isize)
+            }
+            NumberFormat::BigEndian => Some(i32::from_be_bytes(payload.try_into().ok()?) as isize),
+        }
+    }
+
+    pub(crate) fn is_binary(&self) -> bool {
+        *self != NumberFormat::AsciiDecimal
+    }
+}
+
+#[test]
+fn test_number_format_round_trip() {
+    let values = [0, 1, -1, 1600, -4000, i32::MAX as isize, i32::MIN as isize];
+    for format in [
+        NumberFormat::AsciiDecimal,
+        NumberFormat::LittleEndian,
+        NumberFormat::BigEndian,
+    ] {
+        for value in values {
+            assert_eq!(
+                format.decode(&format.encode(value)),
+                Some(value),
+                "{format:?} {value}"
+            );
+        }
+    }
+    assert_eq!(NumberFormat::AsciiDecimal.encode(-800), b"-800");
+    assert_eq!(NumberFormat::LittleEndian.encode(1600), [0x40, 0x06, 0, 0]);
+    assert_eq!(NumberFormat::BigEndian.encode(1600), [0, 0, 0x06, 0x40]);
+    assert_eq!(
+        NumberFormat::BigEndian.encode(i64::MAX as isize),
+        [0x7f, 0xff, 0xff, 0xff]
+    );
+    assert_eq!(NumberFormat::AsciiDecimal.decode(b"_"), None);
+    assert_eq!(NumberFormat::LittleEndian.decode(b"1600"), Some(0x30303631));
+    assert_eq!(NumberFormat::LittleEndian.decode(&[0x40, 0x06]), None);
+}
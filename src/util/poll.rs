@@ -0,0 +1,121 @@
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+//How the wait loops pace their reads. A wait reads straight away, then every `interval`, and each
+//read that finds nothing new stretches the gap by `backoff` up to `max_interval`. Short waits stay
+//responsive while long ones stop hammering the link. A backoff of 1 polls at a fixed interval.
+//In a config file the durations are in milliseconds.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PollConfig {
+    #[serde(rename = "interval_ms", with = "millis")]
+    pub interval: Duration,
+    #[serde(rename = "max_interval_ms", with = "millis")]
+    pub max_interval: Duration,
+    pub backoff: f64,
+}
+
+impl PollConfig {
+    pub fn fixed(interval: Duration) -> Self {
+        Self {
+            interval,
+            max_interval: interval,
+            backoff: 1.,
+        }
+    }
+
+    pub(crate) fn poller(&self) -> Poller {
+        Poller {
+            config: *self,
+            next: None,
+        }
+    }
+
+    fn stretch(&self, wait: Duration) -> Duration {
+        //Neither a shrinking backoff nor a max under the interval make sense, both mean fixed
+        let stretched = wait.mul_f64(self.backoff.max(1.));
+        stretched.min(self.max_interval.max(self.interval))
+    }
+}
+
+impl Default for PollConfig {
+    fn default() -> Self {
+        Self::fixed(Duration::from_millis(50))
+    }
+}
+
+pub(crate) struct Poller {
+    config: PollConfig,
+    //None until the first tick, which doesn't wait
+    next: Option<Duration>,
+}
+
+impl Poller {
+    pub(crate) async fn tick(&mut self) {
+        match self.next {
+            None => self.next = Some(self.config.interval),
+            Some(wait) => {
+                tokio::time::sleep(wait).await;
+                self.next = Some(self.config.stretch(wait));
+            }
+        }
+    }
+
+    //Back to the base interval, for when a read did find something and more may follow
+    pub(crate) fn reset(&mut self) {
+        if self.next.is_some() {
+            self.next = Some(self.config.interval);
+        }
+    }
+}
+
+pub(crate) mod millis {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(duration.as_millis() as u64)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        u64::deserialize(deserializer).map(Duration::from_millis)
+    }
+}
+
+#[tokio::test]
+async fn test_poller_backoff() {
+    use tokio::time::Instant;
+
+    let config = PollConfig {
+        interval: Duration::from_millis(10),
+        max_interval: Duration::from_millis(40),
+        backoff: 2.,
+    };
+    let mut poller = config.poller();
+    let mut gaps = Vec::new();
+    for _ in 0..5 {
+        poller.tick().await;
+        gaps.push(poller.next.unwrap().as_millis());
+    }
+    assert_eq!(gaps, [10, 20, 40, 40, 40]);
+    poller.reset();
+    assert_eq!(poller.next, Some(Duration::from_millis(10)));
+
+    //Only waits after the first tick
+    let mut fixed = PollConfig::fixed(Duration::from_millis(20)).poller();
+    let start = Instant::now();
+    fixed.tick().await;
+    assert!(start.elapsed() < Duration::from_millis(20));
+    fixed.tick().await;
+    fixed.tick().await;
+    assert!(start.elapsed() >= Duration::from_millis(40));
+    assert_eq!(fixed.next, Some(Duration::from_millis(20)));
+}
+
+#[test]
+fn test_poll_config_toml() {
+    let config: PollConfig = toml::from_str("interval_ms = 20\nbackoff = 1.5").unwrap();
+    assert_eq!(config.interval, Duration::from_millis(20));
+    assert_eq!(config.max_interval, Duration::from_millis(50));
+    assert_eq!(config.backoff, 1.5);
+}
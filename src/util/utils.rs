@@ -10,8 +10,29 @@ pub fn int_to_byte(number: u8) -> u8 {
     number + 48
 }
 
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+//Like to_hex but with the framing bytes called out, e.g. "02<STX> 4d 30 47 53 0d<CR>"
+pub fn hex_dump(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|&byte| match byte {
+            2 => "02<STX>".to_string(),
+            13 => "0d<CR>".to_string(),
+            _ => format!("{byte:02x}"),
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
 pub fn ascii_to_int(bytes: &[u8]) -> isize {
-    let sign = if bytes[0] == 45 { -1 } else { 1 };
+    let sign = if bytes.first() == Some(&45) { -1 } else { 1 };
     let int = bytes
         .iter()
         .filter(|&&x| (48..=57).contains(&x))
@@ -31,6 +52,15 @@ fn test_make_prefix() {
     assert_eq!(prefix, [2, 77, 50]);
 }
 
+#[test]
+fn test_to_hex() {
+    assert_eq!(
+        to_hex(&[2, b'M', b'0', b'G', b'S', 13]),
+        "02 4d 30 47 53 0d"
+    );
+    assert_eq!(to_hex(&[]), "");
+}
+
 #[test]
 fn test_int_to_bytes() {
     let bytes = num_to_bytes(2300);